@@ -0,0 +1,32 @@
+// renders a small scene built in code into memory, then writes it out as a ppm
+extern crate rustrace;
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Write;
+
+use rustrace::CameraSettings;
+use rustrace::RenderSettings;
+use rustrace::Renderer;
+use rustrace::Vector3;
+use rustrace::World;
+use rustrace::snrt::Sphere;
+use rustrace::snrt::material::Lambertian;
+use rustrace::snrt::texture::solid;
+
+fn main() -> std::io::Result<()> {
+    let mut world = World::new();
+    world.add(Box::new(Sphere {pos: Vector3 {x:0.0, y:-1000.0, z:0.0}, radius: 1000.0, material: Box::new(Lambertian {albedo: solid(Vector3 {x:0.5, y:0.5, z:0.5})})}));
+    world.add(Box::new(Sphere {pos: Vector3 {x:0.0, y:1.0, z:0.0}, radius: 1.0, material: Box::new(Lambertian {albedo: solid(Vector3 {x:0.8, y:0.3, z:0.2})})}));
+
+    let camera = CameraSettings {look_from: Vector3 {x:0.0, y:2.0, z:6.0}, look_at: Vector3 {x:0.0, y:1.0, z:0.0}, aperture: 0.0, ..CameraSettings::default()};
+    let settings = RenderSettings {width: 160, height: 120, samples: 32, ..RenderSettings::default()};
+
+    let renderer = Renderer::new(&world, &camera, settings, 1).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let image = renderer.render();
+
+    let mut file = BufWriter::new(File::create("embed.ppm")?);
+    write!(file, "P6\n{} {}\n255\n", image.width, image.height)?;
+    file.write_all(&image.to_rgb8(&settings.display))?;
+    Ok(())
+}
@@ -0,0 +1,340 @@
+use std::path::Path;
+use std::path::PathBuf;
+
+use rustrace::snmath::Vector3;
+
+use rustrace::snrt::film::FilterKind;
+use rustrace::snrt::sampler::SamplerKind;
+use rustrace::snrt::sampler::SAMPLER_NAMES;
+use rustrace::snrt::film::FILTER_NAMES;
+use rustrace::snrt::tiles::TileOrder;
+use rustrace::snrt::tiles::TILE_ORDER_NAMES;
+use rustrace::snrt::tonemap::ToneMapper;
+use rustrace::snrt::tonemap::TONE_MAPPER_NAMES;
+
+pub const USAGE: &str = "usage: rustrace [OPTIONS] [SCENE]
+
+SCENE is a .toml scene description or an .obj mesh. Without one the random
+demo scene is rendered.
+
+options:
+  -W, --width <N>              image width in pixels
+  -H, --height <N>             image height in pixels
+  -s, --samples <N>            samples per pixel
+  -d, --max-depth <N>          maximum number of bounces per path
+  -t, --threads <N>            worker threads (default one per cpu)
+      --tile-size <N>          width and height of the tiles handed to threads (default 32)
+      --tile-order <ORDER>     spiral, hilbert or scanline (default spiral)
+      --pass-samples <N>       samples per pixel in each progressive pass (default 16)
+      --checkpoint <PATH>      save the accumulated samples here between passes and resume
+                               from it when it already exists
+      --checkpoint-interval <SECONDS>
+                               how often the image and checkpoint are saved (default 60)
+      --time-limit <SECONDS>   stop after this long and save what has been rendered so far
+  -o, --output <PATH>          output file (default out<W>x<H>.<ext>)
+  -f, --format <FMT>           png, jpeg, bmp, ppm, or the linear hdr, pfm and exr
+                               (default from the output extension, else png)
+      --adaptive <THRESHOLD>   stop sampling pixels whose relative error is under THRESHOLD and
+                               spend what they leave on the noisy ones, --samples becomes the
+                               average per pixel
+      --min-samples <N>        samples every pixel takes before it can stop (default 16)
+      --max-samples <N>        the most samples a noisy pixel can take (default 4 x --samples)
+      --sample-heatmap <PATH>  also write a png showing how many samples each pixel took
+      --sampler <NAME>         independent, stratified, halton, sobol or blue-noise (default sobol)
+      --filter <NAME>          pixel filter: box, tent, gaussian, mitchell or lanczos (default box)
+      --filter-radius <R>      filter radius in pixels (default depends on the filter)
+      --tonemap <OP>           clamp, reinhard, reinhard-extended, aces or hable (default clamp)
+      --exposure <STOPS>       exposure adjustment before tone mapping
+      --white-point <L>        luminance that becomes white with reinhard-extended
+      --seed <N>               seed for the demo scene and the render's random numbers
+      --environment <PATH>     light the scene with an equirectangular .hdr image
+      --environment-rotation <DEGREES>
+                               turn the environment about the vertical axis
+      --environment-intensity <K>
+                               scale the environment's brightness (default 1)
+      --daylight               light the scene with the default daylight sky instead of its own
+                               background
+      --look-from <X,Y,Z>      camera position
+      --look-at <X,Y,Z>        camera target
+      --up <X,Y,Z>             camera up vector
+      --fov <DEGREES>          vertical field of view
+      --aperture <A>           lens aperture, 0 for a pinhole camera
+      --focus-distance <D>     distance to the plane in focus
+  -h, --help                   print this message";
+
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum OutputFormat {
+    Png,
+    Jpeg,
+    Bmp,
+    Ppm,
+    Hdr,
+    Pfm,
+    Exr,
+}
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> Option<OutputFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "png" => Some(OutputFormat::Png),
+            "jpg" | "jpeg" => Some(OutputFormat::Jpeg),
+            "bmp" => Some(OutputFormat::Bmp),
+            "ppm" => Some(OutputFormat::Ppm),
+            "hdr" => Some(OutputFormat::Hdr),
+            "pfm" => Some(OutputFormat::Pfm),
+            "exr" => Some(OutputFormat::Exr),
+            _ => None,
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Option<OutputFormat> {
+        path.extension().and_then(|e| e.to_str()).and_then(OutputFormat::from_name)
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Bmp => "bmp",
+            OutputFormat::Ppm => "ppm",
+            OutputFormat::Hdr => "hdr",
+            OutputFormat::Pfm => "pfm",
+            OutputFormat::Exr => "exr",
+        }
+    }
+}
+
+#[derive(Clone,PartialEq,Default,Debug)]
+pub struct Options {
+    pub scene: Option<PathBuf>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub samples: Option<u32>,
+    pub max_depth: Option<u32>,
+    pub threads: Option<u32>,
+    pub tile_size: Option<u32>,
+    pub tile_order: Option<TileOrder>,
+    pub pass_samples: Option<u32>,
+    pub checkpoint: Option<PathBuf>,
+    pub checkpoint_interval: Option<f64>,
+    pub time_limit: Option<f64>,
+    pub output: Option<PathBuf>,
+    pub format: Option<OutputFormat>,
+    pub adaptive_threshold: Option<f32>,
+    pub min_samples: Option<u32>,
+    pub max_samples: Option<u32>,
+    pub sample_heatmap: Option<PathBuf>,
+    pub sampler: Option<SamplerKind>,
+    pub filter: Option<FilterKind>,
+    pub filter_radius: Option<f32>,
+    pub tone_mapper: Option<ToneMapper>,
+    pub exposure: Option<f32>,
+    pub white_point: Option<f32>,
+    pub seed: Option<u64>,
+    pub environment: Option<PathBuf>,
+    pub environment_rotation: Option<f32>,
+    pub environment_intensity: Option<f32>,
+    pub daylight: bool,
+    pub look_from: Option<Vector3>,
+    pub look_at: Option<Vector3>,
+    pub up: Option<Vector3>,
+    pub fov: Option<f32>,
+    pub aperture: Option<f32>,
+    pub focus_distance: Option<f32>,
+}
+
+pub enum Command {
+    Render(Box<Options>),
+    Help,
+}
+
+fn parse_count(flag: &str, value: &str) -> Result<u32, String> {
+    match value.parse::<u32>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("{} expects a positive whole number, got '{}'", flag, value)),
+    }
+}
+
+fn parse_float(flag: &str, value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(f) if f.is_finite() => Ok(f),
+        _ => Err(format!("{} expects a number, got '{}'", flag, value)),
+    }
+}
+
+fn parse_vector(flag: &str, value: &str) -> Result<Vector3, String> {
+    let parts: Vec<Option<f32>> = value.split(',').map(|p| p.trim().parse::<f32>().ok().filter(|f| f.is_finite())).collect();
+    match parts.as_slice() {
+        [Some(x), Some(y), Some(z)] => Ok(Vector3 {x:*x, y:*y, z:*z}),
+        _ => Err(format!("{} expects three comma separated numbers like 1,2.5,-3, got '{}'", flag, value)),
+    }
+}
+
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    let mut opts = Options::default();
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with('-') || arg == "-" {
+            if opts.scene.is_some() {
+                return Err(format!("unexpected argument '{}', only one scene can be rendered", arg));
+            }
+            opts.scene = Some(PathBuf::from(arg));
+            continue;
+        }
+
+        // accept both --flag value and --flag=value
+        let (flag, inline) = match arg.find('=') {
+            Some(i) if arg.starts_with("--") => (&arg[..i], Some(arg[i+1..].to_string())),
+            _ => (arg.as_str(), None),
+        };
+
+        if flag == "-h" || flag == "--help" {
+            return Ok(Command::Help);
+        }
+
+        // only taken once the flag is known, so a misspelt flag at the end is reported as unknown
+        let mut inline = inline;
+        let mut value = || match inline.take() {
+            Some(v) => Ok(v),
+            None => iter.next().cloned().ok_or_else(|| format!("{} needs a value", flag)),
+        };
+
+        match flag {
+            "-W" | "--width" => opts.width = Some(parse_count(flag, &value()?)?),
+            "-H" | "--height" => opts.height = Some(parse_count(flag, &value()?)?),
+            "-s" | "--samples" => opts.samples = Some(parse_count(flag, &value()?)?),
+            "-d" | "--max-depth" => opts.max_depth = Some(parse_count(flag, &value()?)?),
+            "-t" | "--threads" => opts.threads = Some(parse_count(flag, &value()?)?),
+            "--tile-size" => opts.tile_size = Some(parse_count(flag, &value()?)?),
+            "--tile-order" => {
+                let value = value()?;
+                match TileOrder::from_name(&value) {
+                    Some(o) => opts.tile_order = Some(o),
+                    None => return Err(format!("unknown tile order '{}', expected {}", value, TILE_ORDER_NAMES)),
+                }
+            },
+            "--pass-samples" => opts.pass_samples = Some(parse_count(flag, &value()?)?),
+            "--checkpoint" => opts.checkpoint = Some(PathBuf::from(value()?)),
+            "--checkpoint-interval" => {
+                let value = value()?;
+                match value.parse::<f64>() {
+                    Ok(s) if s >= 0.0 && s.is_finite() => opts.checkpoint_interval = Some(s),
+                    _ => return Err(format!("--checkpoint-interval expects a number of seconds, got '{}'", value)),
+                }
+            },
+            "--time-limit" => {
+                let value = value()?;
+                match value.parse::<f64>() {
+                    Ok(s) if s > 0.0 && s.is_finite() => opts.time_limit = Some(s),
+                    _ => return Err(format!("--time-limit expects a positive number of seconds, got '{}'", value)),
+                }
+            },
+            "-o" | "--output" => opts.output = Some(PathBuf::from(value()?)),
+            "-f" | "--format" => {
+                let value = value()?;
+                match OutputFormat::from_name(&value) {
+                    Some(f) => opts.format = Some(f),
+                    None => return Err(format!("unknown output format '{}', expected png, jpeg, bmp, ppm, hdr, pfm or exr", value)),
+                }
+            },
+            "--adaptive" => opts.adaptive_threshold = Some(parse_float(flag, &value()?)?),
+            "--min-samples" => opts.min_samples = Some(parse_count(flag, &value()?)?),
+            "--max-samples" => opts.max_samples = Some(parse_count(flag, &value()?)?),
+            "--sample-heatmap" => opts.sample_heatmap = Some(PathBuf::from(value()?)),
+            "--sampler" => {
+                let value = value()?;
+                match SamplerKind::from_name(&value) {
+                    Some(s) => opts.sampler = Some(s),
+                    None => return Err(format!("unknown sampler '{}', expected {}", value, SAMPLER_NAMES)),
+                }
+            },
+            "--filter" => {
+                let value = value()?;
+                match FilterKind::from_name(&value) {
+                    Some(f) => opts.filter = Some(f),
+                    None => return Err(format!("unknown filter '{}', expected {}", value, FILTER_NAMES)),
+                }
+            },
+            "--filter-radius" => opts.filter_radius = Some(parse_float(flag, &value()?)?),
+            "--tonemap" => {
+                let value = value()?;
+                match ToneMapper::from_name(&value) {
+                    Some(t) => opts.tone_mapper = Some(t),
+                    None => return Err(format!("unknown tone mapper '{}', expected {}", value, TONE_MAPPER_NAMES)),
+                }
+            },
+            "--exposure" => opts.exposure = Some(parse_float(flag, &value()?)?),
+            "--white-point" => opts.white_point = Some(parse_float(flag, &value()?)?),
+            "--seed" => {
+                let value = value()?;
+                match value.parse::<u64>() {
+                    Ok(s) => opts.seed = Some(s),
+                    Err(_) => return Err(format!("--seed expects a whole number, got '{}'", value)),
+                }
+            },
+            "--environment" => opts.environment = Some(PathBuf::from(value()?)),
+            "--environment-rotation" => opts.environment_rotation = Some(parse_float(flag, &value()?)?),
+            "--environment-intensity" => opts.environment_intensity = Some(parse_float(flag, &value()?)?),
+            "--daylight" => opts.daylight = true,
+            "--look-from" => opts.look_from = Some(parse_vector(flag, &value()?)?),
+            "--look-at" => opts.look_at = Some(parse_vector(flag, &value()?)?),
+            "--up" => opts.up = Some(parse_vector(flag, &value()?)?),
+            "--fov" => opts.fov = Some(parse_float(flag, &value()?)?),
+            "--aperture" => opts.aperture = Some(parse_float(flag, &value()?)?),
+            "--focus-distance" => opts.focus_distance = Some(parse_float(flag, &value()?)?),
+            _ => return Err(format!("unknown option '{}'", flag)),
+        }
+    }
+
+    if let (Some(output), Some(format)) = (&opts.output, opts.format) {
+        if let Some(ext_format) = OutputFormat::from_path(output) {
+            if ext_format != format {
+                return Err(format!("--format {} doesn't match the extension of {}", format.extension(), output.display()));
+            }
+        }
+    }
+
+    Ok(Command::Render(Box::new(opts)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Command;
+    use super::parse;
+
+    fn run(args: &[&str]) -> Result<Command, String> {
+        parse(&args.iter().map(|a| a.to_string()).collect::<Vec<String>>())
+    }
+
+    #[test]
+    fn unknown_flags_are_reported_before_missing_values() {
+        assert_eq!(run(&["--bogus"]).err().unwrap(), "unknown option '--bogus'");
+        assert_eq!(run(&["--bogus", "3"]).err().unwrap(), "unknown option '--bogus'");
+        assert_eq!(run(&["--samples"]).err().unwrap(), "--samples needs a value");
+    }
+
+    #[test]
+    fn takes_values_inline_or_from_the_next_argument() {
+        match run(&["scene.toml", "--samples=12", "-W", "64"]) {
+            Ok(Command::Render(opts)) => {
+                assert_eq!(opts.samples, Some(12));
+                assert_eq!(opts.width, Some(64));
+                assert_eq!(opts.scene.unwrap().to_str(), Some("scene.toml"));
+            },
+            _ => panic!("expected a render command"),
+        }
+    }
+
+    #[test]
+    fn daylight_is_off_unless_asked_for() {
+        match (run(&["model.obj"]), run(&["model.obj", "--daylight", "-s", "4"])) {
+            (Ok(Command::Render(plain)), Ok(Command::Render(lit))) => {
+                assert!(!plain.daylight);
+                assert!(lit.daylight);
+                assert_eq!(lit.samples, Some(4));
+            },
+            _ => panic!("expected render commands"),
+        }
+    }
+}
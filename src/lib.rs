@@ -0,0 +1,24 @@
+// the renderer as a library: build a World (by hand, from a scene file or an obj), describe
+// the camera with CameraSettings and hand both to a Renderer to get a Framebuffer back
+
+extern crate image;
+
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+
+extern crate rand;
+
+extern crate scoped_threadpool;
+
+pub mod snmath;
+pub mod snrt;
+
+pub use snmath::Vector3;
+pub use snrt::Camera;
+pub use snrt::CameraSettings;
+pub use snrt::framebuffer::Framebuffer;
+pub use snrt::render::Renderer;
+pub use snrt::scene::RenderSettings;
+pub use snrt::scene::Scene;
+pub use snrt::world::World;
@@ -1,135 +1,315 @@
 use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
 
 extern crate image;
 pub use image::png::PNGEncoder;
-pub use image::ImageFormat::PNG;
+pub use image::jpeg::JPEGEncoder;
+pub use image::bmp::BMPEncoder;
+pub use image::ppm::PPMEncoder;
+pub use image::hdr::HDREncoder;
 
 extern crate time;
 
 extern crate rand;
 use rand::{thread_rng, Rng};
 
-extern crate scoped_threadpool;
-use scoped_threadpool::Pool;
-
-mod snmath;
-mod snrt;
-
-use snmath::Vector3;
-use snmath::Ray;
-
-use snrt::Camera;
-use snrt::world::World;
-
-fn color(r: Ray, world: &World, bounce: i32) -> Vector3 {
-    if bounce > 50 {
-        return Vector3::default();
+extern crate rustrace;
+
+mod cli;
+mod progress;
+
+use rustrace::CameraSettings;
+use rustrace::Framebuffer;
+use rustrace::RenderSettings;
+use rustrace::Renderer;
+use rustrace::Scene;
+use rustrace::World;
+use rustrace::snmath::seeded_rng;
+use rustrace::snrt::adaptive::AdaptiveSettings;
+use rustrace::snrt::adaptive::heatmap_rgb8;
+use rustrace::snrt::checkpoint::Checkpoint;
+use rustrace::snrt::environment::ImageEnvironment;
+use rustrace::snrt::film::Filter;
+use rustrace::snrt::sky::PreethamSky;
+use rustrace::snrt::render::CancelToken;
+use rustrace::snrt::tonemap::DisplayTransform;
+
+use cli::Command;
+use cli::OutputFormat;
+
+use progress::Progress;
+
+fn write_image(path: &Path, format: OutputFormat, framebuffer: &Framebuffer, display: &DisplayTransform) -> std::io::Result<()> {
+    let (width, height) = (framebuffer.width, framebuffer.height);
+    let mut file = BufWriter::new(File::create(path)?);
+    match format {
+        OutputFormat::Png => PNGEncoder::new(file).encode(&framebuffer.to_rgb8(display),width,height,image::ColorType::RGB(8))?,
+        OutputFormat::Jpeg => JPEGEncoder::new(&mut file).encode(&framebuffer.to_rgb8(display),width,height,image::ColorType::RGB(8))?,
+        OutputFormat::Bmp => BMPEncoder::new(&mut file).encode(&framebuffer.to_rgb8(display),width,height,image::ColorType::RGB(8))?,
+        OutputFormat::Ppm => PPMEncoder::new(&mut file).encode(&framebuffer.to_rgb8(display),width,height,image::ColorType::RGB(8))?,
+        OutputFormat::Hdr => {
+            let pixels: Vec<image::Rgb<f32>> = framebuffer.pixels.iter().map(|p| image::Rgb {data: [p.x, p.y, p.z]}).collect();
+            HDREncoder::new(file).encode(&pixels, width as usize, height as usize)?
+        },
+        OutputFormat::Pfm => framebuffer.write_pfm(&mut file)?,
+        OutputFormat::Exr => framebuffer.write_exr(&mut file)?,
     }
+    Ok(())
+}
 
-    let max_t = 100000.0;
-    let min_t = 0.001;
-
-    let best = world.trace(r, min_t, max_t);
+// scene textures are kept in a map, so the order is fixed here to keep the fingerprint stable
+fn asset_stamps(assets: &[PathBuf]) -> String {
+    let mut assets = assets.to_vec();
+    assets.sort();
+    assets.dedup();
+    let stamps: Vec<_> = assets.iter().map(|path| {
+        let metadata = std::fs::metadata(path).ok();
+        (path, metadata.as_ref().map(|m| m.len()), metadata.and_then(|m| m.modified().ok()))
+    }).collect();
+    format!("{:?}", stamps)
+}
 
-    match best.1 {
-        Some(b) => {
-            let scat = b.hit_process(&r,best.0);
-            if scat.0 {
-                return scat.2*color(scat.1, world, bounce+1);
-            }
-            return Vector3::default();
-        },
-        None => {
-            //fake sky
-            let dir_norm = r.direction.normalize();
-            let t = 0.5*(dir_norm.y + 1.0);
-            return Vector3::lerp(&Vector3{x:1.0,y:1.0,z:1.0}, &Vector3{x:0.5,y:0.7,z:1.0}, t);
-        }
-    }
+fn exit_with_error(message: &str) -> ! {
+    eprintln!("rustrace: {}", message);
+    std::process::exit(1);
 }
 
 fn main() -> std::io::Result<()> {
     let args: Vec<String> = std::env::args().collect();
 
-    if args.len() < 3 {
-        println!("run with two digits for width and height");
-        return Ok(());
-    }
-
+    let opts = match cli::parse(&args[1..]) {
+        Ok(Command::Render(opts)) => *opts,
+        Ok(Command::Help) => {
+            println!("{}", cli::USAGE);
+            return Ok(());
+        },
+        Err(e) => {
+            eprintln!("rustrace: {} (see --help)", e);
+            std::process::exit(2);
+        },
+    };
 
     let start_time = time::precise_time_s();
 
-    let width = match args[1].parse::<u32>() {
-        Ok(n) => n,
-        Err(_e) => 256,
+    // the same seed reproduces the demo scene and every sample of the render
+    // and a resumed render has to carry on with the seed it started with
+    let saved_seed = match opts.checkpoint {
+        Some(ref path) if path.exists() => Some(Checkpoint::saved_seed(path)
+            .unwrap_or_else(|e| exit_with_error(&format!("can't resume from {}: {}", path.display(), e)))),
+        _ => None,
     };
+    let seed = opts.seed.or(saved_seed).unwrap_or_else(|| thread_rng().gen());
 
-    let height = match args[2].parse::<u32>() {
-        Ok(n) => n,
-        Err(_e) => 256,
+    let is_obj = |p: &Path| p.extension().and_then(|e| e.to_str()).is_some_and(|e| e.eq_ignore_ascii_case("obj"));
+    let (mut world, mut cam_settings, mut settings) = match opts.scene {
+        Some(ref path) if is_obj(path) => {
+            let world = World::from_obj(path).unwrap_or_else(|e| exit_with_error(&e.to_string()));
+            (world, CameraSettings::default(), RenderSettings::default())
+        },
+        Some(ref path) => {
+            let scene = Scene::load(path).unwrap_or_else(|e| exit_with_error(&e.to_string()));
+            (scene.world, scene.camera, scene.settings)
+        },
+        None => (World::create(&mut seeded_rng(seed)), CameraSettings::default(), RenderSettings::default()),
     };
 
-    let f_width = width as f32;
-    let f_height = height as f32;
-
-    let n_samples = 150;
-
-    println!("width {}, height {}", width, height);
-
-
-    let look_from = Vector3 {x:7.0,y:2.0,z:2.0};
-    let look_at = Vector3 {x:0.0,y:0.0,z:0.0};
-    let focal_dist = (look_from-look_at).length();
-    let cam = Camera::create_camera(look_from, look_at, Vector3 {x:0.0,y:1.0,z:0.0}, 40.0, f_width/f_height, 0.3, focal_dist);
-
-    let world = World::create();
+    if opts.environment_intensity.is_some_and(|k| k < 0.0) {
+        exit_with_error("--environment-intensity must not be negative");
+    }
 
-    let buffer_stride = 3;
-    let mut buffer_rgb: Vec<u8> = Vec::new();
+    match opts.environment {
+        Some(ref path) => {
+            let env = ImageEnvironment::load(path, opts.environment_rotation.unwrap_or(0.0), opts.environment_intensity.unwrap_or(1.0))
+                .unwrap_or_else(|e| exit_with_error(&format!("could not load {}: {}", path.display(), e)));
+            world.environment = Box::new(env);
+            world.assets.push(path.clone());
+        },
+        None if opts.environment_rotation.is_some() || opts.environment_intensity.is_some() =>
+            exit_with_error("--environment-rotation and --environment-intensity only apply with --environment"),
+        None => {},
+    }
+    if opts.daylight {
+        if opts.environment.is_some() {
+            exit_with_error("--daylight and --environment can't be used together");
+        }
+        world.environment = Box::new(PreethamSky::default());
+    }
+    settings.width = opts.width.unwrap_or(settings.width);
+    settings.height = opts.height.unwrap_or(settings.height);
+    settings.samples = opts.samples.unwrap_or(settings.samples);
+    settings.max_bounces = opts.max_depth.unwrap_or(settings.max_bounces);
+    if let Some(kind) = opts.filter {
+        settings.filter = Filter {kind, radius: kind.default_radius()};
+    }
+    settings.filter.radius = opts.filter_radius.unwrap_or(settings.filter.radius);
+    if let Some(threshold) = opts.adaptive_threshold {
+        settings.adaptive = Some(AdaptiveSettings {threshold, ..settings.adaptive.unwrap_or_default()});
+    }
+    if let Some(min_samples) = opts.min_samples {
+        match settings.adaptive {
+            Some(ref mut adaptive) => adaptive.min_samples = min_samples,
+            None => exit_with_error("--min-samples only applies with adaptive sampling, see --adaptive"),
+        }
+    }
+    if let Some(max_samples) = opts.max_samples {
+        match settings.adaptive {
+            Some(ref mut adaptive) => adaptive.max_samples = Some(max_samples),
+            None => exit_with_error("--max-samples only applies with adaptive sampling, see --adaptive"),
+        }
+    }
+    settings.sampler = opts.sampler.unwrap_or(settings.sampler);
+    settings.tile_size = opts.tile_size.unwrap_or(settings.tile_size);
+    settings.tile_order = opts.tile_order.unwrap_or(settings.tile_order);
+    settings.pass_samples = opts.pass_samples.unwrap_or(settings.pass_samples);
+    settings.display.tone_mapper = opts.tone_mapper.unwrap_or(settings.display.tone_mapper);
+    settings.display.exposure = opts.exposure.unwrap_or(settings.display.exposure);
+    settings.display.white_point = opts.white_point.unwrap_or(settings.display.white_point);
+
+    cam_settings.look_from = opts.look_from.unwrap_or(cam_settings.look_from);
+    cam_settings.look_at = opts.look_at.unwrap_or(cam_settings.look_at);
+    cam_settings.up = opts.up.unwrap_or(cam_settings.up);
+    cam_settings.v_fov = opts.fov.unwrap_or(cam_settings.v_fov);
+    cam_settings.aperture = opts.aperture.unwrap_or(cam_settings.aperture);
+    if opts.focus_distance.is_some() {
+        cam_settings.focal_dist = opts.focus_distance;
+    }
 
-    let mut thread_jobs = Vec::new();
-    for y in 0..height {
-        let mut line: Vec<u8> = vec![0;(width*buffer_stride) as usize];
-        thread_jobs.push((y,line,&world)); // should world be an arc? is this copying all of world or just the pointer?
+    let mut renderer = Renderer::new(&world, &cam_settings, settings, seed).unwrap_or_else(|e| exit_with_error(&e));
+    if let Some(threads) = opts.threads {
+        renderer.set_threads(threads);
     }
 
-    let mut pool = Pool::new(16);
+    let (width, height) = (settings.width, settings.height);
+    let format = opts.format.or_else(|| opts.output.as_ref().and_then(|o| OutputFormat::from_path(o))).unwrap_or(OutputFormat::Png);
+    let output = opts.output.clone().unwrap_or_else(|| PathBuf::from(format!("out{}x{}.{}", width, height, format.extension())));
+
+    println!("width {}, height {}, seed {}", width, height, seed);
+
+    // the scene file stands in for the world when checking a checkpoint belongs to this render,
+    // with the size and modification time of every mesh and image it pulled in
+    let mut scene_bytes = opts.scene.as_ref().and_then(|p| std::fs::read(p).ok()).unwrap_or_default();
+    scene_bytes.extend(format!("{:?}", (&opts.environment, opts.environment_rotation, opts.environment_intensity, opts.daylight)).bytes());
+    scene_bytes.extend(asset_stamps(&world.assets).bytes());
+    let fingerprint = renderer.fingerprint(&scene_bytes);
+
+    let mut state = match opts.checkpoint {
+        Some(ref path) if path.exists() => {
+            let state = Checkpoint::load(path, fingerprint, width, height, settings.filter)
+                .unwrap_or_else(|e| exit_with_error(&format!("can't resume from {}: {}", path.display(), e)));
+            let taken: u64 = state.sample_counts.iter().map(|&c| u64::from(c)).sum();
+            println!("resuming from {} with {:.1} samples per pixel on average", path.display(), taken as f64 / state.sample_counts.len() as f64);
+            state
+        },
+        _ => renderer.start(fingerprint),
+    };
 
-    pool.scoped( |scope| {
-        for e in &mut thread_jobs {
-            scope.execute(move || {
-                let mut rng = thread_rng();
-                let y = e.0;
-                for x in 0..width {
-                    let mut c = Vector3{x:0.0,y:0.0,z:0.0};
-                    for _sample in 0..n_samples {
-                        let u = (x as f32 + rng.gen_range::<f32>(0.0,1.0)) / f_width;
-                        let v = (y as f32 + rng.gen_range::<f32>(0.0,1.0)) / f_height;
-                        let r = cam.get_ray(u,v);
-                        c = c + color(r, e.2, 0);
-                    }
+    let checkpoint_interval = opts.checkpoint_interval.unwrap_or(60.0);
+    let cancel = match opts.time_limit {
+        Some(seconds) => CancelToken::with_budget(Duration::from_secs_f64(seconds)),
+        None => CancelToken::new(),
+    };
+    let mut progress = Progress::new(u64::from(width) * u64::from(height) * u64::from(renderer.passes_left(&state)));
+    let mut last_save = time::precise_time_s();
+
+    while renderer.passes_left(&state) > 0 {
+        if !renderer.render_pass(&mut state, &cancel, &mut |pixels, rays| progress.tile_done(pixels, rays)) {
+            progress.stopped();
+            println!("stopped at the time limit");
+            break;
+        }
 
-                    let c = (c / n_samples as f32).powf(1.0/2.2) * 255.99;
-                    let pixel_idx = (x*buffer_stride) as usize;
-                    e.1[pixel_idx+0] = c.x as u8;
-                    e.1[pixel_idx+1] = c.y as u8;
-                    e.1[pixel_idx+2] = c.z as u8;
-                }
-            });
+        // the finished image is written below, so only save here when there is more to come
+        if renderer.passes_left(&state) > 0 && time::precise_time_s() - last_save >= checkpoint_interval {
+            write_image(&output, format, &state.film.resolve(), &settings.display)?;
+            if let Some(ref path) = opts.checkpoint {
+                state.save(path)?;
+            }
+            last_save = time::precise_time_s();
         }
-    });
+    }
+    progress.stopped();
 
     let trace_time = time::precise_time_s();
 
-    for mut line in thread_jobs.drain(..).rev() {
-        buffer_rgb.append(&mut line.1);
+    // saved at the end too so a later run can add more samples
+    if let Some(ref path) = opts.checkpoint {
+        state.save(path)?;
     }
 
-    let file_png = File::create(format!("out{}x{}.png", width, height))?;
-    let encoder = PNGEncoder::new(file_png);
-    encoder.encode(&buffer_rgb,width,height,image::ColorType::RGB(8))?;
+    let sample_counts = &state.sample_counts;
+    if settings.adaptive.is_some() || renderer.passes_left(&state) > 0 {
+        let total: u64 = sample_counts.iter().map(|&c| u64::from(c)).sum();
+        println!("{:.1} samples per pixel on average", total as f64 / sample_counts.len() as f64);
+    }
+    if let Some(ref path) = opts.sample_heatmap {
+        let file = BufWriter::new(File::create(path)?);
+        PNGEncoder::new(file).encode(&heatmap_rgb8(sample_counts, settings.max_pixel_samples()),width,height,image::ColorType::RGB(8))?;
+    }
+
+    write_image(&output, format, &state.film.resolve(), &settings.display)?;
 
+    println!("{} rays traced, {:.0} per second", progress.rays(), progress.rays() as f64 / progress.elapsed().max(1e-6));
     println!("Execution time: {} (tracing took {})", time::precise_time_s()-start_time, trace_time-start_time);
 
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::File;
+    use std::io::BufReader;
+    use std::process;
+
+    use image::hdr::HDRDecoder;
+
+    use rustrace::Framebuffer;
+    use rustrace::snmath::Vector3;
+    use rustrace::snrt::tonemap::DisplayTransform;
+
+    use cli::OutputFormat;
+
+    use super::asset_stamps;
+    use super::write_image;
+
+    #[test]
+    fn hdr_output_keeps_radiance_above_one_and_the_row_order() {
+        let mut framebuffer = Framebuffer::new(2, 2);
+        framebuffer.pixels[0] = Vector3 {x:4.0, y:0.5, z:0.25};
+        framebuffer.pixels[3] = Vector3 {x:0.125, y:2.0, z:16.0};
+        let path = env::temp_dir().join(format!("rustrace-main-{}-image.hdr", process::id()));
+        write_image(&path, OutputFormat::Hdr, &framebuffer, &DisplayTransform::default()).unwrap();
+
+        let decoder = HDRDecoder::new(BufReader::new(File::open(&path).unwrap())).unwrap();
+        let meta = decoder.metadata();
+        assert_eq!((meta.width, meta.height), (2, 2));
+        let pixels = decoder.read_image_hdr().unwrap();
+        for (read, written) in pixels.iter().zip(&framebuffer.pixels) {
+            for (c, expected) in read.data.iter().zip(&[written.x, written.y, written.z]) {
+                // rgbe shares one exponent across the channels
+                assert!((c - expected).abs() <= 0.01 * written.x.max(written.y).max(written.z), "{:?} {:?}", read.data, written);
+            }
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn asset_stamps_change_with_the_files_but_not_their_order() {
+        let dir = env::temp_dir();
+        let a = dir.join(format!("rustrace-main-{}-a.obj", process::id()));
+        let b = dir.join(format!("rustrace-main-{}-b.png", process::id()));
+        std::fs::write(&a, "v 0 0 0\n").unwrap();
+        std::fs::write(&b, "png").unwrap();
+
+        let before = asset_stamps(&[a.clone(), b.clone()]);
+        assert_eq!(asset_stamps(&[b.clone(), a.clone(), b.clone()]), before);
+        std::fs::write(&a, "v 0 0 0\nv 1 0 0\n").unwrap();
+        assert_ne!(asset_stamps(&[a.clone(), b.clone()]), before);
+        // a file that has gone missing counts as changed too
+        std::fs::remove_file(&b).unwrap();
+        assert_ne!(asset_stamps(&[a.clone(), b.clone()]), before);
+        let _ = std::fs::remove_file(&a);
+    }
+}
@@ -0,0 +1,103 @@
+use std::io::IsTerminal;
+use std::io::Write;
+use std::io;
+
+extern crate time;
+
+// reports how far a render has got on stderr, redrawing one line on a terminal and
+// printing every tenth of the way otherwise so logs stay readable
+pub struct Progress {
+    total_pixels: u64,
+    done_pixels: u64,
+    rays: u64,
+    start: f64,
+    last_report: f64,
+    last_tenth: u64,
+    interactive: bool,
+}
+
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.max(0.0).round() as u64;
+    if seconds >= 3600 {
+        format!("{}h{:02}m{:02}s", seconds / 3600, (seconds / 60) % 60, seconds % 60)
+    } else if seconds >= 60 {
+        format!("{}m{:02}s", seconds / 60, seconds % 60)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+fn format_rate(per_second: f64) -> String {
+    if per_second >= 1e6 {
+        format!("{:.2}M", per_second / 1e6)
+    } else if per_second >= 1e3 {
+        format!("{:.1}k", per_second / 1e3)
+    } else {
+        format!("{:.0}", per_second)
+    }
+}
+
+impl Progress {
+    pub fn new(total_pixels: u64) -> Progress {
+        let now = time::precise_time_s();
+        Progress {
+            total_pixels: total_pixels.max(1),
+            done_pixels: 0,
+            rays: 0,
+            start: now,
+            last_report: now,
+            last_tenth: 0,
+            interactive: io::stderr().is_terminal(),
+        }
+    }
+
+    pub fn rays(&self) -> u64 {
+        self.rays
+    }
+
+    pub fn elapsed(&self) -> f64 {
+        time::precise_time_s() - self.start
+    }
+
+    // ends the redrawn line when the render stops before reaching 100%, which adaptive renders
+    // usually do since the total counts every pass they could take
+    pub fn stopped(&mut self) {
+        if self.interactive && self.done_pixels < self.total_pixels {
+            eprintln!();
+        }
+        self.done_pixels = self.total_pixels;
+    }
+
+    pub fn tile_done(&mut self, pixels: u64, rays: u64) {
+        self.done_pixels += pixels;
+        self.rays += rays;
+
+        let now = time::precise_time_s();
+        let finished = self.done_pixels >= self.total_pixels;
+        let tenth = self.done_pixels * 10 / self.total_pixels;
+        let due = if self.interactive { now - self.last_report >= 0.2 } else { tenth > self.last_tenth };
+        if !due && !finished {
+            return;
+        }
+        self.last_report = now;
+        self.last_tenth = tenth;
+
+        let fraction = self.done_pixels as f64 / self.total_pixels as f64;
+        let elapsed = now - self.start;
+        let eta = if fraction > 0.0 { elapsed / fraction - elapsed } else { 0.0 };
+        let line = format!("{:5.1}% elapsed {} eta {} {} rays/s",
+            fraction * 100.0, format_duration(elapsed), format_duration(eta), format_rate(self.rays as f64 / elapsed.max(1e-6)));
+
+        let stderr = io::stderr();
+        let mut err = stderr.lock();
+        if self.interactive {
+            let _ = write!(err, "\r{:<60}", line);
+            if finished {
+                let _ = writeln!(err);
+            }
+        } else {
+            let _ = writeln!(err, "{}", line);
+        }
+        let _ = err.flush();
+    }
+}
@@ -2,9 +2,28 @@ use std::ops::Add;
 use std::ops::Sub;
 use std::ops::Mul;
 use std::ops::Div;
+use std::f32;
 
 extern crate rand;
-use rand::{thread_rng, Rng};
+use rand::{SeedableRng, XorShiftRng};
+
+// every random decision while rendering draws from one of these, seeded per pixel sample
+pub type SampleRng = XorShiftRng;
+
+// splitmix64 finalizer, folds value into seed so neighbouring pixels get unrelated streams
+pub fn mix_seed(seed: u64, value: u64) -> u64 {
+    let mut z = seed ^ value.wrapping_add(0x9e37_79b9_7f4a_7c15).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+pub fn seeded_rng(seed: u64) -> SampleRng {
+    let a = mix_seed(seed, 1);
+    let b = mix_seed(seed, 2);
+    // xorshift must not start from an all zero state
+    XorShiftRng::from_seed([a as u32, (a >> 32) as u32, b as u32, (b >> 32) as u32 | 1])
+}
 
 #[derive(Clone,Copy,PartialEq,Default,Debug)]
 pub struct Vector3 { 
@@ -37,7 +56,7 @@ impl<'a> Sub<&'a Vector3> for &'a Vector3 {
     }
 }
 
-impl<'a> Sub<&'a Vector3> for Vector3 {
+impl Sub<&Vector3> for Vector3 {
     type Output = Vector3;
 
     fn sub(self, other: &Vector3) -> Vector3 {
@@ -49,7 +68,7 @@ impl<'a> Sub<&'a Vector3> for Vector3 {
     }
 }
 
-impl<'a> Sub<Vector3> for &'a Vector3 {
+impl Sub<Vector3> for &Vector3 {
     type Output = Vector3;
 
     fn sub(self, other: Vector3) -> Vector3 {
@@ -73,7 +92,7 @@ impl Sub for Vector3 {
     }
 }
 
-impl<'a> Mul<f32> for &'a Vector3 {
+impl Mul<f32> for &Vector3 {
     type Output = Vector3;
 
     fn mul(self, other: f32) -> Vector3 {
@@ -110,7 +129,7 @@ impl Mul<Vector3> for f32 {
     }
 }
 
-impl<'a> Mul<&'a Vector3> for f32 {
+impl Mul<&Vector3> for f32 {
     type Output = Vector3;
 
     fn mul(self, other: &Vector3) -> Vector3 {
@@ -135,7 +154,7 @@ impl Div<f32> for Vector3 {
     }
 }
 
-impl<'a> Div<f32> for &'a Vector3 {
+impl Div<f32> for &Vector3 {
     type Output = Vector3;
 
     fn div(self, other: f32) -> Vector3 {
@@ -194,6 +213,9 @@ impl Vector3 {
     pub fn length(&self) -> f32 {
         self.length_sq().sqrt()
     }
+    pub fn is_finite(&self) -> bool {
+        self.x.is_finite() && self.y.is_finite() && self.z.is_finite()
+    }
     pub fn length_sq(&self) -> f32 {
         self.x*self.x + self.y*self.y + self.z*self.z
     }
@@ -201,28 +223,61 @@ impl Vector3 {
         self / self.length()
     }
 
+    pub fn get(&self, axis: usize) -> f32 {
+        match axis {
+            0 => self.x,
+            1 => self.y,
+            _ => self.z,
+        }
+    }
+    pub fn component_min(&self, other: &Vector3) -> Vector3 {
+        Vector3 {
+            x: self.x.min(other.x),
+            y: self.y.min(other.y),
+            z: self.z.min(other.z),
+        }
+    }
+    pub fn component_max(&self, other: &Vector3) -> Vector3 {
+        Vector3 {
+            x: self.x.max(other.x),
+            y: self.y.max(other.y),
+            z: self.z.max(other.z),
+        }
+    }
+
     pub fn lerp(a: &Vector3, b: &Vector3, t: f32) -> Vector3 {
         a*(1.0-t)+b*t
     }
 
-    pub fn generate_random_unit_vector() -> Vector3 {
-        let mut rng = thread_rng();
-        let mut p = Vector3 {x:100.0,y:0.0,z:0.0};
-        let ones = Vector3 {x:1.0, y:1.0, z:1.0};
-        while p.length_sq() > 1.0 {
-            p = Vector3{x:rng.gen_range::<f32>(0.0,1.0),y:rng.gen_range::<f32>(0.0,1.0),z:rng.gen_range::<f32>(0.0,1.0)}*2.0 - ones;
+    // uniform on the unit disc in the xy plane, shirley's concentric mapping from the unit square
+    pub fn sample_unit_disc(u: (f32, f32)) -> Vector3 {
+        let (a, b) = (2.0*u.0 - 1.0, 2.0*u.1 - 1.0);
+        if a == 0.0 && b == 0.0 {
+            return Vector3::default();
         }
-        return p;
+        let (r, theta) = if a.abs() > b.abs() {
+            (a, f32::consts::FRAC_PI_4 * (b / a))
+        } else {
+            (b, f32::consts::FRAC_PI_2 - f32::consts::FRAC_PI_4 * (a / b))
+        };
+        Vector3 {x:r*theta.cos(), y:r*theta.sin(), z:0.0}
     }
 
-    pub fn generate_random_unit_disc() -> Vector3 {
-        let mut rng = thread_rng();
-        let mut p = Vector3 {x:100.0,y:0.0,z:0.0};
-        let ones = Vector3 {x:1.0, y:1.0, z:0.0};
-        while p.length_sq() > 1.0 {
-            p = Vector3{x:rng.gen_range::<f32>(0.0,1.0),y:rng.gen_range::<f32>(0.0,1.0),z:0.0} - ones;
-        }
-        return p;
+    // uniform on the surface of the unit sphere
+    pub fn sample_unit_sphere(u: (f32, f32)) -> Vector3 {
+        let z = 1.0 - 2.0*u.0;
+        let r = (1.0 - z*z).max(0.0).sqrt();
+        let phi = 2.0 * f32::consts::PI * u.1;
+        Vector3 {x:r*phi.cos(), y:r*phi.sin(), z}
+    }
+
+    // two unit vectors completing a right handed frame around this one, which must be normalized
+    pub fn orthonormal_basis(&self) -> (Vector3, Vector3) {
+        let sign = 1.0f32.copysign(self.z);
+        let a = -1.0 / (sign + self.z);
+        let b = self.x * self.y * a;
+        (Vector3 {x:1.0 + sign*self.x*self.x*a, y:sign*b, z:-sign*self.x},
+         Vector3 {x:b, y:sign + self.y*self.y*a, z:-self.y})
     }
 
     pub fn reflect_on(&self, normal: &Vector3) -> Vector3 {
@@ -236,7 +291,7 @@ impl Vector3 {
         if discriminant > 0.0 {
             return Some(ni_over_nt*(uv - normal*dt) - normal*discriminant.sqrt());
         }
-        return None;
+        None
     }
 }
 
@@ -251,3 +306,357 @@ impl Ray {
         self.origin + self.direction * t
     }
 }
+
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub struct Aabb {
+    pub min: Vector3,
+    pub max: Vector3,
+}
+
+impl Aabb {
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Vector3 {x:f32::MAX, y:f32::MAX, z:f32::MAX},
+            max: Vector3 {x:-f32::MAX, y:-f32::MAX, z:-f32::MAX},
+        }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: self.min.component_min(&other.min),
+            max: self.max.component_max(&other.max),
+        }
+    }
+
+    pub fn grow(&self, p: &Vector3) -> Aabb {
+        Aabb {
+            min: self.min.component_min(p),
+            max: self.max.component_max(p),
+        }
+    }
+
+    pub fn centroid(&self) -> Vector3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn extent(&self) -> Vector3 {
+        self.max - self.min
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let d = self.extent();
+        if d.x < 0.0 || d.y < 0.0 || d.z < 0.0 {
+            return 0.0;
+        }
+        2.0 * (d.x*d.y + d.y*d.z + d.z*d.x)
+    }
+
+    // slab test, inv_dir is 1/direction so a traversal only divides once per ray
+    pub fn hit(&self, r: &Ray, inv_dir: &Vector3, t_min: f32, t_max: f32) -> bool {
+        let t0 = (self.min - r.origin) * *inv_dir;
+        let t1 = (self.max - r.origin) * *inv_dir;
+        let near = t0.component_min(&t1);
+        let far = t0.component_max(&t1);
+
+        let t_near = near.x.max(near.y).max(near.z).max(t_min);
+        let t_far = far.x.min(far.y).min(far.z).min(t_max);
+        t_near <= t_far
+    }
+}
+
+// row major, transforms column vectors so a * b applies b first
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub struct Matrix4 {
+    pub m: [[f32; 4]; 4],
+}
+
+impl Mul for Matrix4 {
+    type Output = Matrix4;
+
+    fn mul(self, other: Matrix4) -> Matrix4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Matrix4 {m}
+    }
+}
+
+impl Default for Matrix4 {
+    fn default() -> Matrix4 {
+        Matrix4::identity()
+    }
+}
+
+impl Matrix4 {
+    pub fn identity() -> Matrix4 {
+        Matrix4 {m: [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]]}
+    }
+
+    pub fn translation(offset: Vector3) -> Matrix4 {
+        let mut t = Matrix4::identity();
+        t.m[0][3] = offset.x;
+        t.m[1][3] = offset.y;
+        t.m[2][3] = offset.z;
+        t
+    }
+
+    pub fn scaling(factors: Vector3) -> Matrix4 {
+        let mut s = Matrix4::identity();
+        s.m[0][0] = factors.x;
+        s.m[1][1] = factors.y;
+        s.m[2][2] = factors.z;
+        s
+    }
+
+    // counter clockwise looking down axis towards the origin
+    pub fn rotation(axis: Vector3, degrees: f32) -> Matrix4 {
+        let a = axis.normalize();
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let c = 1.0 - cos;
+        Matrix4 {m: [
+            [a.x*a.x*c + cos, a.x*a.y*c - a.z*sin, a.x*a.z*c + a.y*sin, 0.0],
+            [a.y*a.x*c + a.z*sin, a.y*a.y*c + cos, a.y*a.z*c - a.x*sin, 0.0],
+            [a.z*a.x*c - a.y*sin, a.z*a.y*c + a.x*sin, a.z*a.z*c + cos, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]}
+    }
+
+    pub fn transpose(&self) -> Matrix4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = self.m[j][i];
+            }
+        }
+        Matrix4 {m}
+    }
+
+    // gauss-jordan with partial pivoting, None when the matrix is singular
+    pub fn inverse(&self) -> Option<Matrix4> {
+        let mut a = self.m;
+        let mut inv = Matrix4::identity().m;
+        for col in 0..4 {
+            let pivot = (col..4).max_by(|&i, &j| a[i][col].abs().partial_cmp(&a[j][col].abs()).unwrap())?;
+            if a[pivot][col].abs() < 1e-12 || !a[pivot][col].is_finite() {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let scale = 1.0 / a[col][col];
+            for j in 0..4 {
+                a[col][j] *= scale;
+                inv[col][j] *= scale;
+            }
+            for i in 0..4 {
+                if i != col {
+                    let f = a[i][col];
+                    for j in 0..4 {
+                        a[i][j] -= f * a[col][j];
+                        inv[i][j] -= f * inv[col][j];
+                    }
+                }
+            }
+        }
+        Some(Matrix4 {m: inv})
+    }
+
+    pub fn transform_point(&self, p: &Vector3) -> Vector3 {
+        let m = &self.m;
+        Vector3 {
+            x: m[0][0]*p.x + m[0][1]*p.y + m[0][2]*p.z + m[0][3],
+            y: m[1][0]*p.x + m[1][1]*p.y + m[1][2]*p.z + m[1][3],
+            z: m[2][0]*p.x + m[2][1]*p.y + m[2][2]*p.z + m[2][3],
+        }
+    }
+
+    // ignores the translation
+    pub fn transform_vector(&self, v: &Vector3) -> Vector3 {
+        let m = &self.m;
+        Vector3 {
+            x: m[0][0]*v.x + m[0][1]*v.y + m[0][2]*v.z,
+            y: m[1][0]*v.x + m[1][1]*v.y + m[1][2]*v.z,
+            z: m[2][0]*v.x + m[2][1]*v.y + m[2][2]*v.z,
+        }
+    }
+}
+
+// an affine object to world transform kept together with its inverse
+#[derive(Clone,Copy,PartialEq,Default,Debug)]
+pub struct Transform {
+    pub matrix: Matrix4,
+    pub inverse: Matrix4,
+}
+
+impl Transform {
+    pub fn translate(offset: Vector3) -> Transform {
+        Transform {matrix: Matrix4::translation(offset), inverse: Matrix4::translation(offset * -1.0)}
+    }
+
+    // factors must all be non zero
+    pub fn scale(factors: Vector3) -> Transform {
+        Transform {matrix: Matrix4::scaling(factors), inverse: Matrix4::scaling(Vector3 {x:1.0/factors.x, y:1.0/factors.y, z:1.0/factors.z})}
+    }
+
+    pub fn rotate(axis: Vector3, degrees: f32) -> Transform {
+        let matrix = Matrix4::rotation(axis, degrees);
+        Transform {matrix, inverse: matrix.transpose()}
+    }
+
+    // this transform followed by next
+    pub fn then(&self, next: &Transform) -> Transform {
+        Transform {matrix: next.matrix * self.matrix, inverse: self.inverse * next.inverse}
+    }
+
+    pub fn point(&self, p: &Vector3) -> Vector3 {
+        self.matrix.transform_point(p)
+    }
+
+    pub fn vector(&self, v: &Vector3) -> Vector3 {
+        self.matrix.transform_vector(v)
+    }
+
+    // normals go through the inverse transpose to stay perpendicular under non uniform scaling, not normalized
+    pub fn normal(&self, n: &Vector3) -> Vector3 {
+        let m = &self.inverse.m;
+        Vector3 {
+            x: m[0][0]*n.x + m[1][0]*n.y + m[2][0]*n.z,
+            y: m[0][1]*n.x + m[1][1]*n.y + m[2][1]*n.z,
+            z: m[0][2]*n.x + m[1][2]*n.y + m[2][2]*n.z,
+        }
+    }
+
+    pub fn inverse_point(&self, p: &Vector3) -> Vector3 {
+        self.inverse.transform_point(p)
+    }
+
+    pub fn inverse_vector(&self, v: &Vector3) -> Vector3 {
+        self.inverse.transform_vector(v)
+    }
+
+    // the box around all eight transformed corners
+    pub fn bounds(&self, b: &Aabb) -> Aabb {
+        let mut out = Aabb::empty();
+        for i in 0..8 {
+            let corner = Vector3 {
+                x: if i & 1 == 0 { b.min.x } else { b.max.x },
+                y: if i & 2 == 0 { b.min.y } else { b.max.y },
+                z: if i & 4 == 0 { b.min.z } else { b.max.z },
+            };
+            out = out.grow(&self.point(&corner));
+        }
+        out
+    }
+
+    // how much areas grow, if the transform scales every direction equally and so keeps shapes
+    pub fn area_scale(&self) -> Option<f32> {
+        let m = &self.matrix.m;
+        let columns = [
+            Vector3 {x:m[0][0], y:m[1][0], z:m[2][0]},
+            Vector3 {x:m[0][1], y:m[1][1], z:m[2][1]},
+            Vector3 {x:m[0][2], y:m[1][2], z:m[2][2]},
+        ];
+        let scale_sq = columns[0].length_sq();
+        let tolerance = 1e-4 * scale_sq;
+        let similar = (columns[1].length_sq() - scale_sq).abs() < tolerance && (columns[2].length_sq() - scale_sq).abs() < tolerance
+            && columns[0].dot(&columns[1]).abs() < tolerance && columns[1].dot(&columns[2]).abs() < tolerance && columns[2].dot(&columns[0]).abs() < tolerance;
+        if similar { Some(scale_sq) } else { None }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Aabb;
+    use super::Matrix4;
+    use super::Transform;
+    use super::Vector3;
+
+    fn v(x: f32, y: f32, z: f32) -> Vector3 {
+        Vector3 {x, y, z}
+    }
+
+    fn close(a: Vector3, b: Vector3) -> bool {
+        (a - b).length() < 1e-4
+    }
+
+    #[test]
+    fn matrix_times_its_inverse_is_identity() {
+        let skewed = Matrix4 {m: [[2.0, 1.0, 0.0, 3.0], [0.5, -1.0, 4.0, 0.0], [0.0, 3.0, 1.0, -2.0], [0.0, 0.0, 0.0, 1.0]]};
+        let composed = Matrix4::translation(v(1.0, -2.0, 3.0)) * Matrix4::rotation(v(1.0, 1.0, 0.0), 30.0) * Matrix4::scaling(v(2.0, 0.5, 3.0));
+        // a zero on the diagonal needs the rows swapping
+        let permuted = Matrix4 {m: [[0.0, 1.0, 0.0, 0.0], [1.0, 0.0, 0.0, 0.0], [0.0, 0.0, 0.0, 2.0], [0.0, 0.0, 1.0, 0.0]]};
+        for m in &[skewed, composed, permuted] {
+            let inverse = m.inverse().unwrap();
+            for product in &[*m * inverse, inverse * *m] {
+                for i in 0..4 {
+                    for j in 0..4 {
+                        let expected = if i == j { 1.0 } else { 0.0 };
+                        assert!((product.m[i][j] - expected).abs() < 1e-5, "{:?}", product);
+                    }
+                }
+            }
+        }
+
+        assert!(Matrix4::scaling(v(1.0, 0.0, 1.0)).inverse().is_none());
+        let repeated_row = Matrix4 {m: [[1.0, 2.0, 3.0, 0.0], [2.0, 4.0, 6.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]]};
+        assert!(repeated_row.inverse().is_none());
+    }
+
+    #[test]
+    fn then_applies_the_first_transform_first() {
+        let translate = Transform::translate(v(1.0, 0.0, 0.0));
+        let scale = Transform::scale(v(2.0, 2.0, 2.0));
+        let p = v(1.0, 0.0, 0.0);
+        assert!(close(translate.then(&scale).point(&p), v(4.0, 0.0, 0.0)));
+        assert!(close(scale.then(&translate).point(&p), v(3.0, 0.0, 0.0)));
+
+        let rotate = Transform::rotate(v(0.0, 0.0, 1.0), 90.0);
+        let t = translate.then(&rotate).then(&scale);
+        assert!(close(t.point(&p), v(0.0, 4.0, 0.0)));
+        assert!(close(t.inverse_point(&t.point(&v(0.3, -2.0, 5.0))), v(0.3, -2.0, 5.0)));
+        // vectors don't move with the translation
+        assert!(close(t.vector(&p), v(0.0, 2.0, 0.0)));
+        assert!(close(t.inverse_vector(&v(0.0, 2.0, 0.0)), p));
+    }
+
+    #[test]
+    fn normals_stay_perpendicular_under_non_uniform_scale() {
+        let t = Transform::scale(v(4.0, 1.0, 1.0)).then(&Transform::rotate(v(0.0, 1.0, 1.0), 40.0));
+        // the plane x + y = 0 and two directions lying in it
+        let normal = v(1.0, 1.0, 0.0);
+        let tangents = [v(1.0, -1.0, 0.0), v(0.0, 0.0, 1.0)];
+        let n = t.normal(&normal).normalize();
+        for tangent in &tangents {
+            assert!(n.dot(&t.vector(tangent).normalize()).abs() < 1e-5);
+        }
+        // transforming it like a direction would tilt it off the plane
+        assert!(t.vector(&normal).normalize().dot(&t.vector(&tangents[0]).normalize()).abs() > 0.5);
+    }
+
+    #[test]
+    fn bounds_hold_every_transformed_corner() {
+        let unit = Aabb {min: v(-1.0, -1.0, -1.0), max: v(1.0, 1.0, 1.0)};
+        let b = Transform::rotate(v(0.0, 0.0, 1.0), 45.0).then(&Transform::translate(v(10.0, 0.0, 0.0))).bounds(&unit);
+        let r = 2f32.sqrt();
+        assert!(close(b.min, v(10.0 - r, -r, -1.0)));
+        assert!(close(b.max, v(10.0 + r, r, 1.0)));
+
+        let b = Transform::scale(v(-2.0, 1.0, 3.0)).bounds(&Aabb {min: v(0.0, 0.0, 0.0), max: v(1.0, 1.0, 1.0)});
+        assert!(close(b.min, v(-2.0, 0.0, 0.0)));
+        assert!(close(b.max, v(0.0, 1.0, 3.0)));
+    }
+
+    #[test]
+    fn area_scale_is_only_known_for_similarity_transforms() {
+        assert_eq!(Transform::default().area_scale(), Some(1.0));
+        let similar = Transform::scale(v(2.0, 2.0, 2.0)).then(&Transform::rotate(v(1.0, 2.0, 3.0), 70.0)).then(&Transform::translate(v(5.0, 0.0, 0.0)));
+        assert!((similar.area_scale().unwrap() - 4.0).abs() < 1e-4);
+        // a mirror keeps areas too
+        assert_eq!(Transform::scale(v(-3.0, 3.0, 3.0)).area_scale(), Some(9.0));
+        assert_eq!(Transform::scale(v(2.0, 1.0, 1.0)).area_scale(), None);
+        assert_eq!(Transform::scale(v(2.0, 1.0, 1.0)).then(&Transform::rotate(v(0.0, 0.0, 1.0), 30.0)).area_scale(), None);
+    }
+}
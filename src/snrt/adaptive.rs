@@ -0,0 +1,166 @@
+use snmath::Vector3;
+
+// pixels stop taking samples once the relative standard error of their luminance drops under
+// threshold, but never before min_samples so a lucky streak can't end a pixel early. the samples
+// they leave over go to the pixels still noisy, up to max_samples each
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub struct AdaptiveSettings {
+    pub min_samples: u32,
+    // four times the sample count when not set
+    pub max_samples: Option<u32>,
+    pub threshold: f32,
+}
+
+impl Default for AdaptiveSettings {
+    fn default() -> AdaptiveSettings {
+        AdaptiveSettings {min_samples: 16, max_samples: None, threshold: 0.01}
+    }
+}
+
+impl AdaptiveSettings {
+    pub fn validate(&self, samples: u32) -> Result<(), String> {
+        if self.min_samples == 0 || self.min_samples > samples {
+            return Err(format!("min samples must be between 1 and the sample count ({})", samples));
+        }
+        if self.sample_limit(samples) < samples {
+            return Err(format!("max samples can't be under the sample count ({})", samples));
+        }
+        if self.threshold <= 0.0 || !self.threshold.is_finite() {
+            return Err("adaptive threshold must be positive".to_string());
+        }
+        Ok(())
+    }
+
+    // the most samples one pixel can take when samples is the average
+    pub fn sample_limit(&self, samples: u32) -> u32 {
+        self.max_samples.unwrap_or_else(|| samples.saturating_mul(4))
+    }
+}
+
+// running mean and variance of a pixel's luminance, welford's method
+#[derive(Clone,Copy,PartialEq,Default,Debug)]
+pub struct PixelVariance {
+    pub count: u32,
+    pub mean: f32,
+    pub m2: f32,
+}
+
+impl PixelVariance {
+    pub fn add(&mut self, radiance: Vector3) {
+        let l = 0.2126*radiance.x + 0.7152*radiance.y + 0.0722*radiance.z;
+        if !l.is_finite() {
+            return;
+        }
+        self.count += 1;
+        let delta = l - self.mean;
+        self.mean += delta / self.count as f32;
+        self.m2 += delta * (l - self.mean);
+    }
+
+    pub fn converged(&self, settings: &AdaptiveSettings) -> bool {
+        if self.count < settings.min_samples.max(2) {
+            return false;
+        }
+        let variance = self.m2 / (self.count - 1) as f32;
+        let standard_error = (variance / self.count as f32).sqrt();
+        // the small offset keeps near black pixels from chasing a tiny relative error forever
+        standard_error / (self.mean + 0.01) < settings.threshold
+    }
+}
+
+// samples per pixel as a blue to red ramp, rows from the top like the framebuffer
+pub fn heatmap_rgb8(counts: &[u32], max_samples: u32) -> Vec<u8> {
+    let ramp = [
+        Vector3 {x:0.0, y:0.0, z:0.5},
+        Vector3 {x:0.0, y:0.5, z:1.0},
+        Vector3 {x:0.2, y:0.9, z:0.3},
+        Vector3 {x:1.0, y:0.9, z:0.0},
+        Vector3 {x:0.9, y:0.1, z:0.0},
+    ];
+    let mut bytes = Vec::with_capacity(counts.len() * 3);
+    for &count in counts {
+        let t = (count as f32 / max_samples.max(1) as f32).min(1.0) * (ramp.len() - 1) as f32;
+        let i = (t as usize).min(ramp.len() - 2);
+        let c = Vector3::lerp(&ramp[i], &ramp[i + 1], t - i as f32) * 255.0;
+        bytes.push(c.x as u8);
+        bytes.push(c.y as u8);
+        bytes.push(c.z as u8);
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use snmath::Vector3;
+
+    use super::AdaptiveSettings;
+    use super::PixelVariance;
+    use super::heatmap_rgb8;
+
+    fn gray(v: f32) -> Vector3 {
+        Vector3 {x:v, y:v, z:v}
+    }
+
+    #[test]
+    fn constant_pixels_converge_once_they_have_the_minimum() {
+        let settings = AdaptiveSettings {min_samples: 8, ..AdaptiveSettings::default()};
+        let mut pixel = PixelVariance::default();
+        for i in 0..8 {
+            assert!(!pixel.converged(&settings), "after {} samples", i);
+            pixel.add(gray(0.7));
+        }
+        assert!(pixel.converged(&settings));
+        assert!((pixel.mean - 0.7).abs() < 1e-6);
+        assert!(pixel.m2.abs() < 1e-6);
+
+        // one sample says nothing about the variance even if it is allowed to stop
+        let mut single = PixelVariance::default();
+        single.add(gray(0.7));
+        assert!(!single.converged(&AdaptiveSettings {min_samples: 1, ..settings}));
+    }
+
+    #[test]
+    fn noisy_pixels_keep_sampling_until_the_error_is_small() {
+        let settings = AdaptiveSettings {min_samples: 4, threshold: 0.05, ..AdaptiveSettings::default()};
+        let mut pixel = PixelVariance::default();
+        // alternating 0 and 1, the standard error is about 0.5 / sqrt(n)
+        let mut converged_at = None;
+        for i in 0..1000 {
+            pixel.add(gray((i % 2) as f32));
+            if converged_at.is_none() && pixel.converged(&settings) {
+                converged_at = Some(pixel.count);
+            }
+        }
+        let n = converged_at.unwrap();
+        assert!(n > 350 && n < 450, "{}", n);
+        assert!((pixel.mean - 0.5).abs() < 1e-3);
+        assert!((pixel.m2 / (pixel.count - 1) as f32 - 0.25).abs() < 1e-3);
+
+        // broken samples are left out rather than poisoning the estimate
+        pixel.add(gray(f32::NAN));
+        assert_eq!(pixel.count, 1000);
+    }
+
+    #[test]
+    fn max_samples_defaults_to_four_times_the_average() {
+        let settings = AdaptiveSettings::default();
+        assert_eq!(settings.sample_limit(32), 128);
+        assert_eq!(AdaptiveSettings {max_samples: Some(40), ..settings}.sample_limit(32), 40);
+        assert!(settings.validate(32).is_ok());
+        assert!(AdaptiveSettings {max_samples: Some(31), ..settings}.validate(32).is_err());
+        assert!(AdaptiveSettings {min_samples: 33, ..settings}.validate(32).is_err());
+        assert!(AdaptiveSettings {threshold: f32::NAN, ..settings}.validate(32).is_err());
+    }
+
+    #[test]
+    fn heatmap_runs_from_blue_to_red() {
+        let bytes = heatmap_rgb8(&[0, 50, 100, 250, 0], 100);
+        assert_eq!(bytes.len(), 15);
+        assert_eq!(&bytes[0..3], &[0, 0, 127]);
+        assert_eq!(&bytes[3..6], &[51, 229, 76]);
+        assert_eq!(&bytes[6..9], &[229, 25, 0]);
+        // past the most a pixel can take is still red
+        assert_eq!(&bytes[9..12], &bytes[6..9]);
+        assert_eq!(heatmap_rgb8(&[3], 0), vec![229, 25, 0]);
+    }
+}
@@ -0,0 +1,237 @@
+use snmath::Vector3;
+use snmath::Ray;
+use snmath::Aabb;
+
+const BIN_COUNT: usize = 12;
+const MAX_LEAF_SIZE: usize = 4;
+// cost of visiting a node relative to testing one primitive
+const TRAVERSAL_COST: f32 = 0.125;
+
+enum BvhNode {
+    Leaf { bounds: Aabb, first: usize, count: usize },
+    Interior { bounds: Aabb, left: usize, right: usize, axis: usize },
+}
+
+impl BvhNode {
+    fn bounds(&self) -> &Aabb {
+        match *self {
+            BvhNode::Leaf { ref bounds, .. } => bounds,
+            BvhNode::Interior { ref bounds, .. } => bounds,
+        }
+    }
+}
+
+// a bvh over primitive indices, the caller keeps the primitives and does the actual hit tests
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    order: Vec<usize>,
+}
+
+#[derive(Clone,Copy)]
+struct Bin {
+    bounds: Aabb,
+    count: usize,
+}
+
+impl Bvh {
+    pub fn build(bounds: &[Aabb]) -> Bvh {
+        let mut bvh = Bvh { nodes: Vec::new(), order: (0..bounds.len()).collect() };
+        if !bounds.is_empty() {
+            let centroids: Vec<Vector3> = bounds.iter().map(|b| b.centroid()).collect();
+            bvh.build_node(bounds, &centroids, 0, bounds.len());
+        }
+        bvh
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        match self.nodes.first() {
+            Some(root) => *root.bounds(),
+            None => Aabb::empty(),
+        }
+    }
+
+    fn build_node(&mut self, bounds: &[Aabb], centroids: &[Vector3], first: usize, count: usize) -> usize {
+        let prims = &mut self.order[first..first+count];
+        let node_bounds = prims.iter().fold(Aabb::empty(), |b, &p| b.union(&bounds[p]));
+        let centroid_bounds = prims.iter().fold(Aabb::empty(), |b, &p| b.grow(&centroids[p]));
+
+        let extent = centroid_bounds.extent();
+        let axis = if extent.x > extent.y && extent.x > extent.z { 0 } else if extent.y > extent.z { 1 } else { 2 };
+        let axis_min = centroid_bounds.min.get(axis);
+        let axis_extent = extent.get(axis);
+
+        if count <= 1 || axis_extent <= 0.0 {
+            self.nodes.push(BvhNode::Leaf { bounds: node_bounds, first, count });
+            return self.nodes.len() - 1;
+        }
+
+        let bin_of = |c: &Vector3| (((c.get(axis) - axis_min) / axis_extent * BIN_COUNT as f32) as usize).min(BIN_COUNT - 1);
+
+        let mut bins = [Bin { bounds: Aabb::empty(), count: 0 }; BIN_COUNT];
+        for &p in prims.iter() {
+            let bin = &mut bins[bin_of(&centroids[p])];
+            bin.bounds = bin.bounds.union(&bounds[p]);
+            bin.count += 1;
+        }
+
+        // sweep from the right so each split candidate knows its right side's area and count
+        let mut right_area = [0.0; BIN_COUNT];
+        let mut right_count = [0; BIN_COUNT];
+        let mut acc = Bin { bounds: Aabb::empty(), count: 0 };
+        for i in (1..BIN_COUNT).rev() {
+            acc.bounds = acc.bounds.union(&bins[i].bounds);
+            acc.count += bins[i].count;
+            right_area[i] = acc.bounds.surface_area();
+            right_count[i] = acc.count;
+        }
+
+        let inv_area = 1.0 / node_bounds.surface_area().max(f32::MIN_POSITIVE);
+        let mut best = (f32::MAX, 0);
+        let mut acc = Bin { bounds: Aabb::empty(), count: 0 };
+        for i in 0..BIN_COUNT-1 {
+            acc.bounds = acc.bounds.union(&bins[i].bounds);
+            acc.count += bins[i].count;
+            let cost = TRAVERSAL_COST + (acc.count as f32 * acc.bounds.surface_area() + right_count[i+1] as f32 * right_area[i+1]) * inv_area;
+            if cost < best.0 {
+                best = (cost, i);
+            }
+        }
+
+        if count <= MAX_LEAF_SIZE && best.0 >= count as f32 {
+            self.nodes.push(BvhNode::Leaf { bounds: node_bounds, first, count });
+            return self.nodes.len() - 1;
+        }
+
+        let mut mid = 0;
+        for i in 0..count {
+            if bin_of(&centroids[prims[i]]) <= best.1 {
+                prims.swap(i, mid);
+                mid += 1;
+            }
+        }
+
+        // everything landed in one bin, fall back to an even split
+        if mid == 0 || mid == count {
+            prims.sort_by(|a, b| centroids[*a].get(axis).partial_cmp(&centroids[*b].get(axis)).unwrap());
+            mid = count / 2;
+        }
+
+        let idx = self.nodes.len();
+        self.nodes.push(BvhNode::Leaf { bounds: node_bounds, first, count: 0 });
+        let left = self.build_node(bounds, centroids, first, mid);
+        let right = self.build_node(bounds, centroids, first + mid, count - mid);
+        self.nodes[idx] = BvhNode::Interior { bounds: node_bounds, left, right, axis };
+        idx
+    }
+
+    // hit is called with a primitive index and the closest t so far, and returns a closer t if it hits
+    pub fn trace<F>(&self, r: &Ray, t_min: f32, t_max: f32, mut hit: F) -> Option<(usize, f32)>
+        where F: FnMut(usize, f32) -> Option<f32> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let inv_dir = Vector3 {x:1.0/r.direction.x, y:1.0/r.direction.y, z:1.0/r.direction.z};
+        let mut best: Option<(usize, f32)> = None;
+        let mut closest = t_max;
+
+        let mut stack: Vec<usize> = Vec::with_capacity(64);
+        stack.push(0);
+        while let Some(idx) = stack.pop() {
+            let node = &self.nodes[idx];
+            if !node.bounds().hit(r, &inv_dir, t_min, closest) {
+                continue;
+            }
+
+            match *node {
+                BvhNode::Leaf { first, count, .. } => {
+                    for &prim in &self.order[first..first+count] {
+                        if let Some(t) = hit(prim, closest) {
+                            if t < closest {
+                                closest = t;
+                                best = Some((prim, t));
+                            }
+                        }
+                    }
+                },
+                BvhNode::Interior { left, right, axis, .. } => {
+                    // visit the near child first so the far one is more likely to be culled
+                    if r.direction.get(axis) < 0.0 {
+                        stack.push(left);
+                        stack.push(right);
+                    } else {
+                        stack.push(right);
+                        stack.push(left);
+                    }
+                },
+            }
+        }
+
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use snmath::Vector3;
+    use snmath::Ray;
+    use snmath::Aabb;
+    use snmath::seeded_rng;
+
+    use snrt::Hitable;
+    use snrt::Sphere;
+    use snrt::material::Lambertian;
+    use snrt::texture::gray;
+    use super::Bvh;
+
+    #[test]
+    fn agrees_with_a_linear_scan() {
+        let mut rng = seeded_rng(7);
+        let mut unit = || rng.gen_range::<f32>(0.0, 1.0);
+        let spheres: Vec<Sphere> = (0..300).map(|_| Sphere {
+            pos: Vector3 {x:unit()*20.0 - 10.0, y:unit()*20.0 - 10.0, z:unit()*20.0 - 10.0},
+            radius: 0.05 + unit()*0.6,
+            material: Box::new(Lambertian {albedo: gray(0.5)}),
+        }).collect();
+        let bounds: Vec<Aabb> = spheres.iter().map(|s| s.bounding_box()).collect();
+        let bvh = Bvh::build(&bounds);
+
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let origin = Vector3 {x:unit()*30.0 - 15.0, y:unit()*30.0 - 15.0, z:unit()*30.0 - 15.0};
+            let direction = Vector3::sample_unit_sphere((unit(), unit()));
+            let r = Ray {origin, direction};
+
+            let linear = spheres.iter().enumerate()
+                .filter_map(|(i, s)| s.hit(&r, 0.001, 1000.0).map(|h| (i, h.t)))
+                .fold(None, |best: Option<(usize, f32)>, (i, t)| match best {
+                    Some((_, best_t)) if best_t <= t => best,
+                    _ => Some((i, t)),
+                });
+            let traced = bvh.trace(&r, 0.001, 1000.0, |i, closest| spheres[i].hit(&r, 0.001, closest).map(|h| h.t));
+
+            assert_eq!(linear.map(|h| h.0), traced.map(|h| h.0));
+            if let (Some(a), Some(b)) = (linear, traced) {
+                assert_eq!(a.1, b.1);
+                hits += 1;
+            }
+        }
+        // make sure the rays actually exercised the tree
+        assert!(hits > 100, "{}", hits);
+    }
+
+    #[test]
+    fn bounds_cover_every_primitive() {
+        let bounds: Vec<Aabb> = (0..50).map(|i| {
+            let p = Vector3 {x:i as f32, y:(i * 7 % 11) as f32, z:-(i as f32)};
+            Aabb {min: p, max: p + Vector3 {x:0.5, y:0.5, z:0.5}}
+        }).collect();
+        let all = Bvh::build(&bounds).bounds();
+        for b in &bounds {
+            assert_eq!(all.union(b), all);
+        }
+        assert!(Bvh::build(&[]).trace(&Ray {origin: Vector3::default(), direction: Vector3 {x:1.0, y:0.0, z:0.0}}, 0.0, 1.0, |_, _| Some(0.5)).is_none());
+    }
+}
@@ -0,0 +1,253 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+
+use snrt::adaptive::PixelVariance;
+use snrt::film::Film;
+use snrt::film::Filter;
+
+const MAGIC: &[u8; 8] = b"RTCHECK1";
+
+// fnv-1a, stable between builds unlike the std hashers
+pub fn fingerprint(bytes: &[u8]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for &b in bytes {
+        hash ^= u64::from(b);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_f32<R: Read>(r: &mut R) -> io::Result<f32> {
+    Ok(f32::from_bits(read_u32(r)?))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// everything a render has accumulated so far, enough to pick it up again in a later run
+pub struct Checkpoint {
+    pub seed: u64,
+    // hash of the scene and of the settings that change what a sample returns, it only resumes when they match
+    pub fingerprint: u64,
+    // every pixel has taken the samples before this index, or stopped early once converged
+    pub samples_done: u32,
+    pub film: Film,
+    pub sample_counts: Vec<u32>,
+    pub variances: Vec<PixelVariance>,
+}
+
+impl Checkpoint {
+    pub fn new(seed: u64, fingerprint: u64, width: u32, height: u32, filter: Filter) -> Checkpoint {
+        let count = (width * height) as usize;
+        Checkpoint {
+            seed,
+            fingerprint,
+            samples_done: 0,
+            film: Film::new(width, height, filter),
+            sample_counts: vec![0; count],
+            variances: vec![PixelVariance::default(); count],
+        }
+    }
+
+    fn read_header<R: Read>(r: &mut R) -> io::Result<u64> {
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a rustrace checkpoint"));
+        }
+        read_u64(r)
+    }
+
+    // the seed has to be known before the demo scene can be built and the fingerprint taken
+    pub fn saved_seed(path: &Path) -> io::Result<u64> {
+        Checkpoint::read_header(&mut BufReader::new(File::open(path)?))
+    }
+
+    pub fn load(path: &Path, fingerprint: u64, width: u32, height: u32, filter: Filter) -> io::Result<Checkpoint> {
+        let mut r = BufReader::new(File::open(path)?);
+
+        let seed = Checkpoint::read_header(&mut r)?;
+        if read_u64(&mut r)? != fingerprint || read_u32(&mut r)? != width || read_u32(&mut r)? != height {
+            return Err(invalid("saved from a different scene or settings, delete it to start over"));
+        }
+        let samples_done = read_u32(&mut r)?;
+
+        let film = Film::read_from(&mut r, width, height, filter)?;
+        let count = (width * height) as usize;
+        let mut sample_counts = Vec::with_capacity(count);
+        let mut variances = Vec::with_capacity(count);
+        for _ in 0..count {
+            sample_counts.push(read_u32(&mut r)?);
+            variances.push(PixelVariance {count: read_u32(&mut r)?, mean: read_f32(&mut r)?, m2: read_f32(&mut r)?});
+        }
+
+        Ok(Checkpoint {seed, fingerprint, samples_done, film, sample_counts, variances})
+    }
+
+    // written next to the destination and renamed over it, so being killed mid write leaves the old one intact
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut tmp_name = path.as_os_str().to_owned();
+        tmp_name.push(".tmp");
+        {
+            let mut w = BufWriter::new(File::create(&tmp_name)?);
+            w.write_all(MAGIC)?;
+            w.write_all(&self.seed.to_le_bytes())?;
+            w.write_all(&self.fingerprint.to_le_bytes())?;
+            w.write_all(&self.film.width.to_le_bytes())?;
+            w.write_all(&self.film.height.to_le_bytes())?;
+            w.write_all(&self.samples_done.to_le_bytes())?;
+            self.film.write_to(&mut w)?;
+            for (count, variance) in self.sample_counts.iter().zip(&self.variances) {
+                w.write_all(&count.to_le_bytes())?;
+                w.write_all(&variance.count.to_le_bytes())?;
+                w.write_all(&variance.mean.to_le_bytes())?;
+                w.write_all(&variance.m2.to_le_bytes())?;
+            }
+            w.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        }
+        fs::rename(&tmp_name, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::io;
+    use std::path::PathBuf;
+    use std::process;
+
+    use snmath::Vector3;
+    use snrt::CameraSettings;
+    use snrt::adaptive::PixelVariance;
+    use snrt::film::Film;
+    use snrt::film::Filter;
+    use snrt::film::FilterKind;
+    use snrt::render::Renderer;
+    use snrt::scene::RenderSettings;
+    use snrt::world::World;
+    use super::Checkpoint;
+    use super::fingerprint;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("rustrace-checkpoint-{}-{}", process::id(), name))
+    }
+
+    fn film_bytes(film: &Film) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        film.write_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    // a checkpoint with something in every field
+    fn filled(fingerprint: u64, filter: Filter) -> Checkpoint {
+        let mut state = Checkpoint::new(77, fingerprint, 5, 3, filter);
+        state.samples_done = 12;
+        let mut tile = state.film.tile(0, 0, 5, 3);
+        tile.add_sample(1.3, 2.6, Vector3 {x:0.5, y:1.5, z:2.5});
+        tile.add_sample(4.9, 0.1, Vector3 {x:3.0, y:0.25, z:0.0});
+        state.film.merge(&tile);
+        for (i, (count, variance)) in state.sample_counts.iter_mut().zip(state.variances.iter_mut()).enumerate() {
+            *count = i as u32 * 3;
+            *variance = PixelVariance {count: i as u32, mean: i as f32 * 0.5, m2: 1.0 / (i + 1) as f32};
+        }
+        state
+    }
+
+    fn expect_invalid(result: io::Result<Checkpoint>) {
+        match result {
+            Err(ref e) if e.kind() == io::ErrorKind::InvalidData => {},
+            Err(e) => panic!("expected invalid data, got {}", e),
+            Ok(_) => panic!("expected the checkpoint to be rejected"),
+        }
+    }
+
+    #[test]
+    fn round_trips_everything() {
+        let filter = Filter {kind: FilterKind::Gaussian, radius: 1.5};
+        let path = temp_path("round-trip");
+        let saved = filled(0xdead_beef, filter);
+        saved.save(&path).unwrap();
+
+        assert_eq!(Checkpoint::saved_seed(&path).unwrap(), 77);
+        let loaded = Checkpoint::load(&path, 0xdead_beef, 5, 3, filter).unwrap();
+        assert_eq!(loaded.seed, saved.seed);
+        assert_eq!(loaded.fingerprint, saved.fingerprint);
+        assert_eq!(loaded.samples_done, 12);
+        assert_eq!(loaded.sample_counts, saved.sample_counts);
+        assert_eq!(loaded.variances, saved.variances);
+        assert_eq!(film_bytes(&loaded.film), film_bytes(&saved.film));
+        assert!(loaded.film.resolve().pixels.iter().any(|p| *p != Vector3::default()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_a_different_fingerprint() {
+        let path = temp_path("fingerprint");
+        filled(1, Filter::default()).save(&path).unwrap();
+        expect_invalid(Checkpoint::load(&path, 2, 5, 3, Filter::default()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_a_different_size() {
+        let path = temp_path("size");
+        filled(1, Filter::default()).save(&path).unwrap();
+        expect_invalid(Checkpoint::load(&path, 1, 3, 5, Filter::default()));
+        expect_invalid(Checkpoint::load(&path, 1, 5, 4, Filter::default()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_a_different_filter() {
+        // the filter is part of what the renderer fingerprints
+        let world = World::new();
+        let camera = CameraSettings::default();
+        let box_filter = RenderSettings {width: 5, height: 3, ..RenderSettings::default()};
+        let tent_filter = RenderSettings {filter: Filter {kind: FilterKind::Tent, radius: 1.0}, ..box_filter};
+        let saved_with = Renderer::new(&world, &camera, box_filter, 9).unwrap().fingerprint(b"scene");
+        let loaded_with = Renderer::new(&world, &camera, tent_filter, 9).unwrap().fingerprint(b"scene");
+        assert_ne!(saved_with, loaded_with);
+
+        let path = temp_path("filter");
+        filled(saved_with, box_filter.filter).save(&path).unwrap();
+        expect_invalid(Checkpoint::load(&path, loaded_with, 5, 3, tent_filter.filter));
+        assert!(Checkpoint::load(&path, saved_with, 5, 3, box_filter.filter).is_ok());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let path = temp_path("other");
+        fs::write(&path, b"P6\n1 1\n255\n\0\0\0").unwrap();
+        expect_invalid(Checkpoint::load(&path, 1, 1, 1, Filter::default()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn fingerprint_is_fnv1a() {
+        // published fnv-1a 64 test vectors, so the hash can't drift between builds
+        assert_eq!(fingerprint(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fingerprint(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fingerprint(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+}
@@ -0,0 +1,342 @@
+use std::f32;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+extern crate image;
+use image::hdr::HDRDecoder;
+
+use snmath::Vector3;
+
+// what rays leaving the scene see, and how to aim shadow rays at the bright parts of it
+pub trait Environment {
+    fn radiance(&self, dir: &Vector3) -> Vector3;
+    // a unit direction with its radiance and solid angle pdf, none when there's nothing worth sampling
+    fn sample(&self, u: (f32, f32)) -> Option<(Vector3, Vector3, f32)>;
+    // the pdf sample has of returning dir, 0 for environments that are never sampled
+    fn pdf(&self, dir: &Vector3) -> f32;
+}
+
+// a vertical gradient, only ever found by bsdf sampling
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub struct GradientSky {
+    pub horizon: Vector3,
+    pub zenith: Vector3,
+}
+
+impl Default for GradientSky {
+    fn default() -> GradientSky {
+        GradientSky {horizon: Vector3 {x:1.0, y:1.0, z:1.0}, zenith: Vector3 {x:0.5, y:0.7, z:1.0}}
+    }
+}
+
+impl Environment for GradientSky {
+    fn radiance(&self, dir: &Vector3) -> Vector3 {
+        let t = 0.5*(dir.normalize().y + 1.0);
+        Vector3::lerp(&self.horizon, &self.zenith, t)
+    }
+
+    fn sample(&self, _u: (f32, f32)) -> Option<(Vector3, Vector3, f32)> {
+        None
+    }
+
+    fn pdf(&self, _dir: &Vector3) -> f32 {
+        0.0
+    }
+}
+
+fn luminance(c: Vector3) -> f32 {
+    0.2126*c.x + 0.7152*c.y + 0.0722*c.z
+}
+
+// piecewise constant over [0, 1), sampled by inverting its cdf
+struct Distribution1D {
+    func: Vec<f32>,
+    cdf: Vec<f32>,
+    integral: f32,
+}
+
+impl Distribution1D {
+    fn new(func: Vec<f32>) -> Distribution1D {
+        let n = func.len() as f32;
+        let mut cdf = Vec::with_capacity(func.len() + 1);
+        cdf.push(0.0);
+        for f in &func {
+            let last = cdf[cdf.len() - 1];
+            cdf.push(last + f / n);
+        }
+        let integral = cdf[cdf.len() - 1];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            // all zero falls back to uniform
+            *c = if integral > 0.0 { *c / integral } else { i as f32 / n };
+        }
+        Distribution1D {func, cdf, integral}
+    }
+
+    fn pdf(&self, i: usize) -> f32 {
+        if self.integral > 0.0 { self.func[i] / self.integral } else { 1.0 }
+    }
+
+    // the continuous position, its pdf and which piece it fell in
+    fn sample(&self, u: f32) -> (f32, f32, usize) {
+        // the last piece starting at or before u, which skips over empty ones
+        let i = (self.cdf.partition_point(|c| *c <= u).max(1) - 1).min(self.func.len() - 1);
+        let width = self.cdf[i + 1] - self.cdf[i];
+        // kept off the piece's edges, where the rows of a lat-long map meet the poles
+        let du = if width > 0.0 { ((u - self.cdf[i]) / width).clamp(0.000_001, 0.999_999) } else { 0.5 };
+        ((i as f32 + du) / self.func.len() as f32, self.pdf(i), i)
+    }
+}
+
+// an equirectangular (latitude/longitude) image around the scene. directions are importance
+// sampled in proportion to texel brightness so small bright suns don't turn into fireflies
+pub struct ImageEnvironment {
+    width: u32,
+    height: u32,
+    texels: Vec<Vector3>,
+    // about the y axis, as sin and cos of the angle
+    rotation: (f32, f32),
+    intensity: f32,
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl ImageEnvironment {
+    // rotation in degrees turns the map about the vertical axis, intensity scales its radiance
+    pub fn new(width: u32, height: u32, texels: Vec<Vector3>, rotation: f32, intensity: f32) -> ImageEnvironment {
+        let mut rows = Vec::with_capacity(height as usize);
+        for y in 0..height {
+            // rows near the poles cover less of the sphere
+            let sin_theta = (f32::consts::PI * (y as f32 + 0.5) / height as f32).sin();
+            let row = &texels[(y * width) as usize..((y + 1) * width) as usize];
+            rows.push(Distribution1D::new(row.iter().map(|t| luminance(*t).max(0.0) * sin_theta).collect()));
+        }
+        let marginal = Distribution1D::new(rows.iter().map(|r| r.integral).collect());
+        ImageEnvironment {width, height, texels, rotation: rotation.to_radians().sin_cos(), intensity, rows, marginal}
+    }
+
+    pub fn load(path: &Path, rotation: f32, intensity: f32) -> image::ImageResult<ImageEnvironment> {
+        let decoder = HDRDecoder::new(BufReader::new(File::open(path)?))?;
+        let meta = decoder.metadata();
+        let texels = decoder.read_image_hdr()?.iter().map(|p| Vector3 {x:p.data[0], y:p.data[1], z:p.data[2]}).collect();
+        Ok(ImageEnvironment::new(meta.width, meta.height, texels, rotation, intensity))
+    }
+
+    // u runs around from +x towards +z, v from straight up to straight down
+    fn direction_to_uv(&self, dir: &Vector3) -> (f32, f32) {
+        let d = dir.normalize();
+        let (sin, cos) = self.rotation;
+        let x = d.x*cos - d.z*sin;
+        let z = d.x*sin + d.z*cos;
+        let phi = z.atan2(x);
+        let phi = if phi < 0.0 { phi + 2.0*f32::consts::PI } else { phi };
+        (phi / (2.0*f32::consts::PI), d.y.clamp(-1.0, 1.0).acos() / f32::consts::PI)
+    }
+
+    fn texel_at(&self, uv: (f32, f32)) -> (usize, usize) {
+        let x = ((uv.0 * self.width as f32) as usize).min(self.width as usize - 1);
+        let y = ((uv.1 * self.height as f32) as usize).min(self.height as usize - 1);
+        (x, y)
+    }
+}
+
+impl Environment for ImageEnvironment {
+    // nearest texel, so what is seen matches what is sampled exactly
+    fn radiance(&self, dir: &Vector3) -> Vector3 {
+        if self.texels.is_empty() {
+            return Vector3::default();
+        }
+        let (x, y) = self.texel_at(self.direction_to_uv(dir));
+        self.texels[y * self.width as usize + x] * self.intensity
+    }
+
+    fn sample(&self, u: (f32, f32)) -> Option<(Vector3, Vector3, f32)> {
+        if self.texels.is_empty() || self.marginal.integral <= 0.0 {
+            return None;
+        }
+        let (v, row_pdf, y) = self.marginal.sample(u.1);
+        let (u, column_pdf, _) = self.rows[y].sample(u.0);
+
+        let theta = v * f32::consts::PI;
+        let phi = u * 2.0 * f32::consts::PI;
+        let sin_theta = theta.sin();
+        if sin_theta <= 0.0 {
+            return None;
+        }
+        let (x, z) = (sin_theta * phi.cos(), sin_theta * phi.sin());
+        // back out of the map's rotation
+        let (sin, cos) = self.rotation;
+        let dir = Vector3 {x: x*cos + z*sin, y: theta.cos(), z: -x*sin + z*cos};
+
+        // from density over the image to density over solid angle
+        let pdf = row_pdf * column_pdf / (2.0 * f32::consts::PI * f32::consts::PI * sin_theta);
+        Some((dir, self.radiance(&dir), pdf))
+    }
+
+    fn pdf(&self, dir: &Vector3) -> f32 {
+        if self.texels.is_empty() || self.marginal.integral <= 0.0 {
+            return 0.0;
+        }
+        let uv = self.direction_to_uv(dir);
+        // from the horizontal part rather than the angle, which loses precision near the poles
+        let d = dir.normalize();
+        let sin_theta = (d.x*d.x + d.z*d.z).sqrt();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        let (x, y) = self.texel_at(uv);
+        self.marginal.pdf(y) * self.rows[y].pdf(x) / (2.0 * f32::consts::PI * f32::consts::PI * sin_theta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32;
+
+    use snmath::Vector3;
+    use snrt::sampler::Sampler;
+    use snrt::sampler::SamplerKind;
+    use super::Environment;
+    use super::ImageEnvironment;
+
+    fn sampler(seed: u64) -> Box<dyn Sampler> {
+        let mut sampler = SamplerKind::Independent.create(seed, 1);
+        sampler.start_sample(0, 0, 0);
+        sampler
+    }
+
+    // a dim uneven background with a small bright patch, turned so the rotation is exercised
+    fn map() -> ImageEnvironment {
+        let (width, height) = (16, 8);
+        let texels = (0..width * height).map(|i| {
+            let (x, y) = (i % width, i / width);
+            let base = 0.1 + 0.05 * ((x * 7 + y * 3) % 5) as f32;
+            if x == 11 && (y == 2 || y == 3) {
+                Vector3 {x:40.0, y:30.0, z:20.0}
+            } else {
+                Vector3 {x:base, y:base * 1.2, z:base * 1.5}
+            }
+        }).collect();
+        ImageEnvironment::new(width, height, texels, 30.0, 2.0)
+    }
+
+    fn direction(theta: f32, phi: f32) -> Vector3 {
+        Vector3 {x:theta.sin() * phi.cos(), y:theta.cos(), z:theta.sin() * phi.sin()}
+    }
+
+    // integrates f over the sphere on a grid much finer than the texels
+    fn sphere_integral<F: Fn(&Vector3) -> f64>(f: F) -> f64 {
+        let (n_theta, n_phi) = (512, 1024);
+        let d_theta = f32::consts::PI / n_theta as f32;
+        let d_phi = 2.0 * f32::consts::PI / n_phi as f32;
+        let mut sum = 0.0;
+        for i in 0..n_theta {
+            let theta = (i as f32 + 0.5) * d_theta;
+            for j in 0..n_phi {
+                let phi = (j as f32 + 0.5) * d_phi;
+                sum += f(&direction(theta, phi)) * f64::from(theta.sin() * d_theta * d_phi);
+            }
+        }
+        sum
+    }
+
+    #[test]
+    fn samples_agree_with_pdf_and_radiance() {
+        let env = map();
+        let mut s = sampler(1);
+        for _ in 0..5000 {
+            let (dir, radiance, pdf) = env.sample(s.next_2d()).unwrap();
+            assert!((dir.length() - 1.0).abs() < 1e-4);
+            assert!(pdf > 0.0);
+            assert!((pdf - env.pdf(&dir)).abs() <= 1e-3 * pdf, "{} vs {} towards {:?}", pdf, env.pdf(&dir), dir);
+            assert_eq!(radiance, env.radiance(&dir));
+        }
+    }
+
+    // right at the ends of the unit interval, where the first and last rows reach the poles
+    #[test]
+    fn samples_at_the_edges_of_the_unit_square_are_kept() {
+        let env = map();
+        for &u in &[(0.0, 0.0), (0.0, 0.999_999_9), (0.999_999_9, 0.0), (0.5, 0.0), (0.5, 0.999_999_9)] {
+            let (dir, _, pdf) = env.sample(u).unwrap();
+            assert!(pdf.is_finite() && pdf > 0.0, "{:?}: {}", u, pdf);
+            assert!((pdf - env.pdf(&dir)).abs() <= 1e-3 * pdf, "{:?}: {} vs {}", u, pdf, env.pdf(&dir));
+        }
+    }
+
+    #[test]
+    fn pdf_integrates_to_one() {
+        let env = map();
+        let integral = sphere_integral(|d| f64::from(env.pdf(d)));
+        assert!((integral - 1.0).abs() < 1e-3, "{}", integral);
+    }
+
+    #[test]
+    fn samples_land_on_texels_by_their_pdf() {
+        let env = map();
+        let mut s = sampler(2);
+        let count = 400000;
+        let mut hits = vec![0u32; 16 * 8];
+        for _ in 0..count {
+            let (dir, _, _) = env.sample(s.next_2d()).unwrap();
+            let (x, y) = env.texel_at(env.direction_to_uv(&dir));
+            hits[y * 16 + x] += 1;
+        }
+        // each texel's share is its pdf over the image, which is the same for every point in it
+        for y in 0..8 {
+            for x in 0..16 {
+                let expected = env.marginal.pdf(y) * env.rows[y].pdf(x) / (16.0 * 8.0);
+                let fraction = hits[y * 16 + x] as f32 / count as f32;
+                assert!((fraction - expected).abs() < 0.003 + 0.03 * expected, "texel {} {}: {} vs {}", x, y, fraction, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn importance_sampled_radiance_is_unbiased() {
+        let env = map();
+        let expected = sphere_integral(|d| f64::from(env.radiance(d).y));
+        let mut s = sampler(3);
+        let count = 200000;
+        let mut sum = 0.0;
+        for _ in 0..count {
+            let (_, radiance, pdf) = env.sample(s.next_2d()).unwrap();
+            sum += f64::from(radiance.y / pdf);
+        }
+        let estimate = sum / f64::from(count);
+        assert!((estimate - expected).abs() < 0.01 * expected, "{} vs {}", estimate, expected);
+    }
+
+    #[test]
+    fn a_single_bright_texel_gets_every_sample() {
+        let mut texels = vec![Vector3::default(); 8 * 4];
+        texels[8 + 5] = Vector3 {x:1.0, y:1.0, z:1.0};
+        let env = ImageEnvironment::new(8, 4, texels, 0.0, 1.0);
+        let mut s = sampler(4);
+        for _ in 0..1000 {
+            let (dir, radiance, _) = env.sample(s.next_2d()).unwrap();
+            assert_eq!(env.texel_at(env.direction_to_uv(&dir)), (5, 1));
+            assert_eq!(radiance, Vector3 {x:1.0, y:1.0, z:1.0});
+        }
+    }
+
+    #[test]
+    fn a_black_map_is_not_sampled() {
+        let env = ImageEnvironment::new(4, 2, vec![Vector3::default(); 8], 0.0, 1.0);
+        assert!(env.sample((0.3, 0.6)).is_none());
+        assert_eq!(env.pdf(&Vector3 {x:0.0, y:1.0, z:0.0}), 0.0);
+    }
+
+    #[test]
+    fn rotation_turns_the_map_about_the_vertical() {
+        let texels: Vec<Vector3> = (0..16 * 8).map(|i| Vector3 {x:(i % 16) as f32, y:0.0, z:0.0}).collect();
+        let straight = ImageEnvironment::new(16, 8, texels.clone(), 0.0, 1.0);
+        let turned = ImageEnvironment::new(16, 8, texels, 90.0, 1.0);
+        // the map turns one way, so looking the other way round by 90 degrees finds the same texel
+        for &phi in &[0.3f32, 1.7, 4.0] {
+            let d = direction(1.2, phi);
+            let back = direction(1.2, phi - f32::consts::FRAC_PI_2);
+            assert_eq!(turned.radiance(&back), straight.radiance(&d), "at {}", phi);
+        }
+    }
+}
@@ -0,0 +1,342 @@
+use std::f32;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+
+use snmath::Vector3;
+
+use snrt::framebuffer::Framebuffer;
+
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum FilterKind {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+    Lanczos,
+}
+
+pub const FILTER_NAMES: &str = "box, tent, gaussian, mitchell or lanczos";
+
+impl FilterKind {
+    pub fn from_name(name: &str) -> Option<FilterKind> {
+        match name {
+            "box" => Some(FilterKind::Box),
+            "tent" | "triangle" => Some(FilterKind::Tent),
+            "gaussian" => Some(FilterKind::Gaussian),
+            "mitchell" => Some(FilterKind::Mitchell),
+            "lanczos" => Some(FilterKind::Lanczos),
+            _ => None,
+        }
+    }
+
+    // a radius that suits each filter's shape when none is given
+    pub fn default_radius(&self) -> f32 {
+        match *self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell => 2.0,
+            FilterKind::Lanczos => 3.0,
+        }
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    let x = x.abs();
+    if x < 1e-5 {
+        return 1.0;
+    }
+    let px = f32::consts::PI * x;
+    px.sin() / px
+}
+
+// a separable reconstruction filter, radius is in pixels
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub struct Filter {
+    pub kind: FilterKind,
+    pub radius: f32,
+}
+
+impl Default for Filter {
+    fn default() -> Filter {
+        Filter {kind: FilterKind::Box, radius: 0.5}
+    }
+}
+
+impl Filter {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.radius <= 0.0 || !self.radius.is_finite() {
+            return Err("filter radius must be positive".to_string());
+        }
+        Ok(())
+    }
+
+    fn eval_1d(&self, d: f32) -> f32 {
+        let r = self.radius;
+        match self.kind {
+            // half open so a sample on a pixel boundary only lands in one pixel
+            FilterKind::Box => if d >= -r && d < r { 1.0 } else { 0.0 },
+            FilterKind::Tent => (1.0 - d.abs() / r).max(0.0),
+            FilterKind::Gaussian => {
+                let alpha = 2.0;
+                ((-alpha * d * d).exp() - (-alpha * r * r).exp()).max(0.0)
+            },
+            FilterKind::Mitchell => {
+                // b = c = 1/3, stretched so the curve's support of 2 covers the radius
+                let (b, c) = (1.0/3.0, 1.0/3.0);
+                let x = (2.0 * d / r).abs();
+                if x >= 2.0 {
+                    0.0
+                } else if x >= 1.0 {
+                    ((-b - 6.0*c)*x*x*x + (6.0*b + 30.0*c)*x*x + (-12.0*b - 48.0*c)*x + (8.0*b + 24.0*c)) / 6.0
+                } else {
+                    ((12.0 - 9.0*b - 6.0*c)*x*x*x + (-18.0 + 12.0*b + 6.0*c)*x*x + (6.0 - 2.0*b)) / 6.0
+                }
+            },
+            FilterKind::Lanczos => if d.abs() >= r { 0.0 } else { sinc(d) * sinc(d / r) },
+        }
+    }
+
+    pub fn eval(&self, dx: f32, dy: f32) -> f32 {
+        self.eval_1d(dx) * self.eval_1d(dy)
+    }
+}
+
+// weighted sums for a rectangle of pixels, workers fill their own and the film merges them
+pub struct FilmTile {
+    pub x0: u32,
+    pub y0: u32,
+    pub width: u32,
+    pub height: u32,
+    filter: Filter,
+    sums: Vec<Vector3>,
+    weights: Vec<f32>,
+}
+
+impl FilmTile {
+    // sample position is in film space, pixel (x, y) covers [x, x+1) x [y, y+1) with y running down
+    pub fn add_sample(&mut self, px: f32, py: f32, radiance: Vector3) {
+        // a single broken path shouldn't wipe out every pixel under the filter
+        if !(radiance.x.is_finite() && radiance.y.is_finite() && radiance.z.is_finite()) {
+            return;
+        }
+
+        let r = self.filter.radius;
+        let x_min = ((px - 0.5 - r).ceil() as i64).max(i64::from(self.x0));
+        let x_max = ((px - 0.5 + r).floor() as i64).min(i64::from(self.x0 + self.width) - 1);
+        let y_min = ((py - 0.5 - r).ceil() as i64).max(i64::from(self.y0));
+        let y_max = ((py - 0.5 + r).floor() as i64).min(i64::from(self.y0 + self.height) - 1);
+
+        for y in y_min..=y_max {
+            for x in x_min..=x_max {
+                let weight = self.filter.eval(px - (x as f32 + 0.5), py - (y as f32 + 0.5));
+                if weight == 0.0 {
+                    continue;
+                }
+                let idx = ((y - i64::from(self.y0)) * i64::from(self.width) + (x - i64::from(self.x0))) as usize;
+                self.sums[idx] = self.sums[idx] + radiance * weight;
+                self.weights[idx] += weight;
+            }
+        }
+    }
+}
+
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    pub filter: Filter,
+    sums: Vec<Vector3>,
+    weights: Vec<f32>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32, filter: Filter) -> Film {
+        let count = (width * height) as usize;
+        Film {width, height, filter, sums: vec![Vector3::default(); count], weights: vec![0.0; count]}
+    }
+
+    // a tile for samples taken in pixels [x0, x1) x [y0, y1), grown by the filter radius
+    pub fn tile(&self, x0: u32, y0: u32, x1: u32, y1: u32) -> FilmTile {
+        let pad = (self.filter.radius - 0.5).max(0.0).ceil() as u32;
+        let tx0 = x0.saturating_sub(pad);
+        let ty0 = y0.saturating_sub(pad);
+        let tx1 = (x1 + pad).min(self.width);
+        let ty1 = (y1 + pad).min(self.height);
+        let count = ((tx1 - tx0) * (ty1 - ty0)) as usize;
+        FilmTile {
+            x0: tx0,
+            y0: ty0,
+            width: tx1 - tx0,
+            height: ty1 - ty0,
+            filter: self.filter,
+            sums: vec![Vector3::default(); count],
+            weights: vec![0.0; count],
+        }
+    }
+
+    pub fn merge(&mut self, tile: &FilmTile) {
+        for ty in 0..tile.height {
+            for tx in 0..tile.width {
+                let t_idx = (ty * tile.width + tx) as usize;
+                let idx = ((tile.y0 + ty) * self.width + tile.x0 + tx) as usize;
+                self.sums[idx] = self.sums[idx] + tile.sums[t_idx];
+                self.weights[idx] += tile.weights[t_idx];
+            }
+        }
+    }
+
+    // the raw sums and weights, little endian, so a render can carry on where it stopped
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        for (sum, weight) in self.sums.iter().zip(&self.weights) {
+            w.write_all(&sum.x.to_le_bytes())?;
+            w.write_all(&sum.y.to_le_bytes())?;
+            w.write_all(&sum.z.to_le_bytes())?;
+            w.write_all(&weight.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(r: &mut R, width: u32, height: u32, filter: Filter) -> io::Result<Film> {
+        let mut film = Film::new(width, height, filter);
+        let mut buf = [0u8; 16];
+        for i in 0..film.sums.len() {
+            r.read_exact(&mut buf)?;
+            let value = |j: usize| f32::from_le_bytes([buf[j], buf[j+1], buf[j+2], buf[j+3]]);
+            film.sums[i] = Vector3 {x:value(0), y:value(4), z:value(8)};
+            film.weights[i] = value(12);
+        }
+        Ok(film)
+    }
+
+    // normalise by the filter weights, negative lobes can push a pixel below zero so clamp there
+    pub fn resolve(&self) -> Framebuffer {
+        let mut framebuffer = Framebuffer::new(self.width, self.height);
+        for (i, p) in framebuffer.pixels.iter_mut().enumerate() {
+            if self.weights[i] > 0.0 {
+                *p = (self.sums[i] / self.weights[i]).component_max(&Vector3::default());
+            }
+        }
+        framebuffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32;
+
+    use snmath::Vector3;
+    use super::Film;
+    use super::Filter;
+    use super::FilterKind;
+
+    const KINDS: [FilterKind; 5] = [FilterKind::Box, FilterKind::Tent, FilterKind::Gaussian, FilterKind::Mitchell, FilterKind::Lanczos];
+
+    fn filters() -> Vec<Filter> {
+        KINDS.iter().flat_map(|&kind| vec![
+            Filter {kind, radius: kind.default_radius()},
+            Filter {kind, radius: 1.3},
+        ]).collect()
+    }
+
+    #[test]
+    fn filters_peak_in_the_middle_and_vanish_past_their_radius() {
+        for filter in filters() {
+            let r = filter.radius;
+            let peak = filter.eval(0.0, 0.0);
+            assert!(peak > 0.0, "{:?}", filter);
+            for i in 1..100 {
+                let d = r * i as f32 / 100.0;
+                assert!(filter.eval(d, 0.0) <= peak, "{:?} at {}", filter, d);
+                assert!((filter.eval(d * 0.99, 0.3) - filter.eval(-d * 0.99, 0.3)).abs() < 1e-6, "{:?} at {}", filter, d);
+                assert_eq!(filter.eval(d, 0.2), filter.eval(0.2, d), "{:?} at {}", filter, d);
+            }
+            // the box is half open, so only its upper edge is outside
+            assert_eq!(filter.eval(r, 0.0), 0.0, "{:?}", filter);
+            for &d in &[r * 1.01, r + 1.0, 100.0] {
+                assert_eq!(filter.eval(d, 0.0), 0.0, "{:?} at {}", filter, d);
+                assert_eq!(filter.eval(0.0, -d), 0.0, "{:?} at {}", filter, d);
+            }
+        }
+    }
+
+    // a flat image stays flat whatever the filter, right up to the edges, and that only works
+    // out when every pixel is normalised by exactly the weights its samples brought
+    #[test]
+    fn a_constant_image_resolves_to_the_constant() {
+        let color = Vector3 {x:0.25, y:0.5, z:2.0};
+        for filter in filters() {
+            let mut film = Film::new(6, 5, filter);
+            let mut tile = film.tile(0, 0, 6, 5);
+            for sy in 0..5 * 7 {
+                for sx in 0..6 * 7 {
+                    tile.add_sample((sx as f32 + 0.5) / 7.0, (sy as f32 + 0.5) / 7.0, color);
+                }
+            }
+            film.merge(&tile);
+            for p in film.resolve().pixels {
+                assert!((p - color).length() < 1e-4, "{:?}: {:?}", filter, p);
+            }
+        }
+    }
+
+    #[test]
+    fn tiles_add_up_to_the_whole_film() {
+        for filter in filters() {
+            let samples: Vec<(f32, f32, Vector3)> = (0..2000u32).map(|i| {
+                let x = (i as f32 * 0.618_034).fract() * 9.0;
+                let y = (i as f32 * 0.754_878).fract() * 7.0;
+                (x, y, Vector3 {x, y, z:(i % 5) as f32})
+            }).collect();
+
+            let mut whole = Film::new(9, 7, filter);
+            let mut tile = whole.tile(0, 0, 9, 7);
+            for &(x, y, c) in &samples {
+                tile.add_sample(x, y, c);
+            }
+            whole.merge(&tile);
+
+            // each tile only gets the samples taken in its own pixels, as the renderer does
+            let mut tiled = Film::new(9, 7, filter);
+            for &(x0, y0, x1, y1) in &[(0, 0, 4, 3), (4, 0, 9, 3), (0, 3, 4, 7), (4, 3, 9, 7)] {
+                let mut tile = tiled.tile(x0, y0, x1, y1);
+                for &(x, y, c) in &samples {
+                    if x >= x0 as f32 && x < x1 as f32 && y >= y0 as f32 && y < y1 as f32 {
+                        tile.add_sample(x, y, c);
+                    }
+                }
+                tiled.merge(&tile);
+            }
+
+            for (a, b) in whole.resolve().pixels.iter().zip(tiled.resolve().pixels.iter()) {
+                assert!((*a - *b).length() < 1e-4, "{:?}: {:?} {:?}", filter, a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn a_box_sample_on_a_pixel_edge_lands_in_one_pixel() {
+        let mut film = Film::new(3, 1, Filter::default());
+        let mut tile = film.tile(0, 0, 3, 1);
+        tile.add_sample(1.0, 0.5, Vector3 {x:1.0, y:1.0, z:1.0});
+        film.merge(&tile);
+        let pixels = film.resolve().pixels;
+        assert_eq!(pixels[0], Vector3::default());
+        assert_eq!(pixels[1], Vector3 {x:1.0, y:1.0, z:1.0});
+        assert_eq!(pixels[2], Vector3::default());
+    }
+
+    #[test]
+    fn non_finite_samples_are_dropped() {
+        let filter = Filter {kind: FilterKind::Gaussian, radius: 1.5};
+        let mut film = Film::new(3, 3, filter);
+        let mut tile = film.tile(0, 0, 3, 3);
+        tile.add_sample(1.5, 1.5, Vector3 {x:0.5, y:0.5, z:0.5});
+        tile.add_sample(1.6, 1.4, Vector3 {x:f32::NAN, y:0.0, z:0.0});
+        tile.add_sample(1.4, 1.6, Vector3 {x:0.0, y:f32::INFINITY, z:0.0});
+        film.merge(&tile);
+        for p in film.resolve().pixels {
+            assert_eq!(p, Vector3 {x:0.5, y:0.5, z:0.5});
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use std::io;
+use std::io::Write;
+
+use snmath::Vector3;
+
+use snrt::tonemap::DisplayTransform;
+
+// linear radiance per pixel, rows run from the top of the image down
+pub struct Framebuffer {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Vector3>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32) -> Framebuffer {
+        Framebuffer {width, height, pixels: vec![Vector3::default(); (width * height) as usize]}
+    }
+
+    pub fn row(&self, y: u32) -> &[Vector3] {
+        let start = (y * self.width) as usize;
+        &self.pixels[start..start + self.width as usize]
+    }
+
+    // tone mapped and srgb encoded for the ldr formats
+    pub fn to_rgb8(&self, display: &DisplayTransform) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.pixels.len() * 3);
+        for p in &self.pixels {
+            bytes.extend_from_slice(&display.encode(*p));
+        }
+        bytes
+    }
+
+    // portable float map, little endian and stored bottom row first
+    pub fn write_pfm<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write!(w, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+        for y in (0..self.height).rev() {
+            for p in self.row(y) {
+                w.write_all(&p.x.to_le_bytes())?;
+                w.write_all(&p.y.to_le_bytes())?;
+                w.write_all(&p.z.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    // the smallest valid openexr file: one part, uncompressed 32 bit float scanlines
+    pub fn write_exr<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+            header.extend_from_slice(name.as_bytes());
+            header.push(0);
+            header.extend_from_slice(kind.as_bytes());
+            header.push(0);
+            header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+            header.extend_from_slice(value);
+        }
+
+        // channels have to be listed alphabetically
+        let mut channels = Vec::new();
+        for name in &["B", "G", "R"] {
+            channels.extend_from_slice(name.as_bytes());
+            channels.push(0);
+            channels.extend_from_slice(&2i32.to_le_bytes()); // float
+            channels.extend_from_slice(&[0, 0, 0, 0]); // plinear and reserved
+            channels.extend_from_slice(&1i32.to_le_bytes());
+            channels.extend_from_slice(&1i32.to_le_bytes());
+        }
+        channels.push(0);
+
+        let mut window = Vec::new();
+        for v in &[0, 0, self.width as i32 - 1, self.height as i32 - 1] {
+            window.extend_from_slice(&v.to_le_bytes());
+        }
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&20_000_630i32.to_le_bytes());
+        header.extend_from_slice(&2i32.to_le_bytes());
+        attribute(&mut header, "channels", "chlist", &channels);
+        attribute(&mut header, "compression", "compression", &[0]);
+        attribute(&mut header, "dataWindow", "box2i", &window);
+        attribute(&mut header, "displayWindow", "box2i", &window);
+        attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+        attribute(&mut header, "pixelAspectRatio", "float", &1.0f32.to_le_bytes());
+        attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+        attribute(&mut header, "screenWindowWidth", "float", &1.0f32.to_le_bytes());
+        header.push(0);
+        w.write_all(&header)?;
+
+        // without compression every chunk is a single scanline
+        let line_size = self.width as u64 * 3 * 4;
+        let chunk_size = 8 + line_size;
+        let first_chunk = header.len() as u64 + 8 * self.height as u64;
+        for y in 0..self.height as u64 {
+            w.write_all(&(first_chunk + y * chunk_size).to_le_bytes())?;
+        }
+
+        for y in 0..self.height {
+            w.write_all(&(y as i32).to_le_bytes())?;
+            w.write_all(&(line_size as i32).to_le_bytes())?;
+            let row = self.row(y);
+            for channel in &[2, 1, 0] {
+                for p in row {
+                    w.write_all(&p.get(*channel).to_le_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use snmath::Vector3;
+
+    use super::Framebuffer;
+
+    // every channel of every pixel is different
+    fn gradient() -> Framebuffer {
+        let mut framebuffer = Framebuffer::new(3, 2);
+        for (i, p) in framebuffer.pixels.iter_mut().enumerate() {
+            let i = i as f32;
+            *p = Vector3 {x:i, y:i + 0.25, z:i + 0.5};
+        }
+        framebuffer
+    }
+
+    fn f32_at(bytes: &[u8], at: usize) -> f32 {
+        f32::from_bits(u32_at(bytes, at))
+    }
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        let mut word = [0; 4];
+        word.copy_from_slice(&bytes[at..at + 4]);
+        u32::from_le_bytes(word)
+    }
+
+    fn cstr(bytes: &[u8], at: usize) -> (String, usize) {
+        let end = at + bytes[at..].iter().position(|&b| b == 0).unwrap();
+        (String::from_utf8(bytes[at..end].to_vec()).unwrap(), end + 1)
+    }
+
+    #[test]
+    fn pfm_is_little_endian_and_bottom_up() {
+        let framebuffer = gradient();
+        let mut bytes = Vec::new();
+        framebuffer.write_pfm(&mut bytes).unwrap();
+
+        let header = "PF\n3 2\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header.as_bytes());
+        assert_eq!(bytes.len(), header.len() + 3 * 2 * 3 * 4);
+
+        let data = &bytes[header.len()..];
+        for (i, y) in [1, 0].iter().enumerate() {
+            for (x, p) in framebuffer.row(*y).iter().enumerate() {
+                let at = (i * 3 + x) * 12;
+                assert_eq!(f32_at(data, at), p.x);
+                assert_eq!(f32_at(data, at + 4), p.y);
+                assert_eq!(f32_at(data, at + 8), p.z);
+            }
+        }
+    }
+
+    #[test]
+    fn exr_header_and_offsets_describe_its_scanlines() {
+        let framebuffer = gradient();
+        let mut bytes = Vec::new();
+        framebuffer.write_exr(&mut bytes).unwrap();
+
+        assert_eq!(u32_at(&bytes, 0), 20_000_630);
+        assert_eq!(u32_at(&bytes, 4), 2);
+
+        let mut names = Vec::new();
+        let mut at = 8;
+        loop {
+            let (name, next) = cstr(&bytes, at);
+            if name.is_empty() {
+                at = next;
+                break;
+            }
+            let (_, next) = cstr(&bytes, next);
+            let size = u32_at(&bytes, next) as usize;
+            if name == "dataWindow" {
+                let window: Vec<u32> = (0..4).map(|i| u32_at(&bytes, next + 4 + i * 4)).collect();
+                assert_eq!(window, vec![0, 0, 2, 1]);
+            }
+            names.push(name);
+            at = next + 4 + size;
+        }
+        assert_eq!(names, vec!["channels", "compression", "dataWindow", "displayWindow", "lineOrder",
+                               "pixelAspectRatio", "screenWindowCenter", "screenWindowWidth"]);
+
+        let line_size = 3 * 3 * 4;
+        for y in 0..2 {
+            let offset = u32_at(&bytes, at + y * 8) as usize;
+            assert_eq!(u32_at(&bytes, at + y * 8 + 4), 0);
+            assert_eq!(u32_at(&bytes, offset) as usize, y);
+            assert_eq!(u32_at(&bytes, offset + 4) as usize, line_size);
+            // channels are stored one after the other, alphabetically
+            let row = framebuffer.row(y as u32);
+            for (x, p) in row.iter().enumerate() {
+                assert_eq!(f32_at(&bytes, offset + 8 + x * 4), p.z);
+                assert_eq!(f32_at(&bytes, offset + 8 + (3 + x) * 4), p.y);
+                assert_eq!(f32_at(&bytes, offset + 8 + (6 + x) * 4), p.x);
+            }
+            if y == 1 {
+                assert_eq!(offset + 8 + line_size, bytes.len());
+            }
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::sync::Arc;
+
+use snmath::Aabb;
+use snmath::Ray;
+use snmath::Transform;
+
+use snrt::Hitable;
+use snrt::HitRecord;
+use snrt::material::Material;
+use snrt::sampler::Sampler;
+
+// places a shared object in the world through a transform, so one mesh can be drawn many times
+// and anything can be rotated or scaled. rays go into object space and hits come back out
+pub struct TransformedInstance {
+    object: Arc<dyn Hitable + Sync + Send>,
+    transform: Transform,
+    bounds: Aabb,
+    // none when the transform stretches the object, uniform points on it wouldn't stay uniform
+    area_scale: Option<f32>,
+}
+
+impl TransformedInstance {
+    pub fn new(object: Arc<dyn Hitable + Sync + Send>, transform: Transform) -> TransformedInstance {
+        let bounds = transform.bounds(&object.bounding_box());
+        let area_scale = transform.area_scale();
+        TransformedInstance {object, transform, bounds, area_scale}
+    }
+
+    fn to_world<'a>(&self, local: &HitRecord<'a>, r: Option<&Ray>) -> HitRecord<'a> {
+        let geometric_normal = self.transform.normal(&local.geometric_normal).normalize();
+        let normal = self.transform.normal(&local.normal).normalize();
+        let mut rec = HitRecord::on_surface(self.transform.point(&local.pos), geometric_normal, normal, local.uv, self.transform.vector(&local.tangent), local.material);
+        // the object space ray's direction isn't normalized either, so t carries straight over
+        rec.t = local.t;
+        rec.medium = local.medium;
+        if let Some(r) = r {
+            rec.set_face(r);
+        }
+        rec
+    }
+}
+
+impl Hitable for TransformedInstance {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let local_ray = Ray {origin: self.transform.inverse_point(&r.origin), direction: self.transform.inverse_vector(&r.direction)};
+        let local = self.object.hit(&local_ray, t_min, t_max)?;
+        Some(self.to_world(&local, Some(r)))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bounds
+    }
+
+    fn material(&self) -> &(dyn Material + Sync) {
+        self.object.material()
+    }
+
+    // stretched emitters report no area so they're only found by bsdf sampling
+    fn area(&self) -> f32 {
+        self.area_scale.map_or(0.0, |s| self.object.area() * s)
+    }
+
+    fn sample_surface(&self, sampler: &mut dyn Sampler) -> HitRecord<'_> {
+        let local = self.object.sample_surface(sampler);
+        self.to_world(&local, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use snmath::Ray;
+    use snmath::Transform;
+    use snmath::Vector3;
+
+    use snrt::Hitable;
+    use snrt::Sphere;
+    use snrt::material::Lambertian;
+    use snrt::texture::gray;
+
+    use super::TransformedInstance;
+
+    fn v(x: f32, y: f32, z: f32) -> Vector3 {
+        Vector3 {x, y, z}
+    }
+
+    fn close(a: Vector3, b: Vector3) -> bool {
+        (a - b).length() < 1e-4
+    }
+
+    // a unit sphere stretched to twice as wide and moved away along -z
+    fn ellipsoid() -> TransformedInstance {
+        let sphere = Sphere {pos: Vector3::default(), radius: 1.0, material: Box::new(Lambertian {albedo: gray(0.5)})};
+        TransformedInstance::new(Arc::new(sphere), Transform::scale(v(2.0, 1.0, 1.0)).then(&Transform::translate(v(0.0, 0.0, -5.0))))
+    }
+
+    #[test]
+    fn hits_come_back_in_world_space() {
+        let instance = ellipsoid();
+        let hit = instance.hit(&Ray {origin: v(0.0, 0.0, 0.0), direction: v(0.0, 0.0, -1.0)}, 0.001, 100.0).unwrap();
+        assert!((hit.t - 4.0).abs() < 1e-4);
+        assert!(close(hit.pos, v(0.0, 0.0, -4.0)));
+        assert!(close(hit.geometric_normal, v(0.0, 0.0, 1.0)));
+        assert!(hit.front_face);
+
+        // the stretched side is twice as far out
+        let r = Ray {origin: v(-10.0, 0.0, -5.0), direction: v(1.0, 0.0, 0.0)};
+        let hit = instance.hit(&r, 0.001, 100.0).unwrap();
+        assert!((hit.t - 8.0).abs() < 1e-4);
+        assert!(close(hit.geometric_normal, v(-1.0, 0.0, 0.0)));
+        assert!(instance.hit(&r, 0.001, 7.0).is_none());
+
+        assert!(close(instance.bounding_box().min, v(-2.0, -1.0, -6.0)));
+        assert!(close(instance.bounding_box().max, v(2.0, 1.0, -4.0)));
+        // stretched, so no area to sample lights on
+        assert_eq!(instance.area(), 0.0);
+    }
+
+    #[test]
+    fn normals_follow_the_stretched_surface() {
+        let instance = ellipsoid();
+        // straight down onto x = 1, where the surface x²/4 + y² = 1 slopes
+        let r = Ray {origin: v(1.0, 5.0, -5.0), direction: v(0.0, -1.0, 0.0)};
+        let hit = instance.hit(&r, 0.001, 100.0).unwrap();
+        let y = 0.75f32.sqrt();
+        assert!(close(hit.pos, v(1.0, y, -5.0)));
+        assert!(close(r.point_at_parameter(hit.t), hit.pos));
+        assert!(close(hit.geometric_normal, v(0.25, y, 0.0).normalize()));
+        assert!((hit.normal.length() - 1.0).abs() < 1e-5);
+    }
+}
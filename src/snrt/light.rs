@@ -0,0 +1,138 @@
+use std::f32;
+
+use snmath::Vector3;
+
+// lights with no area, a bounce can never find them so they are only reached by shadow rays
+pub trait Light {
+    // unit direction from pos to the light, distance to it and the radiance arriving at pos
+    fn sample_li(&self, pos: Vector3) -> Option<(Vector3, f32, Vector3)>;
+}
+
+#[derive(Clone,Copy,PartialEq,Default,Debug)]
+pub struct PointLight {
+    pub pos: Vector3,
+    pub intensity: Vector3,
+}
+
+impl Light for PointLight {
+    fn sample_li(&self, pos: Vector3) -> Option<(Vector3, f32, Vector3)> {
+        let to_light = self.pos - pos;
+        let dist_sq = to_light.length_sq();
+        if dist_sq == 0.0 {
+            return None;
+        }
+        let dist = dist_sq.sqrt();
+        Some((to_light / dist, dist, self.intensity / dist_sq))
+    }
+}
+
+// a point light restricted to a cone, fading out between the two angles (in degrees)
+#[derive(Clone,Copy,PartialEq,Default,Debug)]
+pub struct SpotLight {
+    pub pos: Vector3,
+    pub direction: Vector3,
+    pub intensity: Vector3,
+    pub inner_angle: f32,
+    pub outer_angle: f32,
+}
+
+impl Light for SpotLight {
+    fn sample_li(&self, pos: Vector3) -> Option<(Vector3, f32, Vector3)> {
+        let to_light = self.pos - pos;
+        let dist_sq = to_light.length_sq();
+        if dist_sq == 0.0 {
+            return None;
+        }
+        let dist = dist_sq.sqrt();
+        let dir = to_light / dist;
+
+        let cos_theta = -dir.dot(&self.direction.normalize());
+        let cos_outer = (self.outer_angle * f32::consts::PI / 180.0).cos();
+        let cos_inner = (self.inner_angle * f32::consts::PI / 180.0).cos();
+        if cos_theta <= cos_outer {
+            return None;
+        }
+
+        let falloff = if cos_theta >= cos_inner {
+            1.0
+        } else {
+            let t = (cos_theta - cos_outer) / (cos_inner - cos_outer);
+            t * t * (3.0 - 2.0 * t)
+        };
+
+        Some((dir, dist, self.intensity * (falloff / dist_sq)))
+    }
+}
+
+// light from infinitely far away, like the sun; direction is the way the light travels
+#[derive(Clone,Copy,PartialEq,Default,Debug)]
+pub struct DirectionalLight {
+    pub direction: Vector3,
+    pub radiance: Vector3,
+}
+
+impl Light for DirectionalLight {
+    fn sample_li(&self, _pos: Vector3) -> Option<(Vector3, f32, Vector3)> {
+        Some((self.direction.normalize() * -1.0, f32::MAX, self.radiance))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use snmath::Vector3;
+
+    use super::DirectionalLight;
+    use super::Light;
+    use super::PointLight;
+    use super::SpotLight;
+
+    fn v(x: f32, y: f32, z: f32) -> Vector3 {
+        Vector3 {x, y, z}
+    }
+
+    #[test]
+    fn point_lights_fall_off_with_the_square_of_distance() {
+        let light = PointLight {pos: v(0.0, 4.0, 0.0), intensity: v(8.0, 4.0, 2.0)};
+        let (dir, dist, near) = light.sample_li(v(0.0, 2.0, 0.0)).unwrap();
+        assert_eq!(dir, v(0.0, 1.0, 0.0));
+        assert_eq!(dist, 2.0);
+        assert_eq!(near, v(2.0, 1.0, 0.5));
+        let (_, dist, far) = light.sample_li(v(3.0, 0.0, 0.0)).unwrap();
+        assert!((dist - 5.0).abs() < 1e-6);
+        assert!((far - near * (4.0 / 25.0)).length() < 1e-6);
+        // nothing sensible to say at the light itself
+        assert!(light.sample_li(light.pos).is_none());
+    }
+
+    #[test]
+    fn spot_lights_fade_out_towards_the_outer_angle() {
+        let light = SpotLight {pos: v(0.0, 1.0, 0.0), direction: v(0.0, -2.0, 0.0), intensity: v(1.0, 1.0, 1.0), inner_angle: 20.0, outer_angle: 40.0};
+        // a unit away in every direction from the light, at an angle off the spot's axis
+        let at = |degrees: f32| {
+            let (sin, cos) = degrees.to_radians().sin_cos();
+            light.sample_li(v(sin, 1.0 - cos, 0.0)).map_or(0.0, |(_, _, li)| li.x)
+        };
+        assert!((at(0.0) - 1.0).abs() < 1e-5);
+        assert!((at(19.0) - 1.0).abs() < 1e-5);
+        let fading: Vec<f32> = [22.0, 28.0, 34.0, 39.0].iter().map(|&a| at(a)).collect();
+        assert!(fading.windows(2).all(|w| w[0] > w[1]), "{:?}", fading);
+        assert!(fading[0] < 1.0 && fading[3] > 0.0);
+        // halfway between the cosines is halfway along the smoothstep
+        let half = ((20f32.to_radians().cos() + 40f32.to_radians().cos()) / 2.0).acos().to_degrees();
+        assert!((at(half) - 0.5).abs() < 1e-3);
+        assert!(at(40.0) < 1e-6);
+        assert_eq!(at(60.0), 0.0);
+        assert_eq!(at(180.0), 0.0);
+    }
+
+    #[test]
+    fn directional_lights_are_the_same_everywhere() {
+        let light = DirectionalLight {direction: v(0.0, -3.0, 0.0), radiance: v(2.0, 2.0, 2.0)};
+        for &pos in &[v(0.0, 0.0, 0.0), v(100.0, -50.0, 3.0)] {
+            let (dir, dist, li) = light.sample_li(pos).unwrap();
+            assert_eq!(dir, v(0.0, 1.0, 0.0));
+            assert_eq!(dist, f32::MAX);
+            assert_eq!(li, v(2.0, 2.0, 2.0));
+        }
+    }
+}
@@ -1,40 +1,125 @@
+use std::f32;
+use std::sync::Arc;
+
 use snmath::Ray;
 use snmath::Vector3;
 
-extern crate rand;
-use rand::{thread_rng, Rng};
+use snrt::HitRecord;
+use snrt::microfacet::Ggx;
+use snrt::microfacet::fresnel_conductor;
+use snrt::microfacet::fresnel_dielectric;
+use snrt::microfacet::reflect;
+use snrt::microfacet::refract;
+use snrt::sampler::Sampler;
+use snrt::texture::Texture;
+use snrt::texture::scalar;
+use snrt::texture::gray;
+
 
 
 pub trait Material {
-    fn scatter(&self, r_in: &Ray, pos: Vector3, normal: Vector3) -> (bool, Ray, Vector3);
+    fn scatter(&self, r_in: &Ray, hit: &HitRecord, sampler: &mut dyn Sampler) -> (bool, Ray, Vector3);
+
+    // radiance given off towards the ray that hit us
+    fn emitted(&self, _r_in: &Ray, _hit: &HitRecord) -> Vector3 {
+        Vector3::default()
+    }
+
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    // surfaces that only mark where a medium starts, light goes straight through them
+    fn is_invisible(&self) -> bool {
+        false
+    }
+
+    // brdf for light arriving from dir_light, used when sampling lights directly
+    // perfectly specular materials have nothing to give here
+    fn eval(&self, _r_in: &Ray, _hit: &HitRecord, _dir_light: Vector3) -> Vector3 {
+        Vector3::default()
+    }
+
+    // solid angle pdf of scatter picking dir, 0 for specular materials that can't be evaluated
+    fn pdf(&self, _r_in: &Ray, _hit: &HitRecord, _dir: Vector3) -> f32 {
+        0.0
+    }
 }
 
-#[derive(Clone,Copy,PartialEq,Default,Debug)]
 pub struct Lambertian {
-    pub albedo: Vector3,
+    pub albedo: Arc<dyn Texture + Sync + Send>,
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _r_in: &Ray, pos: Vector3, normal: Vector3) -> (bool, Ray, Vector3) {
-        let target = pos + normal + Vector3::generate_random_unit_vector();
-        let scattered = Ray{origin:pos, direction:target-pos};
-        let attenuation = self.albedo;
+    fn scatter(&self, _r_in: &Ray, hit: &HitRecord, sampler: &mut dyn Sampler) -> (bool, Ray, Vector3) {
+        // project a point on the unit disc up onto the hemisphere for a cosine distribution
+        let d = Vector3::sample_unit_disc(sampler.next_2d());
+        let z = (1.0 - d.x*d.x - d.y*d.y).max(0.0).sqrt();
+        let direction = hit.to_world(Vector3 {x:d.x, y:d.y, z});
+        let scattered = Ray{origin:hit.pos, direction};
+        let attenuation = self.albedo.value(hit.uv, hit.pos);
         (true,scattered,attenuation)
     }
+
+    fn eval(&self, _r_in: &Ray, hit: &HitRecord, dir_light: Vector3) -> Vector3 {
+        if hit.facing_normal().dot(&dir_light) <= 0.0 {
+            return Vector3::default();
+        }
+        self.albedo.value(hit.uv, hit.pos) / f32::consts::PI
+    }
+
+    fn pdf(&self, _r_in: &Ray, hit: &HitRecord, dir: Vector3) -> f32 {
+        hit.facing_normal().dot(&dir.normalize()).max(0.0) / f32::consts::PI
+    }
 }
 
-#[derive(Clone,Copy,PartialEq,Default,Debug)]
+// a tinted mirror blurred by ggx microfacets, alpha is half the roughness so it blurs
+// about as much as the old fuzz did. being glossy rather than a perfect mirror
+// it has an eval, so point, spot and directional lights show up in it
 pub struct Metallic {
-    pub albedo: Vector3,
-    pub roughness: f32,
+    pub albedo: Arc<dyn Texture + Sync + Send>,
+    pub roughness: Arc<dyn Texture + Sync + Send>,
+}
+
+impl Metallic {
+    fn distribution(&self, hit: &HitRecord) -> Ggx {
+        Ggx::isotropic(0.5 * scalar(self.roughness.as_ref(), hit.uv, hit.pos))
+    }
 }
 
 impl Material for Metallic {
-    fn scatter(&self, r_in: &Ray, pos: Vector3, normal: Vector3) -> (bool, Ray, Vector3) {
-        let reflected_vec = r_in.direction.normalize().reflect_on(&normal);
-        let scattered_ray = Ray{origin:pos, direction:reflected_vec + self.roughness*Vector3::generate_random_unit_vector()};
-        let attenuation = self.albedo;
-        (normal.dot(&scattered_ray.direction) > 0.0, scattered_ray, attenuation)
+    fn scatter(&self, r_in: &Ray, hit: &HitRecord, sampler: &mut dyn Sampler) -> (bool, Ray, Vector3) {
+        let wo = hit.to_local(r_in.direction.normalize() * -1.0);
+        let ggx = self.distribution(hit);
+        let m = ggx.sample_visible(&wo, sampler.next_2d());
+        let wi = reflect(&wo, &m);
+        let scattered = Ray {origin:hit.pos, direction:hit.to_world(wi)};
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return (false, scattered, Vector3::default());
+        }
+        let attenuation = self.albedo.value(hit.uv, hit.pos) * (ggx.g2(&wo, &wi) / ggx.g1(&wo));
+        (true, scattered, attenuation)
+    }
+
+    fn eval(&self, r_in: &Ray, hit: &HitRecord, dir_light: Vector3) -> Vector3 {
+        let wo = hit.to_local(r_in.direction.normalize() * -1.0);
+        let wi = hit.to_local(dir_light.normalize());
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Vector3::default();
+        }
+        let ggx = self.distribution(hit);
+        let m = (wo + wi).normalize();
+        self.albedo.value(hit.uv, hit.pos) * (ggx.d(&m) * ggx.g2(&wo, &wi) / (4.0 * wo.z * wi.z))
+    }
+
+    fn pdf(&self, r_in: &Ray, hit: &HitRecord, dir: Vector3) -> f32 {
+        let wo = hit.to_local(r_in.direction.normalize() * -1.0);
+        let wi = hit.to_local(dir.normalize());
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        let m = (wo + wi).normalize();
+        self.distribution(hit).pdf_visible(&wo, &m) / (4.0 * wo.dot(&m))
     }
 }
 
@@ -48,15 +133,17 @@ impl Dielectric {
     fn schlick(cosine: f32, ior: f32) -> f32 {
         let r0 = (1.0-ior) / (1.0+ior);
         let r0 = r0 * r0;
-        return r0 + (1.0-r0)*(1.0-cosine).powi(5);
+        r0 + (1.0-r0)*(1.0-cosine).powi(5)
     }
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, r_in: &Ray, pos: Vector3, normal: Vector3) -> (bool, Ray, Vector3) {
+    fn scatter(&self, r_in: &Ray, hit: &HitRecord, sampler: &mut dyn Sampler) -> (bool, Ray, Vector3) {
         let attenuation = Vector3 {x: 1.0, y: 1.0, z: 1.0};
+        let pos = hit.pos;
+        let normal = hit.normal;
         let reflected = r_in.direction.reflect_on(&normal);
-        let facing = r_in.direction.dot(&normal) > 0.0;
+        let facing = !hit.front_face;
 
         let ni_over_nt = if facing { self.ior } else { 1.0 / self.ior };
         let outward_normal = if facing { normal * -1.0 } else { normal };
@@ -74,9 +161,603 @@ impl Material for Dielectric {
             1.0
         };
 
-        if thread_rng().gen_range::<f32>(0.0, 1.0) < reflect_probability {
+        if sampler.next_1d() < reflect_probability {
             return (true, Ray {origin:pos, direction:reflected}, attenuation);
         }
-        return (true, Ray {origin:pos, direction:refraction.expect("some kind of dielectric probability error")}, attenuation);
+        (true, Ray {origin:pos, direction:refraction.expect("some kind of dielectric probability error")}, attenuation)
+    }
+}
+
+
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum Metal {
+    Gold,
+    Silver,
+    Copper,
+    Aluminum,
+    Iron,
+}
+
+pub const METAL_NAMES: &str = "gold, silver, copper, aluminum or iron";
+
+impl Metal {
+    pub fn from_name(name: &str) -> Option<Metal> {
+        match name.to_ascii_lowercase().as_str() {
+            "gold" => Some(Metal::Gold),
+            "silver" => Some(Metal::Silver),
+            "copper" => Some(Metal::Copper),
+            "aluminum" | "aluminium" => Some(Metal::Aluminum),
+            "iron" => Some(Metal::Iron),
+            _ => None,
+        }
+    }
+
+    // measured eta and k averaged over the red, green and blue parts of the spectrum
+    pub fn eta_k(&self) -> (Vector3, Vector3) {
+        match *self {
+            Metal::Gold => (Vector3 {x:0.143, y:0.374, z:1.442}, Vector3 {x:3.983, y:2.385, z:1.603}),
+            Metal::Silver => (Vector3 {x:0.155, y:0.117, z:0.138}, Vector3 {x:4.828, y:3.122, z:2.147}),
+            Metal::Copper => (Vector3 {x:0.200, y:0.924, z:1.102}, Vector3 {x:3.912, y:2.452, z:2.142}),
+            Metal::Aluminum => (Vector3 {x:1.657, y:0.880, z:0.521}, Vector3 {x:9.224, y:6.270, z:4.837}),
+            Metal::Iron => (Vector3 {x:2.911, y:2.950, z:2.585}, Vector3 {x:3.089, y:2.932, z:2.767}),
+        }
+    }
+}
+
+// a metal with ggx microfacets, its color comes from the fresnel term of its complex ior
+pub struct Conductor {
+    pub eta: Vector3,
+    pub k: Vector3,
+    pub roughness: Arc<dyn Texture + Sync + Send>,
+}
+
+impl Conductor {
+    pub fn from_metal(metal: Metal, roughness: Arc<dyn Texture + Sync + Send>) -> Conductor {
+        let (eta, k) = metal.eta_k();
+        Conductor {eta, k, roughness}
+    }
+
+    fn distribution(&self, hit: &HitRecord) -> Ggx {
+        Ggx::from_roughness(scalar(self.roughness.as_ref(), hit.uv, hit.pos))
+    }
+}
+
+impl Material for Conductor {
+    fn scatter(&self, r_in: &Ray, hit: &HitRecord, sampler: &mut dyn Sampler) -> (bool, Ray, Vector3) {
+        let wo = hit.to_local(r_in.direction.normalize() * -1.0);
+        let ggx = self.distribution(hit);
+        let m = ggx.sample_visible(&wo, sampler.next_2d());
+        let wi = reflect(&wo, &m);
+        let scattered = Ray {origin:hit.pos, direction:hit.to_world(wi)};
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return (false, scattered, Vector3::default());
+        }
+        // d and one of the g1s cancel against the visible normal pdf
+        let attenuation = fresnel_conductor(wo.dot(&m), &self.eta, &self.k) * (ggx.g2(&wo, &wi) / ggx.g1(&wo));
+        (true, scattered, attenuation)
+    }
+
+    fn eval(&self, r_in: &Ray, hit: &HitRecord, dir_light: Vector3) -> Vector3 {
+        let wo = hit.to_local(r_in.direction.normalize() * -1.0);
+        let wi = hit.to_local(dir_light.normalize());
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Vector3::default();
+        }
+        let ggx = self.distribution(hit);
+        let m = (wo + wi).normalize();
+        fresnel_conductor(wo.dot(&m), &self.eta, &self.k) * (ggx.d(&m) * ggx.g2(&wo, &wi) / (4.0 * wo.z * wi.z))
+    }
+
+    fn pdf(&self, r_in: &Ray, hit: &HitRecord, dir: Vector3) -> f32 {
+        let wo = hit.to_local(r_in.direction.normalize() * -1.0);
+        let wi = hit.to_local(dir.normalize());
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        let m = (wo + wi).normalize();
+        self.distribution(hit).pdf_visible(&wo, &m) / (4.0 * wo.dot(&m))
+    }
+}
+
+// frosted glass, ggx microfacets that either reflect or refract by their fresnel term
+pub struct RoughDielectric {
+    pub ior: f32,
+    pub roughness: Arc<dyn Texture + Sync + Send>,
+}
+
+impl RoughDielectric {
+    fn distribution(&self, hit: &HitRecord) -> Ggx {
+        Ggx::from_roughness(scalar(self.roughness.as_ref(), hit.uv, hit.pos))
+    }
+
+    // how much denser the far side is than the one the ray came from
+    fn eta(&self, hit: &HitRecord) -> f32 {
+        if hit.front_face { self.ior } else { 1.0 / self.ior }
+    }
+}
+
+// eval and pdf only cover reflection, light sampling never looks through the surface and
+// transmitted bounces are treated as specular so they still pick up the lights they find
+impl Material for RoughDielectric {
+    fn scatter(&self, r_in: &Ray, hit: &HitRecord, sampler: &mut dyn Sampler) -> (bool, Ray, Vector3) {
+        let wo = hit.to_local(r_in.direction.normalize() * -1.0);
+        let ggx = self.distribution(hit);
+        let m = ggx.sample_visible(&wo, sampler.next_2d());
+        let eta = self.eta(hit);
+
+        // picking by fresnel cancels it out of the weight
+        let refracted = if sampler.next_1d() < fresnel_dielectric(wo.dot(&m), eta) { None } else { refract(&wo, &m, eta) };
+        let wi = match refracted {
+            Some(wt) => wt,
+            None => reflect(&wo, &m),
+        };
+        let scattered = Ray {origin:hit.pos, direction:hit.to_world(wi)};
+        let on_right_side = if refracted.is_some() { wi.z < 0.0 } else { wi.z > 0.0 };
+        if wo.z <= 0.0 || !on_right_side {
+            return (false, scattered, Vector3::default());
+        }
+        let weight = ggx.g2(&wo, &wi) / ggx.g1(&wo);
+        (true, scattered, Vector3 {x:weight, y:weight, z:weight})
+    }
+
+    fn eval(&self, r_in: &Ray, hit: &HitRecord, dir_light: Vector3) -> Vector3 {
+        let wo = hit.to_local(r_in.direction.normalize() * -1.0);
+        let wi = hit.to_local(dir_light.normalize());
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Vector3::default();
+        }
+        let ggx = self.distribution(hit);
+        let m = (wo + wi).normalize();
+        let f = fresnel_dielectric(wo.dot(&m), self.eta(hit)) * ggx.d(&m) * ggx.g2(&wo, &wi) / (4.0 * wo.z * wi.z);
+        Vector3 {x:f, y:f, z:f}
+    }
+
+    fn pdf(&self, r_in: &Ray, hit: &HitRecord, dir: Vector3) -> f32 {
+        let wo = hit.to_local(r_in.direction.normalize() * -1.0);
+        let wi = hit.to_local(dir.normalize());
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        let m = (wo + wi).normalize();
+        fresnel_dielectric(wo.dot(&m), self.eta(hit)) * self.distribution(hit).pdf_visible(&wo, &m) / (4.0 * wo.dot(&m))
+    }
+}
+
+
+fn luminance(c: &Vector3) -> f32 {
+    0.2126*c.x + 0.7152*c.y + 0.0722*c.z
+}
+
+fn schlick_weight(cosine: f32) -> f32 {
+    (1.0 - cosine.clamp(0.0, 1.0)).powi(5)
+}
+
+// disney's principled bsdf (burley 2012 and 2015): a diffuse base with sheen, a ggx specular lobe
+// that goes from dielectric to metal, a clearcoat on top and rough transmission through the base.
+// every parameter is a texture and between 0 and 1
+pub struct Principled {
+    pub base_color: Arc<dyn Texture + Sync + Send>,
+    pub metallic: Arc<dyn Texture + Sync + Send>,
+    pub roughness: Arc<dyn Texture + Sync + Send>,
+    // 0.5 is the 4% reflectance of most dielectrics, it also sets the ior for transmission
+    pub specular: Arc<dyn Texture + Sync + Send>,
+    // how far the dielectric highlight and the sheen are tinted towards the base color
+    pub specular_tint: Arc<dyn Texture + Sync + Send>,
+    pub sheen: Arc<dyn Texture + Sync + Send>,
+    pub sheen_tint: Arc<dyn Texture + Sync + Send>,
+    pub clearcoat: Arc<dyn Texture + Sync + Send>,
+    pub clearcoat_gloss: Arc<dyn Texture + Sync + Send>,
+    pub transmission: Arc<dyn Texture + Sync + Send>,
+    pub anisotropy: Arc<dyn Texture + Sync + Send>,
+}
+
+impl Default for Principled {
+    fn default() -> Principled {
+        Principled {
+            base_color: gray(0.8),
+            metallic: gray(0.0),
+            roughness: gray(0.5),
+            specular: gray(0.5),
+            specular_tint: gray(0.0),
+            sheen: gray(0.0),
+            sheen_tint: gray(0.5),
+            clearcoat: gray(0.0),
+            clearcoat_gloss: gray(1.0),
+            transmission: gray(0.0),
+            anisotropy: gray(0.0),
+        }
+    }
+}
+
+// the parameters looked up at one hit, in the hit's tangent frame
+struct PrincipledPoint {
+    base_color: Vector3,
+    metallic: f32,
+    roughness: f32,
+    specular_color: Vector3,
+    sheen_color: Vector3,
+    clearcoat: f32,
+    transmission: f32,
+    // how much denser the far side is than the one the ray came from
+    eta: f32,
+    specular_lobe: Ggx,
+    clearcoat_lobe: Ggx,
+    // chance of sampling the diffuse, specular, clearcoat and transmission lobes
+    lobe_weights: [f32; 4],
+}
+
+impl PrincipledPoint {
+    fn diffuse_weight(&self) -> f32 {
+        (1.0 - self.metallic) * (1.0 - self.transmission)
+    }
+
+    // everything but transmission, both directions above the surface
+    fn eval(&self, wo: &Vector3, wi: &Vector3) -> Vector3 {
+        let h = (*wo + *wi).normalize();
+        let cos_d = wi.dot(&h);
+        let mut f = Vector3::default();
+
+        if self.diffuse_weight() > 0.0 {
+            // rough surfaces get brighter towards grazing, smooth ones darker
+            let fd90 = 0.5 + 2.0 * cos_d*cos_d * self.roughness;
+            let retro = (1.0 + (fd90 - 1.0) * schlick_weight(wi.z)) * (1.0 + (fd90 - 1.0) * schlick_weight(wo.z));
+            let diffuse = self.base_color * (retro / f32::consts::PI);
+            let sheen = self.sheen_color * schlick_weight(cos_d);
+            f = f + (diffuse + sheen) * self.diffuse_weight();
+        }
+
+        let white = Vector3 {x:1.0, y:1.0, z:1.0};
+        let metal_fresnel = self.base_color + (white - self.base_color) * schlick_weight(cos_d);
+        let fresnel = Vector3::lerp(&(self.specular_color * fresnel_dielectric(cos_d, self.eta)), &metal_fresnel, self.metallic);
+        let ggx = &self.specular_lobe;
+        f = f + fresnel * (ggx.d(&h) * ggx.g2(wo, wi) / (4.0 * wo.z * wi.z));
+
+        if self.clearcoat > 0.0 {
+            let coat = &self.clearcoat_lobe;
+            let coat_fresnel = 0.04 + 0.96 * schlick_weight(cos_d);
+            let c = 0.25 * self.clearcoat * coat_fresnel * coat.d(&h) * coat.g2(wo, wi) / (4.0 * wo.z * wi.z);
+            f = f + Vector3 {x:c, y:c, z:c};
+        }
+        f
+    }
+
+    fn pdf(&self, wo: &Vector3, wi: &Vector3) -> f32 {
+        let h = (*wo + *wi).normalize();
+        let reflection_jacobian = 4.0 * wo.dot(&h);
+        if reflection_jacobian <= 0.0 {
+            return 0.0;
+        }
+        self.lobe_weights[0] * wi.z / f32::consts::PI
+            + self.lobe_weights[1] * self.specular_lobe.pdf_visible(wo, &h) / reflection_jacobian
+            + self.lobe_weights[2] * self.clearcoat_lobe.pdf_visible(wo, &h) / reflection_jacobian
+    }
+}
+
+impl Principled {
+    fn at(&self, hit: &HitRecord) -> PrincipledPoint {
+        let value = |t: &Arc<dyn Texture + Sync + Send>| scalar(t.as_ref(), hit.uv, hit.pos).clamp(0.0, 1.0);
+        let base_color = self.base_color.value(hit.uv, hit.pos);
+        let (metallic, roughness, transmission, clearcoat) = (value(&self.metallic), value(&self.roughness), value(&self.transmission), value(&self.clearcoat));
+
+        // the hue of the base color without its brightness
+        let white = Vector3 {x:1.0, y:1.0, z:1.0};
+        let tint = if luminance(&base_color) > 0.0 { base_color / luminance(&base_color) } else { white };
+
+        // specular sets the reflectance at normal incidence, 0.08 at most, and from that the ior
+        let f0 = (0.08 * value(&self.specular)).sqrt();
+        let ior = ((1.0 + f0) / (1.0 - f0)).max(1.0);
+
+        let diffuse = (1.0 - metallic) * (1.0 - transmission);
+        let weights = [diffuse, 1.0, 0.25 * clearcoat, (1.0 - metallic) * transmission];
+        let total: f32 = weights.iter().sum();
+
+        PrincipledPoint {
+            base_color,
+            metallic,
+            roughness,
+            specular_color: Vector3::lerp(&white, &tint, value(&self.specular_tint)),
+            sheen_color: Vector3::lerp(&white, &tint, value(&self.sheen_tint)) * value(&self.sheen),
+            clearcoat,
+            transmission,
+            eta: if hit.front_face { ior } else { 1.0 / ior },
+            specular_lobe: Ggx::anisotropic(roughness, value(&self.anisotropy)),
+            // disney uses gtr1 here, ggx has a shorter tail but can be sampled the same way as the rest
+            clearcoat_lobe: Ggx::isotropic(0.1 + (0.001 - 0.1) * value(&self.clearcoat_gloss)),
+            lobe_weights: [weights[0] / total, weights[1] / total, weights[2] / total, weights[3] / total],
+        }
+    }
+}
+
+// as with RoughDielectric, eval and pdf leave out transmission
+impl Material for Principled {
+    fn scatter(&self, r_in: &Ray, hit: &HitRecord, sampler: &mut dyn Sampler) -> (bool, Ray, Vector3) {
+        let wo = hit.to_local(r_in.direction.normalize() * -1.0);
+        let p = self.at(hit);
+        let absorbed = (false, Ray {origin:hit.pos, direction:r_in.direction}, Vector3::default());
+        if wo.z <= 0.0 {
+            return absorbed;
+        }
+
+        let pick = sampler.next_1d();
+        let u = sampler.next_2d();
+        let w = p.lobe_weights;
+        let wi = if pick < w[0] {
+            let d = Vector3::sample_unit_disc(u);
+            Vector3 {x:d.x, y:d.y, z:(1.0 - d.x*d.x - d.y*d.y).max(0.0).sqrt()}
+        } else if pick < w[0] + w[1] {
+            reflect(&wo, &p.specular_lobe.sample_visible(&wo, u))
+        } else if pick < w[0] + w[1] + w[2] {
+            reflect(&wo, &p.clearcoat_lobe.sample_visible(&wo, u))
+        } else {
+            // only this lobe goes through, so its weight doesn't need the others' pdfs
+            let m = p.specular_lobe.sample_visible(&wo, u);
+            let wt = match refract(&wo, &m, p.eta) {
+                Some(wt) if wt.z < 0.0 => wt,
+                _ => return absorbed,
+            };
+            let through = (1.0 - fresnel_dielectric(wo.dot(&m), p.eta)) * p.specular_lobe.g2(&wo, &wt) / p.specular_lobe.g1(&wo);
+            let attenuation = p.base_color * (through * (1.0 - p.metallic) * p.transmission / w[3]);
+            return (true, Ray {origin:hit.pos, direction:hit.to_world(wt)}, attenuation);
+        };
+
+        if wi.z <= 0.0 {
+            return absorbed;
+        }
+        let pdf = p.pdf(&wo, &wi);
+        if pdf <= 0.0 {
+            return absorbed;
+        }
+        (true, Ray {origin:hit.pos, direction:hit.to_world(wi)}, p.eval(&wo, &wi) * (wi.z / pdf))
+    }
+
+    fn eval(&self, r_in: &Ray, hit: &HitRecord, dir_light: Vector3) -> Vector3 {
+        let wo = hit.to_local(r_in.direction.normalize() * -1.0);
+        let wi = hit.to_local(dir_light.normalize());
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Vector3::default();
+        }
+        self.at(hit).eval(&wo, &wi)
+    }
+
+    fn pdf(&self, r_in: &Ray, hit: &HitRecord, dir: Vector3) -> f32 {
+        let wo = hit.to_local(r_in.direction.normalize() * -1.0);
+        let wi = hit.to_local(dir.normalize());
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        self.at(hit).pdf(&wo, &wi)
+    }
+}
+
+
+// the boundary of a medium with nothing else there, the integrator steps over it
+#[derive(Clone,Copy,PartialEq,Default,Debug)]
+pub struct Invisible;
+
+impl Material for Invisible {
+    fn scatter(&self, r_in: &Ray, hit: &HitRecord, _sampler: &mut dyn Sampler) -> (bool, Ray, Vector3) {
+        (true, Ray {origin:hit.pos, direction:r_in.direction}, Vector3 {x:1.0, y:1.0, z:1.0})
+    }
+
+    fn is_invisible(&self) -> bool {
+        true
+    }
+}
+
+// an area light, anything it's attached to glows on its front side
+pub struct DiffuseLight {
+    pub emit: Arc<dyn Texture + Sync + Send>,
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, r_in: &Ray, hit: &HitRecord, _sampler: &mut dyn Sampler) -> (bool, Ray, Vector3) {
+        (false, Ray {origin:hit.pos, direction:r_in.direction}, Vector3::default())
+    }
+
+    fn emitted(&self, _r_in: &Ray, hit: &HitRecord) -> Vector3 {
+        if hit.front_face {
+            return self.emit.value(hit.uv, hit.pos);
+        }
+        Vector3::default()
+    }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32;
+
+    use snmath::Ray;
+    use snmath::Vector3;
+
+    use snrt::HitRecord;
+    use snrt::sampler::Sampler;
+    use snrt::sampler::SamplerKind;
+    use snrt::texture::solid;
+    use snrt::texture::gray;
+    use super::Conductor;
+    use super::Material;
+    use super::Metal;
+    use super::Metallic;
+    use super::Principled;
+    use super::RoughDielectric;
+
+    fn v(x: f32, y: f32, z: f32) -> Vector3 {
+        Vector3 {x, y, z}
+    }
+
+    fn sampler(seed: u64) -> Box<dyn Sampler> {
+        let mut sampler = SamplerKind::Independent.create(seed, 1);
+        sampler.start_sample(0, 0, 0);
+        sampler
+    }
+
+    // a ray arriving at the origin of the z=0 plane from the unit direction wo
+    fn hit_from(material: &(dyn Material + Sync), wo: Vector3) -> (Ray, HitRecord<'_>) {
+        let r = Ray {origin: wo, direction: wo * -1.0};
+        let up = v(0.0, 0.0, 1.0);
+        let hit = HitRecord::new(&r, 1.0, up, up, (0.5, 0.5), v(1.0, 0.0, 0.0), material);
+        (r, hit)
+    }
+
+    // every sampled direction must be weighted by exactly eval * cos / pdf, and the pdf has to
+    // integrate to how often scatter returns a direction at all. transmission isn't covered by
+    // eval and pdf, so only reflected directions count
+    fn check_sampling(material: &(dyn Material + Sync), wo: Vector3) {
+        let (r, hit) = hit_from(material, wo);
+        let mut s = sampler(3);
+
+        let count = 20000;
+        let mut scattered = 0;
+        for _ in 0..count {
+            let (ok, ray, attenuation) = material.scatter(&r, &hit, s.as_mut());
+            if !ok || ray.direction.z <= 0.0 {
+                continue;
+            }
+            scattered += 1;
+            let wi = ray.direction.normalize();
+            let pdf = material.pdf(&r, &hit, wi);
+            assert!(pdf > 0.0, "sampled a direction with no pdf");
+            let expected = material.eval(&r, &hit, wi) * (wi.z / pdf);
+            assert!((attenuation - expected).length() <= 1e-3 * (1.0 + expected.length()), "{:?} vs {:?}", attenuation, expected);
+        }
+
+        // uniform directions over the sphere to integrate the pdf
+        let total = 400000;
+        let mut integral = 0.0;
+        for _ in 0..total {
+            let wi = Vector3::sample_unit_sphere(s.next_2d());
+            integral += material.pdf(&r, &hit, wi) as f64;
+        }
+        let integral = integral * 4.0 * f64::from(f32::consts::PI) / total as f64;
+        let fraction = scattered as f64 / count as f64;
+        assert!((integral - fraction).abs() < 0.03, "pdf integrates to {} but {} of samples scatter", integral, fraction);
+    }
+
+    // albedo from sampling against albedo from integrating eval, for a white material both are at most 1
+    fn albedos(material: &(dyn Material + Sync), wo: Vector3) -> (Vector3, Vector3) {
+        let (r, hit) = hit_from(material, wo);
+        let mut s = sampler(5);
+        let count = 100000;
+        let mut sampled = Vector3::default();
+        for _ in 0..count {
+            let (ok, _, attenuation) = material.scatter(&r, &hit, s.as_mut());
+            if ok {
+                sampled = sampled + attenuation;
+            }
+        }
+        // a grid in cos theta and phi, random directions are too noisy for the narrow lobes
+        let (n_cos, n_phi) = (600, 300);
+        let mut integrated = Vector3::default();
+        for i in 0..n_cos {
+            let cos_theta = (i as f32 + 0.5) / n_cos as f32;
+            let sin_theta = (1.0 - cos_theta*cos_theta).sqrt();
+            let mut row = Vector3::default();
+            for j in 0..n_phi {
+                let phi = 2.0 * f32::consts::PI * (j as f32 + 0.5) / n_phi as f32;
+                let wi = v(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+                row = row + material.eval(&r, &hit, wi) * cos_theta;
+            }
+            integrated = integrated + row;
+        }
+        (sampled / count as f32, integrated * (2.0 * f32::consts::PI / (n_cos * n_phi) as f32))
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn metallic_sampling_matches_eval_and_pdf() {
+        for &roughness in &[0.2, 0.6] {
+            let metal = Metallic {albedo: solid(v(0.9, 0.6, 0.3)), roughness: gray(roughness)};
+            for wo in &[v(0.0, 0.0, 1.0), v(0.6, 0.0, 0.8), v(0.0, 0.95, 0.312)] {
+                check_sampling(&metal, wo.normalize());
+                let (sampled, integrated) = albedos(&metal, wo.normalize());
+                assert!((sampled - integrated).length() < 0.02, "{:?} vs {:?}", sampled, integrated);
+                assert!(sampled.x <= 0.9 + 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn metallic_lights_up_from_a_delta_light() {
+        let metal = Metallic {albedo: gray(0.8), roughness: gray(0.3)};
+        let (r, hit) = hit_from(&metal, v(0.6, 0.0, 0.8));
+        assert!(metal.eval(&r, &hit, v(-0.6, 0.0, 0.8)).x > 0.0);
+        assert_eq!(metal.eval(&r, &hit, v(-0.6, 0.0, -0.8)), Vector3::default());
+    }
+
+    #[test]
+    fn conductor_sampling_matches_eval_and_pdf() {
+        for &roughness in &[0.3, 0.6, 0.9] {
+            let metal = Conductor::from_metal(Metal::Gold, gray(roughness));
+            for wo in &[v(0.0, 0.0, 1.0), v(0.6, 0.0, 0.8), v(0.0, 0.95, 0.312)] {
+                check_sampling(&metal, wo.normalize());
+                let (sampled, integrated) = albedos(&metal, wo.normalize());
+                assert!((sampled - integrated).length() < 0.02, "{} {:?}: {:?} vs {:?}", roughness, wo, sampled, integrated);
+                assert!(sampled.x <= 1.0 && sampled.z < sampled.x, "gold reflects red over blue: {:?}", sampled);
+            }
+        }
+    }
+
+    #[test]
+    fn rough_dielectric_reflection_matches_eval_and_pdf() {
+        for &roughness in &[0.2, 0.6] {
+            let glass = RoughDielectric {ior: 1.5, roughness: gray(roughness)};
+            for wo in &[v(0.0, 0.0, 1.0), v(0.6, 0.0, 0.8), v(0.0, 0.95, 0.312)] {
+                check_sampling(&glass, wo.normalize());
+            }
+        }
+    }
+
+    // glass neither makes light nor, past the little shadowing takes, loses it
+    #[test]
+    fn rough_dielectric_keeps_most_of_the_light() {
+        let glass = RoughDielectric {ior: 1.5, roughness: gray(0.3)};
+        let (r, hit) = hit_from(&glass, v(0.6, 0.0, 0.8));
+        let mut s = sampler(7);
+        let count = 100000;
+        let (mut reflected, mut transmitted) = (0.0, 0.0);
+        for _ in 0..count {
+            let (ok, ray, attenuation) = glass.scatter(&r, &hit, s.as_mut());
+            if !ok {
+                continue;
+            }
+            assert!(attenuation.x <= 1.0 + 1e-4);
+            if ray.direction.z > 0.0 { reflected += attenuation.x } else { transmitted += attenuation.x }
+        }
+        let (reflected, transmitted) = (reflected / count as f32, transmitted / count as f32);
+        assert!(reflected + transmitted > 0.95 && reflected + transmitted <= 1.0, "{} + {}", reflected, transmitted);
+        assert!(reflected > 0.03 && reflected < 0.1, "{}", reflected);
+    }
+
+    fn principled_variants() -> Vec<Principled> {
+        vec![
+            Principled::default(),
+            Principled {base_color: solid(v(0.9, 0.5, 0.2)), metallic: gray(1.0), roughness: gray(0.4), ..Principled::default()},
+            Principled {sheen: gray(1.0), clearcoat: gray(1.0), clearcoat_gloss: gray(0.5), roughness: gray(0.7), ..Principled::default()},
+            Principled {metallic: gray(0.5), anisotropy: gray(0.8), specular_tint: gray(1.0), ..Principled::default()},
+            Principled {transmission: gray(0.7), roughness: gray(0.3), ..Principled::default()},
+        ]
+    }
+
+    #[test]
+    fn principled_sampling_matches_eval_and_pdf() {
+        for material in principled_variants() {
+            for wo in &[v(0.0, 0.0, 1.0), v(0.6, 0.0, 0.8), v(0.0, 0.95, 0.312)] {
+                check_sampling(&material, wo.normalize());
+            }
+        }
+    }
+
+    // transmission is left out of eval, so only the variants that don't have any
+    #[test]
+    fn principled_albedo_agrees_with_its_eval() {
+        for material in principled_variants().into_iter().take(4) {
+            for wo in &[v(0.0, 0.0, 1.0), v(0.6, 0.0, 0.8), v(0.0, 0.95, 0.312)] {
+                let (sampled, integrated) = albedos(&material, wo.normalize());
+                assert!((sampled - integrated).length() < 0.03, "{:?} vs {:?} from {:?}", sampled, integrated, wo);
+            }
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use std::f32;
+
+use snmath::Vector3;
+use snmath::Ray;
+use snmath::Aabb;
+
+use snrt::Hitable;
+use snrt::HitRecord;
+use snrt::material::Material;
+use snrt::sampler::Sampler;
+
+// henyey-greenstein, g from -1 (back towards where the light came from) to 1 (straight on).
+// cos_theta is between the direction light was travelling and the one it leaves in
+pub fn henyey_greenstein(cos_theta: f32, g: f32) -> f32 {
+    let denom = 1.0 + g*g - 2.0*g*cos_theta;
+    (1.0 - g*g) / (4.0 * f32::consts::PI * denom * denom.sqrt())
+}
+
+// a direction distributed exactly like henyey_greenstein about the unit vector dir
+pub fn sample_henyey_greenstein(dir: &Vector3, g: f32, u: (f32, f32)) -> Vector3 {
+    let cos_theta = if g.abs() < 1e-3 {
+        1.0 - 2.0*u.0
+    } else {
+        let s = (1.0 - g*g) / (1.0 - g + 2.0*g*u.0);
+        (1.0 + g*g - s*s) / (2.0*g)
+    }.clamp(-1.0, 1.0);
+    let sin_theta = (1.0 - cos_theta*cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * f32::consts::PI * u.1;
+    let (t, b) = dir.orthonormal_basis();
+    t * (sin_theta * phi.cos()) + b * (sin_theta * phi.sin()) + *dir * cos_theta
+}
+
+// a participating medium with the same density everywhere. coefficients are per unit of
+// distance and per color channel
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub struct HomogeneousMedium {
+    pub sigma_a: Vector3,
+    pub sigma_s: Vector3,
+    // henyey-greenstein asymmetry
+    pub g: f32,
+}
+
+impl HomogeneousMedium {
+    pub fn validate(&self) -> Result<(), String> {
+        let non_negative = |v: &Vector3| [v.x, v.y, v.z].iter().all(|c| c.is_finite() && *c >= 0.0);
+        if !non_negative(&self.sigma_a) || !non_negative(&self.sigma_s) {
+            return Err("absorption and scattering must be non-negative".to_string());
+        }
+        if !(self.g > -1.0 && self.g < 1.0) {
+            return Err("g must be between -1 and 1".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn sigma_t(&self) -> Vector3 {
+        self.sigma_a + self.sigma_s
+    }
+
+    // the fraction of light that makes it dist through
+    pub fn transmittance(&self, dist: f32) -> Vector3 {
+        let t = self.sigma_t();
+        Vector3 {x:(-t.x * dist).exp(), y:(-t.y * dist).exp(), z:(-t.z * dist).exp()}
+    }
+
+    // where along a segment of length t_max light scatters, with the throughput to carry on with.
+    // none when it makes it to the end. the distance follows one channel's falloff, picked at random,
+    // and the weight is against the pdf averaged over all three so colored media stay unbiased
+    pub fn sample_distance(&self, t_max: f32, u: (f32, f32)) -> (Option<f32>, Vector3) {
+        // with nothing to scatter off light only ever gets dimmed, exactly and without noise
+        if self.sigma_s == Vector3::default() {
+            return (None, self.transmittance(t_max));
+        }
+
+        let sigma_t = self.sigma_t();
+        let channel = ((u.0 * 3.0) as usize).min(2);
+        let density = sigma_t.get(channel);
+        let t = if density > 0.0 { -(1.0 - u.1).ln() / density } else { f32::INFINITY };
+
+        if t < t_max {
+            let tr = self.transmittance(t);
+            let pdf = (sigma_t.x*tr.x + sigma_t.y*tr.y + sigma_t.z*tr.z) / 3.0;
+            if pdf <= 0.0 {
+                return (Some(t), Vector3::default());
+            }
+            (Some(t), tr * self.sigma_s / pdf)
+        } else {
+            let tr = self.transmittance(t_max);
+            let pdf = (tr.x + tr.y + tr.z) / 3.0;
+            if pdf <= 0.0 {
+                return (None, Vector3::default());
+            }
+            (None, tr / pdf)
+        }
+    }
+}
+
+// fills the inside of a closed shape with a medium. the shape's own material still applies at
+// the boundary, give it Invisible for smoke or fog with no surface of its own
+pub struct Volume {
+    pub boundary: Box<dyn Hitable + Sync>,
+    pub medium: HomogeneousMedium,
+}
+
+impl Hitable for Volume {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let mut rec = self.boundary.hit(r, t_min, t_max)?;
+        rec.medium = Some(&self.medium);
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.boundary.bounding_box()
+    }
+
+    fn material(&self) -> &(dyn Material + Sync) {
+        self.boundary.material()
+    }
+
+    fn area(&self) -> f32 {
+        self.boundary.area()
+    }
+
+    fn sample_surface(&self, sampler: &mut dyn Sampler) -> HitRecord<'_> {
+        let mut rec = self.boundary.sample_surface(sampler);
+        rec.medium = Some(&self.medium);
+        rec
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32;
+
+    use snmath::Vector3;
+    use snrt::sampler::Sampler;
+    use snrt::sampler::SamplerKind;
+    use super::HomogeneousMedium;
+    use super::henyey_greenstein;
+    use super::sample_henyey_greenstein;
+
+    fn sampler(seed: u64) -> Box<dyn Sampler> {
+        let mut sampler = SamplerKind::Independent.create(seed, 1);
+        sampler.start_sample(0, 0, 0);
+        sampler
+    }
+
+    #[test]
+    fn henyey_greenstein_integrates_to_one() {
+        for &g in &[-0.7, 0.0, 0.3, 0.9] {
+            // the phase function only depends on cos theta, so integrate over that
+            let steps = 200000;
+            let integral: f64 = (0..steps).map(|i| {
+                let cos_theta = -1.0 + 2.0 * (i as f32 + 0.5) / steps as f32;
+                (henyey_greenstein(cos_theta, g) * 2.0 * f32::consts::PI) as f64 * (2.0 / steps as f64)
+            }).sum();
+            assert!((integral - 1.0).abs() < 1e-3, "g={}: {}", g, integral);
+        }
+    }
+
+    #[test]
+    fn henyey_greenstein_samples_follow_the_phase_function() {
+        let dir = Vector3 {x:0.48, y:0.6, z:0.64};
+        let mut s = sampler(1);
+        for &g in &[-0.5, 0.0, 0.4, 0.8] {
+            let count = 200000;
+            let mut mean_cos = 0.0;
+            let mut bins = [0u32; 8];
+            for _ in 0..count {
+                let wi = sample_henyey_greenstein(&dir, g, s.next_2d());
+                assert!((wi.length() - 1.0).abs() < 1e-4);
+                let cos_theta = dir.dot(&wi);
+                mean_cos += cos_theta;
+                bins[(((cos_theta + 1.0) * 4.0) as usize).min(7)] += 1;
+            }
+            // the mean cosine of henyey-greenstein is g itself
+            assert!((mean_cos / count as f32 - g).abs() < 0.01, "g={}: {}", g, mean_cos / count as f32);
+
+            for (b, &hits) in bins.iter().enumerate() {
+                let steps = 1000;
+                let expected: f32 = (0..steps).map(|i| {
+                    let cos_theta = -1.0 + (b as f32 + (i as f32 + 0.5) / steps as f32) * 0.25;
+                    henyey_greenstein(cos_theta, g) * 2.0 * f32::consts::PI * 0.25 / steps as f32
+                }).sum();
+                assert!((hits as f32 / count as f32 - expected).abs() < 0.005, "g={} bin {}", g, b);
+            }
+        }
+    }
+
+    #[test]
+    fn purely_absorbing_media_are_not_sampled() {
+        let medium = HomogeneousMedium {sigma_a: Vector3 {x:2.0, y:0.8, z:0.1}, sigma_s: Vector3::default(), g: 0.0};
+        let mut s = sampler(2);
+        for _ in 0..100 {
+            let (scattered, weight) = medium.sample_distance(1.5, s.next_2d());
+            assert!(scattered.is_none());
+            assert_eq!(weight, medium.transmittance(1.5));
+        }
+    }
+
+    // what sample_distance gives on average for both outcomes against the closed forms: light
+    // passing t_max is dimmed by the transmittance, and scattering anywhere before it adds up to
+    // sigma_s / sigma_t of what doesn't make it through
+    #[test]
+    fn free_flight_sampling_is_unbiased_in_every_channel() {
+        let media = [
+            HomogeneousMedium {sigma_a: Vector3 {x:0.1, y:0.1, z:0.1}, sigma_s: Vector3 {x:1.0, y:1.0, z:1.0}, g: 0.0},
+            HomogeneousMedium {sigma_a: Vector3 {x:0.5, y:0.0, z:0.2}, sigma_s: Vector3 {x:0.3, y:2.0, z:0.7}, g: 0.0},
+            HomogeneousMedium {sigma_a: Vector3 {x:0.0, y:1.0, z:0.0}, sigma_s: Vector3 {x:0.0, y:0.5, z:3.0}, g: 0.0},
+        ];
+        let t_max = 0.8;
+        let mut s = sampler(3);
+        for medium in &media {
+            let count = 400000;
+            let mut passed = Vector3::default();
+            let mut scattered = Vector3::default();
+            for _ in 0..count {
+                match medium.sample_distance(t_max, s.next_2d()) {
+                    (Some(t), weight) => {
+                        assert!(t >= 0.0 && t < t_max);
+                        scattered = scattered + weight;
+                    },
+                    (None, weight) => passed = passed + weight,
+                }
+            }
+            let tr = medium.transmittance(t_max);
+            let sigma_t = medium.sigma_t();
+            let one = Vector3 {x:1.0, y:1.0, z:1.0};
+            let expected = medium.sigma_s * (one - tr) / sigma_t;
+            for c in 0..3 {
+                let expected = if sigma_t.get(c) > 0.0 { expected.get(c) } else { 0.0 };
+                assert!((passed.get(c) / count as f32 - tr.get(c)).abs() < 0.01, "{:?} channel {}", medium, c);
+                assert!((scattered.get(c) / count as f32 - expected).abs() < 0.01, "{:?} channel {}", medium, c);
+            }
+        }
+    }
+}
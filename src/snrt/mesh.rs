@@ -0,0 +1,293 @@
+use std::sync::Arc;
+
+use snmath::Vector3;
+use snmath::Ray;
+use snmath::Aabb;
+
+use snrt::Hitable;
+use snrt::HitRecord;
+use snrt::bvh::Bvh;
+use snrt::material::Material;
+use snrt::sampler::Sampler;
+
+// vertex buffers are shared by every face; normals and uvs are either empty or parallel to positions
+pub struct MeshData {
+    pub positions: Vec<Vector3>,
+    pub normals: Vec<Vector3>,
+    pub uvs: Vec<(f32, f32)>,
+    pub indices: Vec<[usize; 3]>,
+}
+
+// moller-trumbore, returns t and the barycentrics of the 2nd and 3rd vertex
+fn intersect_triangle(r: &Ray, p0: Vector3, p1: Vector3, p2: Vector3, t_min: f32, t_max: f32) -> Option<(f32, f32, f32)> {
+    let e1 = p1 - p0;
+    let e2 = p2 - p0;
+    let pvec = r.direction.cross(&e2);
+    let det = e1.dot(&pvec);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+
+    let inv_det = 1.0 / det;
+    let tvec = r.origin - p0;
+    let b1 = tvec.dot(&pvec) * inv_det;
+    if !(0.0..=1.0).contains(&b1) {
+        return None;
+    }
+
+    let qvec = tvec.cross(&e1);
+    let b2 = r.direction.dot(&qvec) * inv_det;
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None;
+    }
+
+    let t = e2.dot(&qvec) * inv_det;
+    if t > t_min && t < t_max {
+        return Some((t, b1, b2));
+    }
+
+    None
+}
+
+impl MeshData {
+    pub fn face_count(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn face_positions(&self, face: usize) -> (Vector3, Vector3, Vector3) {
+        let idx = self.indices[face];
+        (self.positions[idx[0]], self.positions[idx[1]], self.positions[idx[2]])
+    }
+
+    pub fn face_bounds(&self, face: usize) -> Aabb {
+        let (p0, p1, p2) = self.face_positions(face);
+        // pad so axis aligned triangles don't end up with a zero thickness box
+        let pad = Vector3 {x:1e-5, y:1e-5, z:1e-5};
+        Aabb {min: p0.component_min(&p1).component_min(&p2) - pad, max: p0.component_max(&p1).component_max(&p2) + pad}
+    }
+
+    pub fn face_area(&self, face: usize) -> f32 {
+        let (p0, p1, p2) = self.face_positions(face);
+        0.5 * (p1 - p0).cross(&(p2 - p0)).length()
+    }
+
+    // uniform barycentrics on a face
+    pub fn sample_face(&self, u: (f32, f32)) -> (f32, f32) {
+        let su = u.0.sqrt();
+        let b1 = su * (1.0 - u.1);
+        (b1, su - b1)
+    }
+
+    pub fn hit_face(&self, face: usize, r: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32, f32)> {
+        let (p0, p1, p2) = self.face_positions(face);
+        intersect_triangle(r, p0, p1, p2, t_min, t_max)
+    }
+
+    // position, geometric normal, shading normal, uv and dp/du at barycentric (b1, b2) on a face
+    pub fn surface_at(&self, face: usize, b1: f32, b2: f32) -> (Vector3, Vector3, Vector3, (f32, f32), Vector3) {
+        let idx = self.indices[face];
+        let b0 = 1.0 - b1 - b2;
+
+        let (p0, p1, p2) = self.face_positions(face);
+        let pos = b0*p0 + b1*p1 + b2*p2;
+        let geometric_normal = (p1 - p0).cross(&(p2 - p0)).normalize();
+
+        let normal = if self.normals.is_empty() {
+            geometric_normal
+        } else {
+            (b0*self.normals[idx[0]] + b1*self.normals[idx[1]] + b2*self.normals[idx[2]]).normalize()
+        };
+
+        let (uv0, uv1, uv2) = if self.uvs.is_empty() {
+            ((0.0, 0.0), (1.0, 0.0), (0.0, 1.0))
+        } else {
+            (self.uvs[idx[0]], self.uvs[idx[1]], self.uvs[idx[2]])
+        };
+        let uv = (b0*uv0.0 + b1*uv1.0 + b2*uv2.0, b0*uv0.1 + b1*uv1.1 + b2*uv2.1);
+
+        // solve for how position changes with u across the face
+        let duv02 = (uv0.0 - uv2.0, uv0.1 - uv2.1);
+        let duv12 = (uv1.0 - uv2.0, uv1.1 - uv2.1);
+        let det = duv02.0*duv12.1 - duv02.1*duv12.0;
+        let dpdu = if det.abs() < 1e-12 {
+            Vector3::default()
+        } else {
+            (duv12.1*(p0 - p2) - duv02.1*(p1 - p2)) / det
+        };
+
+        (pos, geometric_normal, normal, uv, dpdu)
+    }
+
+    pub fn record_at<'a>(&self, r: Option<&Ray>, t: f32, face: usize, b1: f32, b2: f32, material: &'a (dyn Material + Sync)) -> HitRecord<'a> {
+        let (pos, geometric_normal, normal, uv, dpdu) = self.surface_at(face, b1, b2);
+        match r {
+            Some(r) => HitRecord::new(r, t, geometric_normal, normal, uv, dpdu, material),
+            None => HitRecord::on_surface(pos, geometric_normal, normal, uv, dpdu, material),
+        }
+    }
+}
+
+
+pub struct Triangle {
+    pub mesh: Arc<MeshData>,
+    pub face: usize,
+    pub material: Arc<dyn Material + Sync + Send>,
+}
+
+impl Hitable for Triangle {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let (t, b1, b2) = self.mesh.hit_face(self.face, r, t_min, t_max)?;
+        Some(self.mesh.record_at(Some(r), t, self.face, b1, b2, self.material.as_ref()))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.mesh.face_bounds(self.face)
+    }
+
+    fn material(&self) -> &(dyn Material + Sync) {
+        self.material.as_ref()
+    }
+
+    fn area(&self) -> f32 {
+        self.mesh.face_area(self.face)
+    }
+
+    fn sample_surface(&self, sampler: &mut dyn Sampler) -> HitRecord<'_> {
+        let (b1, b2) = self.mesh.sample_face(sampler.next_2d());
+        self.mesh.record_at(None, 0.0, self.face, b1, b2, self.material.as_ref())
+    }
+}
+
+
+pub struct TriangleMesh {
+    pub mesh: Arc<MeshData>,
+    pub material: Arc<dyn Material + Sync + Send>,
+    bvh: Bvh,
+    // running total of face areas, for picking a face to sample
+    area_cdf: Vec<f32>,
+}
+
+impl TriangleMesh {
+    pub fn new(mesh: Arc<MeshData>, material: Arc<dyn Material + Sync + Send>) -> TriangleMesh {
+        let bounds: Vec<Aabb> = (0..mesh.face_count()).map(|face| mesh.face_bounds(face)).collect();
+        let mut total = 0.0;
+        let area_cdf = (0..mesh.face_count()).map(|face| { total += mesh.face_area(face); total }).collect();
+        TriangleMesh {bvh: Bvh::build(&bounds), area_cdf, mesh, material}
+    }
+
+    // split into individual triangles that share this mesh's buffers and material
+    pub fn triangles(&self) -> Vec<Triangle> {
+        (0..self.mesh.face_count()).map(|face| Triangle {mesh: self.mesh.clone(), face, material: self.material.clone()}).collect()
+    }
+}
+
+impl Hitable for TriangleMesh {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let mut barycentrics = (0.0, 0.0);
+        let (face, t) = self.bvh.trace(r, t_min, t_max, |face, closest| {
+            let (t, b1, b2) = self.mesh.hit_face(face, r, t_min, closest)?;
+            barycentrics = (b1, b2);
+            Some(t)
+        })?;
+        Some(self.mesh.record_at(Some(r), t, face, barycentrics.0, barycentrics.1, self.material.as_ref()))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bvh.bounds()
+    }
+
+    fn material(&self) -> &(dyn Material + Sync) {
+        self.material.as_ref()
+    }
+
+    fn area(&self) -> f32 {
+        self.area_cdf.last().cloned().unwrap_or(0.0)
+    }
+
+    fn sample_surface(&self, sampler: &mut dyn Sampler) -> HitRecord<'_> {
+        let pick = sampler.next_1d() * self.area();
+        let face = match self.area_cdf.binary_search_by(|a| a.partial_cmp(&pick).unwrap()) {
+            Ok(i) | Err(i) => i.min(self.area_cdf.len() - 1),
+        };
+        let (b1, b2) = self.mesh.sample_face(sampler.next_2d());
+        self.mesh.record_at(None, 0.0, face, b1, b2, self.material.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use snmath::Vector3;
+    use snmath::Ray;
+
+    use snrt::Hitable;
+    use snrt::material::Lambertian;
+    use snrt::texture::gray;
+    use super::MeshData;
+    use super::TriangleMesh;
+
+    fn v(x: f32, y: f32, z: f32) -> Vector3 {
+        Vector3 {x, y, z}
+    }
+
+    // a unit square in the z=0 plane, split along its diagonal
+    fn square(normals: Vec<Vector3>, uvs: Vec<(f32, f32)>) -> TriangleMesh {
+        let mesh = MeshData {
+            positions: vec![v(0.0, 0.0, 0.0), v(1.0, 0.0, 0.0), v(1.0, 1.0, 0.0), v(0.0, 1.0, 0.0)],
+            normals,
+            uvs,
+            indices: vec![[0, 1, 2], [0, 2, 3]],
+        };
+        TriangleMesh::new(Arc::new(mesh), Arc::new(Lambertian {albedo: gray(0.5)}))
+    }
+
+    fn down_at(x: f32, y: f32) -> Ray {
+        Ray {origin: v(x, y, 2.0), direction: v(0.0, 0.0, -1.0)}
+    }
+
+    #[test]
+    fn hits_inside_and_misses_outside() {
+        let mesh = square(Vec::new(), Vec::new());
+        let hit = mesh.hit(&down_at(0.25, 0.75), 0.001, 100.0).unwrap();
+        assert!((hit.t - 2.0).abs() < 1e-5);
+        assert!((hit.pos - v(0.25, 0.75, 0.0)).length() < 1e-5);
+        assert!((hit.geometric_normal - v(0.0, 0.0, 1.0)).length() < 1e-5);
+        assert!(hit.front_face);
+
+        assert!(mesh.hit(&down_at(1.5, 0.5), 0.001, 100.0).is_none());
+        assert!(mesh.hit(&down_at(0.5, 0.5), 0.001, 1.0).is_none());
+    }
+
+    #[test]
+    fn every_triangle_agrees_with_the_mesh() {
+        let mesh = square(Vec::new(), Vec::new());
+        for &(x, y) in &[(0.1, 0.05), (0.9, 0.5), (0.3, 0.6), (0.5, 0.95)] {
+            let r = down_at(x, y);
+            let whole = mesh.hit(&r, 0.001, 100.0).unwrap();
+            let hits: Vec<f32> = mesh.triangles().iter().filter_map(|tri| tri.hit(&r, 0.001, 100.0)).map(|h| h.t).collect();
+            assert_eq!(hits.len(), 1);
+            assert!((hits[0] - whole.t).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn interpolates_normals_and_uvs() {
+        let tilted = v(1.0, 0.0, 1.0).normalize();
+        let up = v(0.0, 0.0, 1.0);
+        let mesh = square(vec![up, tilted, tilted, up], vec![(0.0, 0.0), (2.0, 0.0), (2.0, 4.0), (0.0, 4.0)]);
+        let hit = mesh.hit(&down_at(0.5, 0.25), 0.001, 100.0).unwrap();
+
+        // half way between the two normals, the geometric one stays flat
+        assert!((hit.normal - (up + tilted).normalize()).length() < 1e-5);
+        assert!((hit.geometric_normal - up).length() < 1e-5);
+        assert!((hit.uv.0 - 1.0).abs() < 1e-5 && (hit.uv.1 - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn area_is_the_sum_of_its_faces() {
+        let mesh = square(Vec::new(), Vec::new());
+        assert!((mesh.area() - 1.0).abs() < 1e-6);
+        assert_eq!(mesh.triangles().len(), 2);
+    }
+}
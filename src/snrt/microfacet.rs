@@ -0,0 +1,283 @@
+use std::f32;
+
+use snmath::Vector3;
+
+// the smallest alpha handed to the distribution, smoother than this and d overflows
+const MIN_ALPHA: f32 = 1e-3;
+
+// the trowbridge-reitz (ggx) distribution of microfacet normals, in a tangent frame with z
+// along the surface normal. alpha_x and alpha_y differ for anisotropic surfaces
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub struct Ggx {
+    pub alpha_x: f32,
+    pub alpha_y: f32,
+}
+
+impl Ggx {
+    // roughness is perceptual, squared into alpha so it looks about linear
+    pub fn from_roughness(roughness: f32) -> Ggx {
+        Ggx::anisotropic(roughness, 0.0)
+    }
+
+    // anisotropy from 0 to 1 stretches highlights along the tangent, disney's mapping
+    pub fn anisotropic(roughness: f32, anisotropy: f32) -> Ggx {
+        let alpha = roughness.clamp(0.0, 1.0) * roughness.clamp(0.0, 1.0);
+        let aspect = (1.0 - 0.9 * anisotropy.clamp(0.0, 1.0)).sqrt();
+        Ggx {alpha_x: (alpha / aspect).max(MIN_ALPHA), alpha_y: (alpha * aspect).max(MIN_ALPHA)}
+    }
+
+    pub fn isotropic(alpha: f32) -> Ggx {
+        Ggx {alpha_x: alpha.max(MIN_ALPHA), alpha_y: alpha.max(MIN_ALPHA)}
+    }
+
+    // density of microfacet normals facing m
+    pub fn d(&self, m: &Vector3) -> f32 {
+        if m.z <= 0.0 {
+            return 0.0;
+        }
+        let e = (m.x / self.alpha_x).powi(2) + (m.y / self.alpha_y).powi(2) + m.z*m.z;
+        1.0 / (f32::consts::PI * self.alpha_x * self.alpha_y * e * e)
+    }
+
+    fn lambda(&self, w: &Vector3) -> f32 {
+        if w.z == 0.0 {
+            return f32::INFINITY;
+        }
+        let a2 = ((self.alpha_x * w.x).powi(2) + (self.alpha_y * w.y).powi(2)) / (w.z * w.z);
+        0.5 * ((1.0 + a2).sqrt() - 1.0)
+    }
+
+    // smith masking, the fraction of microfacets facing w that w can see
+    pub fn g1(&self, w: &Vector3) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // height correlated masking and shadowing, works for either side of the surface
+    pub fn g2(&self, wo: &Vector3, wi: &Vector3) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // a microfacet normal as seen from wo, which has to be above the surface (heitz 2018)
+    pub fn sample_visible(&self, wo: &Vector3, u: (f32, f32)) -> Vector3 {
+        // stretch to where the distribution is a hemisphere
+        let vh = Vector3 {x:self.alpha_x * wo.x, y:self.alpha_y * wo.y, z:wo.z}.normalize();
+        let len_sq = vh.x*vh.x + vh.y*vh.y;
+        let t1 = if len_sq > 0.0 { Vector3 {x:-vh.y, y:vh.x, z:0.0} / len_sq.sqrt() } else { Vector3 {x:1.0, y:0.0, z:0.0} };
+        let t2 = vh.cross(&t1);
+
+        // a point on the disc, squeezed onto the part of it the view can see
+        let r = u.0.sqrt();
+        let phi = 2.0 * f32::consts::PI * u.1;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * (1.0 - p1*p1).max(0.0).sqrt() + s * r * phi.sin();
+        let nh = t1*p1 + t2*p2 + vh*(1.0 - p1*p1 - p2*p2).max(0.0).sqrt();
+
+        Vector3 {x:self.alpha_x * nh.x, y:self.alpha_y * nh.y, z:nh.z.max(1e-6)}.normalize()
+    }
+
+    // density of sample_visible returning m
+    pub fn pdf_visible(&self, wo: &Vector3, m: &Vector3) -> f32 {
+        if wo.z <= 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * wo.dot(m).max(0.0) * self.d(m) / wo.z
+    }
+}
+
+// mirror w about the microfacet normal m
+pub fn reflect(w: &Vector3, m: &Vector3) -> Vector3 {
+    *m * (2.0 * w.dot(m)) - *w
+}
+
+// bend w through m into a medium eta times denser, none under total internal reflection
+pub fn refract(w: &Vector3, m: &Vector3, eta: f32) -> Option<Vector3> {
+    let cos_i = w.dot(m);
+    let sin2_t = (1.0 - cos_i*cos_i).max(0.0) / (eta*eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(*w * (-1.0 / eta) + *m * (cos_i / eta - cos_t))
+}
+
+// unpolarized reflectance of a boundary into a medium eta times denser, 1 under total internal reflection
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let sin2_t = (1.0 - cos_i*cos_i) / (eta*eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let rs = (cos_i - eta*cos_t) / (cos_i + eta*cos_t);
+    let rp = (eta*cos_i - cos_t) / (eta*cos_i + cos_t);
+    0.5 * (rs*rs + rp*rp)
+}
+
+// reflectance of a metal with complex index of refraction eta + ik, per channel
+pub fn fresnel_conductor(cos_i: f32, eta: &Vector3, k: &Vector3) -> Vector3 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let channel = |eta: f32, k: f32| {
+        let cos2 = cos_i*cos_i;
+        let sin2 = 1.0 - cos2;
+        let t0 = eta*eta - k*k - sin2;
+        let a2_plus_b2 = (t0*t0 + 4.0*eta*eta*k*k).sqrt();
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+        let t1 = a2_plus_b2 + cos2;
+        let t2 = 2.0 * cos_i * a;
+        let rs = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2_plus_b2 + sin2*sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+        0.5 * (rs + rp)
+    };
+    Vector3 {x:channel(eta.x, k.x), y:channel(eta.y, k.y), z:channel(eta.z, k.z)}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32;
+
+    use snmath::Vector3;
+    use snrt::sampler::SamplerKind;
+    use super::Ggx;
+    use super::fresnel_conductor;
+    use super::fresnel_dielectric;
+    use super::reflect;
+    use super::refract;
+
+    fn distributions() -> Vec<Ggx> {
+        vec![Ggx::isotropic(0.05), Ggx::isotropic(0.3), Ggx::isotropic(0.9), Ggx::anisotropic(0.5, 0.8)]
+    }
+
+    fn views() -> Vec<Vector3> {
+        vec![Vector3 {x:0.0, y:0.0, z:1.0}, Vector3 {x:0.6, y:0.0, z:0.8}, Vector3 {x:0.3, y:-0.9, z:0.1}.normalize()]
+    }
+
+    // integrates f over the hemisphere on a grid in cos theta and phi, fine enough for the
+    // sharpest lobe here
+    fn hemisphere_integral<F: Fn(&Vector3) -> f32>(f: F) -> f64 {
+        let (n_cos, n_phi) = (4000, 256);
+        let mut sum = 0.0;
+        for i in 0..n_cos {
+            let cos_theta = (i as f32 + 0.5) / n_cos as f32;
+            let sin_theta = (1.0 - cos_theta*cos_theta).sqrt();
+            for j in 0..n_phi {
+                let phi = 2.0 * f32::consts::PI * (j as f32 + 0.5) / n_phi as f32;
+                sum += f64::from(f(&Vector3 {x:sin_theta * phi.cos(), y:sin_theta * phi.sin(), z:cos_theta}));
+            }
+        }
+        sum * 2.0 * f64::from(f32::consts::PI) / f64::from(n_cos * n_phi)
+    }
+
+    #[test]
+    fn projected_microfacet_area_is_one() {
+        for ggx in distributions() {
+            let area = hemisphere_integral(|m| ggx.d(m) * m.z);
+            assert!((area - 1.0).abs() < 0.01, "{:?}: {}", ggx, area);
+        }
+    }
+
+    #[test]
+    fn visible_normal_pdf_integrates_to_one() {
+        for ggx in distributions() {
+            for wo in views() {
+                let total = hemisphere_integral(|m| ggx.pdf_visible(&wo, m));
+                assert!((total - 1.0).abs() < 0.01, "{:?} from {:?}: {}", ggx, wo, total);
+            }
+        }
+    }
+
+    // bins the sampled normals by cos theta and phi and compares each bin with the integral of
+    // pdf_visible over it
+    #[test]
+    fn visible_normals_are_sampled_by_their_pdf() {
+        let (n_cos, n_phi) = (8, 8);
+        let mut sampler = SamplerKind::Independent.create(9, 1);
+        sampler.start_sample(0, 0, 0);
+        for ggx in distributions() {
+            for wo in views() {
+                let count = 200000;
+                let mut bins = vec![0u32; n_cos * n_phi];
+                for _ in 0..count {
+                    let m = ggx.sample_visible(&wo, sampler.next_2d());
+                    assert!((m.length() - 1.0).abs() < 1e-4 && m.z > 0.0);
+                    let phi = m.y.atan2(m.x).rem_euclid(2.0 * f32::consts::PI);
+                    let i = ((m.z * n_cos as f32) as usize).min(n_cos - 1);
+                    let j = ((phi / (2.0 * f32::consts::PI) * n_phi as f32) as usize).min(n_phi - 1);
+                    bins[i * n_phi + j] += 1;
+                }
+
+                let mut expected = vec![0.0; n_cos * n_phi];
+                let (fine_cos, fine_phi) = (400, 16);
+                for i in 0..n_cos * fine_cos {
+                    let cos_theta = (i as f32 + 0.5) / (n_cos * fine_cos) as f32;
+                    let sin_theta = (1.0 - cos_theta*cos_theta).sqrt();
+                    for j in 0..n_phi * fine_phi {
+                        let phi = 2.0 * f32::consts::PI * (j as f32 + 0.5) / (n_phi * fine_phi) as f32;
+                        let m = Vector3 {x:sin_theta * phi.cos(), y:sin_theta * phi.sin(), z:cos_theta};
+                        expected[(i / fine_cos) * n_phi + j / fine_phi] += f64::from(ggx.pdf_visible(&wo, &m));
+                    }
+                }
+                let cell = 2.0 * f64::from(f32::consts::PI) / (n_cos * fine_cos * n_phi * fine_phi) as f64;
+                for (&hits, &density) in bins.iter().zip(expected.iter()) {
+                    let fraction = f64::from(hits) / f64::from(count);
+                    assert!((fraction - density * cell).abs() < 0.005, "{:?} from {:?}: {} vs {}", ggx, wo, fraction, density * cell);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn masking_is_one_head_on_and_falls_off_towards_grazing() {
+        let ggx = Ggx::isotropic(0.4);
+        assert!((ggx.g1(&Vector3 {x:0.0, y:0.0, z:1.0}) - 1.0).abs() < 1e-6);
+        let mut last = 1.0;
+        for i in 1..10 {
+            let theta = i as f32 * 0.17;
+            let g1 = ggx.g1(&Vector3 {x:theta.sin(), y:0.0, z:theta.cos()});
+            assert!(g1 < last && g1 > 0.0);
+            last = g1;
+        }
+        let wo = Vector3 {x:0.6, y:0.0, z:0.8};
+        let wi = Vector3 {x:-0.28, y:0.0, z:0.96};
+        assert!(ggx.g2(&wo, &wi) <= ggx.g1(&wo).min(ggx.g1(&wi)));
+    }
+
+    #[test]
+    fn reflection_and_refraction_follow_snell() {
+        let m = Vector3 {x:0.0, y:0.0, z:1.0};
+        let w = Vector3 {x:0.6, y:0.0, z:0.8};
+        assert_eq!(reflect(&w, &m), Vector3 {x:-0.6, y:0.0, z:0.8});
+
+        let t = refract(&w, &m, 1.5).unwrap();
+        assert!((t.length() - 1.0).abs() < 1e-5 && t.z < 0.0);
+        assert!((t.x + 0.6 / 1.5).abs() < 1e-5, "{:?}", t);
+
+        // leaving glass at 60 degrees is past the critical angle
+        let steep = Vector3 {x:0.866, y:0.0, z:0.5};
+        assert!(refract(&steep, &m, 1.0 / 1.5).is_none());
+        assert_eq!(fresnel_dielectric(0.5, 1.0 / 1.5), 1.0);
+    }
+
+    #[test]
+    fn fresnel_matches_the_closed_forms() {
+        let eta: f32 = 1.5;
+        let f0 = ((eta - 1.0) / (eta + 1.0)).powi(2);
+        assert!((fresnel_dielectric(1.0, eta) - f0).abs() < 1e-6);
+        assert!((fresnel_dielectric(0.0, eta) - 1.0).abs() < 1e-6);
+
+        // a conductor without absorption is just a dielectric
+        let etas = Vector3 {x:1.5, y:1.5, z:1.5};
+        for &cos_i in &[1.0, 0.7, 0.3, 0.05] {
+            let f = fresnel_conductor(cos_i, &etas, &Vector3::default());
+            assert!((f.x - fresnel_dielectric(cos_i, eta)).abs() < 1e-4, "at {}: {} vs {}", cos_i, f.x, fresnel_dielectric(cos_i, eta));
+        }
+
+        // and head on it's ((eta - 1)^2 + k^2) / ((eta + 1)^2 + k^2)
+        let (n, k): (f32, f32) = (0.2, 3.9);
+        let f = fresnel_conductor(1.0, &Vector3 {x:n, y:n, z:n}, &Vector3 {x:k, y:k, z:k});
+        let expected = ((n - 1.0).powi(2) + k*k) / ((n + 1.0).powi(2) + k*k);
+        assert!((f.x - expected).abs() < 1e-4);
+    }
+}
@@ -1,10 +1,33 @@
 use std::boxed::Box;
 use std::f32;
 
+
 use snmath::Vector3;
 use snmath::Ray;
+use snmath::Aabb;
+
+use snrt::sampler::Sampler;
 
+pub mod adaptive;
+pub mod bvh;
+pub mod checkpoint;
+pub mod environment;
+pub mod film;
+pub mod framebuffer;
+pub mod instance;
+pub mod light;
 pub mod material;
+pub mod medium;
+pub mod mesh;
+pub mod microfacet;
+pub mod obj;
+pub mod render;
+pub mod sampler;
+pub mod scene;
+pub mod sky;
+pub mod texture;
+pub mod tiles;
+pub mod tonemap;
 pub mod world;
 
 #[derive(Clone,Copy,PartialEq,Default,Debug)]
@@ -35,39 +58,159 @@ impl Camera {
             horizontal: 2.0*half_width*focal_dist*u,
             vertical: 2.0*half_height*focal_dist*v,
             origin: look_from,
-            u: u, v: v, w: w,
-            lens_radius: lens_radius
+            u, v, w,
+            lens_radius
         }
     }
 
-    pub fn get_ray(&self, s: f32, t: f32) -> Ray {
-        let rd = self.lens_radius * Vector3::generate_random_unit_disc();
+    pub fn get_ray(&self, s: f32, t: f32, sampler: &mut dyn Sampler) -> Ray {
+        let rd = self.lens_radius * Vector3::sample_unit_disc(sampler.next_2d());
         let jitter = self.u * rd.x + self.v * rd.y;
         Ray {origin:self.origin+jitter, direction:self.lower_left_corner + self.horizontal * s + self.vertical * t - jitter}
     }
 }
 
+// the user facing description of a camera, turned into a Camera once the aspect ratio is known
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub struct CameraSettings {
+    pub look_from: Vector3,
+    pub look_at: Vector3,
+    pub up: Vector3,
+    pub v_fov: f32,
+    pub aperture: f32,
+    // defaults to the distance between look_from and look_at
+    pub focal_dist: Option<f32>,
+}
+
+impl Default for CameraSettings {
+    fn default() -> CameraSettings {
+        CameraSettings {
+            look_from: Vector3 {x:7.0, y:2.0, z:2.0},
+            look_at: Vector3 {x:0.0, y:0.0, z:0.0},
+            up: Vector3 {x:0.0, y:1.0, z:0.0},
+            v_fov: 40.0,
+            aperture: 0.3,
+            focal_dist: None,
+        }
+    }
+}
+
+impl CameraSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.look_from.is_finite() || !self.look_at.is_finite() || !self.up.is_finite() {
+            return Err("look_from, look_at and up must be finite".to_string());
+        }
+        if self.look_from == self.look_at {
+            return Err("look_from and look_at must differ".to_string());
+        }
+        if !(self.v_fov > 0.0 && self.v_fov < 180.0) {
+            return Err(format!("fov must be between 0 and 180 degrees, got {}", self.v_fov));
+        }
+        if self.aperture < 0.0 || !self.aperture.is_finite() {
+            return Err("aperture must not be negative".to_string());
+        }
+        if self.focal_dist.is_some_and(|d| d <= 0.0 || !d.is_finite()) {
+            return Err("focus distance must be positive".to_string());
+        }
+        if self.up.cross(&(self.look_from - self.look_at)).length_sq() == 0.0 {
+            return Err("up must not be parallel to the view direction".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn build(&self, aspect_ratio: f32) -> Camera {
+        let focal_dist = self.focal_dist.unwrap_or((self.look_from - self.look_at).length());
+        Camera::create_camera(self.look_from, self.look_at, self.up, self.v_fov, aspect_ratio, self.aperture, focal_dist)
+    }
+}
+
+
+// everything known about a ray hitting a surface
+pub struct HitRecord<'a> {
+    pub t: f32,
+    pub pos: Vector3,
+    // both normals point out of the surface regardless of which side was hit
+    pub geometric_normal: Vector3,
+    pub normal: Vector3,
+    pub front_face: bool,
+    pub uv: (f32, f32),
+    // tangent follows increasing u and is orthogonal to the shading normal
+    pub tangent: Vector3,
+    pub bitangent: Vector3,
+    pub material: &'a (dyn material::Material + Sync),
+    // what fills the inside, when the surface is the boundary of a medium
+    pub medium: Option<&'a medium::HomogeneousMedium>,
+}
+
+impl<'a> HitRecord<'a> {
+    pub fn new(r: &Ray, t: f32, geometric_normal: Vector3, normal: Vector3, uv: (f32, f32), dpdu: Vector3, material: &'a (dyn material::Material + Sync)) -> HitRecord<'a> {
+        let mut rec = HitRecord::on_surface(r.point_at_parameter(t), geometric_normal, normal, uv, dpdu, material);
+        rec.t = t;
+        rec.set_face(r);
+        rec
+    }
+
+    // a point that wasn't found by a ray, like one sampled on a light
+    pub fn on_surface(pos: Vector3, geometric_normal: Vector3, normal: Vector3, uv: (f32, f32), dpdu: Vector3, material: &'a (dyn material::Material + Sync)) -> HitRecord<'a> {
+        let tangent = dpdu - normal * normal.dot(&dpdu);
+        let tangent = if tangent.length_sq() > 1e-12 { tangent.normalize() } else { normal.orthonormal_basis().0 };
+        let bitangent = normal.cross(&tangent);
+
+        HitRecord { t: 0.0, pos, geometric_normal, normal, front_face: true, uv, tangent, bitangent, material, medium: None }
+    }
+
+    pub fn set_face(&mut self, r: &Ray) {
+        self.front_face = r.direction.dot(&self.geometric_normal) < 0.0;
+    }
+
+    // the shading normal flipped onto the side r came from
+    pub fn facing_normal(&self) -> Vector3 {
+        if self.front_face { self.normal } else { self.normal * -1.0 }
+    }
+
+    // takes a direction in the tangent frame (z along the facing normal) out to world space
+    pub fn to_world(&self, local: Vector3) -> Vector3 {
+        let sign = if self.front_face { 1.0 } else { -1.0 };
+        self.tangent * local.x + self.bitangent * (local.y * sign) + self.normal * (local.z * sign)
+    }
+
+    // and back again
+    pub fn to_local(&self, world: Vector3) -> Vector3 {
+        let sign = if self.front_face { 1.0 } else { -1.0 };
+        Vector3 {x: world.dot(&self.tangent), y: world.dot(&self.bitangent) * sign, z: world.dot(&self.normal) * sign}
+    }
+}
+
 
 pub trait Hitable {
-    fn hit_process(&self, r: &Ray, t: f32) -> (bool, Ray, Vector3);
-    fn hit_check(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<f32>;
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>>;
+    fn bounding_box(&self) -> Aabb;
+
+    fn material(&self) -> &(dyn material::Material + Sync);
+    // total surface area and a uniformly distributed point on it, for sampling area lights
+    fn area(&self) -> f32;
+    fn sample_surface(&self, sampler: &mut dyn Sampler) -> HitRecord<'_>;
 }
 
 pub struct Sphere {
     pub pos: Vector3,
     pub radius: f32,
-    pub material: Box<material::Material + Sync>,
+    pub material: Box<dyn material::Material + Sync + Send>,
 }
 
-impl Hitable for Sphere {
-    fn hit_process(&self, r: &Ray, t: f32) -> (bool, Ray, Vector3) {
-        let hit_pos = r.point_at_parameter(t);
-        let hit_normal = (hit_pos - self.pos).normalize();
-
-        self.material.scatter(r, hit_pos, hit_normal)
+impl Sphere {
+    fn surface_at(&self, normal: Vector3) -> ((f32, f32), Vector3) {
+        // latitude/longitude around the y axis
+        let phi = (-normal.z).atan2(normal.x) + f32::consts::PI;
+        let theta = (-normal.y).clamp(-1.0, 1.0).acos();
+        let uv = (phi / (2.0 * f32::consts::PI), theta / f32::consts::PI);
+        let dpdu = Vector3 {x:normal.z, y:0.0, z:-normal.x};
+        (uv, dpdu)
     }
+}
 
-    fn hit_check(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<f32> {
+impl Hitable for Sphere {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
         let oc = r.origin - self.pos;
 
         let a = r.direction.dot(&r.direction);
@@ -80,17 +223,36 @@ impl Hitable for Sphere {
             return None;
         }
 
-        let t = (-b - discriminant.sqrt()) / a ; 
-        if t > t_min && t < t_max {
-            return Some(t);
+        let mut t = (-b - discriminant.sqrt()) / a ; 
+        if t <= t_min || t >= t_max {
+            t = (-b + discriminant.sqrt()) / a ; 
+            if t <= t_min || t >= t_max {
+                return None;
+            }
         }
 
-        let t = (-b + discriminant.sqrt()) / a ; 
-        if t > t_min && t < t_max {
-            return Some(t);
-        }
+        let hit_normal = (r.point_at_parameter(t) - self.pos) / self.radius;
+        let (uv, dpdu) = self.surface_at(hit_normal);
+        Some(HitRecord::new(r, t, hit_normal, hit_normal, uv, dpdu, self.material.as_ref()))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let r = Vector3 {x:self.radius, y:self.radius, z:self.radius};
+        Aabb {min: self.pos - r, max: self.pos + r}
+    }
 
-        return None;
+    fn material(&self) -> &(dyn material::Material + Sync) {
+        self.material.as_ref()
+    }
+
+    fn area(&self) -> f32 {
+        4.0 * f32::consts::PI * self.radius * self.radius
+    }
+
+    fn sample_surface(&self, sampler: &mut dyn Sampler) -> HitRecord<'_> {
+        let normal = Vector3::sample_unit_sphere(sampler.next_2d());
+        let (uv, dpdu) = self.surface_at(normal);
+        HitRecord::on_surface(self.pos + normal * self.radius, normal, normal, uv, dpdu, self.material.as_ref())
     }
 }
 
@@ -98,31 +260,25 @@ impl Hitable for Sphere {
 pub struct AABox {
     pub pos: Vector3,
     pub dims: Vector3,
-    pub material: Box<material::Material + Sync>,
+    pub material: Box<dyn material::Material + Sync + Send>,
 }
 
-
-impl Hitable for AABox {
-    fn hit_process(&self, r: &Ray, t: f32) -> (bool, Ray, Vector3) {
-        let hit_pos = r.point_at_parameter(t);
-        let offset = (hit_pos - self.pos)/self.dims;
-
-        let hit_normal = if offset.x.abs() > offset.y.abs() || offset.z.abs() > offset.y.abs() {
-            if offset.z.abs() > offset.x.abs() {
-                Vector3 {x:0.0, y:0.0, z:if offset.z > 0.0 { 1.0 } else { -1.0 } }
-            }
-            else {
-                Vector3 {x:if offset.x > 0.0 {1.0} else {-1.0}, y:0.0, z:0.0}
-            }
+impl AABox {
+    // each face is mapped to the unit square along the next two axes
+    fn surface_at(&self, hit_pos: Vector3, normal: Vector3) -> ((f32, f32), Vector3) {
+        let offset = (hit_pos - self.pos + self.dims) / (self.dims * 2.0);
+        if normal.x != 0.0 {
+            ((offset.z, offset.y), Vector3 {x:0.0, y:0.0, z:1.0})
+        } else if normal.y != 0.0 {
+            ((offset.x, offset.z), Vector3 {x:1.0, y:0.0, z:0.0})
+        } else {
+            ((offset.x, offset.y), Vector3 {x:1.0, y:0.0, z:0.0})
         }
-        else {
-            Vector3 {x:0.0, y:if offset.y > 0.0 {1.0} else {-1.0}, z:0.0}
-        };
-
-        self.material.scatter(r, hit_pos, hit_normal)
     }
+}
 
-    fn hit_check(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<f32> {
+impl Hitable for AABox {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
         let mins = self.pos - self.dims;
         let maxs = self.pos + self.dims;
 
@@ -138,13 +294,122 @@ impl Hitable for AABox {
         let ymax = tmins.y.max(tmaxs.y);
         let zmax = tmins.z.max(tmaxs.z);
 
-        if xmax > ymin && xmin < ymax && zmax > xmin && zmin < xmax && ymax  >zmin && ymin < zmax {
-            let hit_t = xmin.max(ymin.max(zmin));
-            if hit_t > t_min && hit_t < t_max {
-                return Some(hit_t);
-            }
+        if !(xmax > ymin && xmin < ymax && zmax > xmin && zmin < xmax && ymax  >zmin && ymin < zmax) {
+            return None;
+        }
+
+        // from inside the box the near slabs are behind the ray, so it hits the far side on the way out
+        let near = xmin.max(ymin.max(zmin));
+        let far = xmax.min(ymax.min(zmax));
+        let (hit_t, entering) = if near > t_min { (near, true) } else { (far, false) };
+        if hit_t <= t_min || hit_t >= t_max {
+            return None;
         }
 
-        return None;
+        // the slab the hit is on, the normal points out of the box either way
+        let axis = if entering {
+            if xmin >= ymin && xmin >= zmin { 0 } else if ymin >= zmin { 1 } else { 2 }
+        } else if xmax <= ymax && xmax <= zmax { 0 } else if ymax <= zmax { 1 } else { 2 };
+        let along = if r.direction.get(axis) > 0.0 { 1.0 } else { -1.0 };
+        let outward = if entering { -along } else { along };
+        let hit_normal = match axis {
+            0 => Vector3 {x:outward, y:0.0, z:0.0},
+            1 => Vector3 {x:0.0, y:outward, z:0.0},
+            _ => Vector3 {x:0.0, y:0.0, z:outward},
+        };
+        let hit_pos = r.point_at_parameter(hit_t);
+
+        let (uv, dpdu) = self.surface_at(hit_pos, hit_normal);
+        Some(HitRecord::new(r, hit_t, hit_normal, hit_normal, uv, dpdu, self.material.as_ref()))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb {min: self.pos - self.dims, max: self.pos + self.dims}
+    }
+
+    fn material(&self) -> &(dyn material::Material + Sync) {
+        self.material.as_ref()
+    }
+
+    fn area(&self) -> f32 {
+        let d = self.dims;
+        8.0 * (d.x*d.y + d.y*d.z + d.z*d.x)
+    }
+
+    fn sample_surface(&self, sampler: &mut dyn Sampler) -> HitRecord<'_> {
+        let d = self.dims;
+        let face_areas = [d.y*d.z, d.x*d.z, d.x*d.y];
+
+        // pick an axis by face area, then one of its two faces with what's left of the same number
+        let mut pick = sampler.next_1d() * (face_areas[0] + face_areas[1] + face_areas[2]);
+        let mut axis = 0;
+        while axis < 2 && pick >= face_areas[axis] {
+            pick -= face_areas[axis];
+            axis += 1;
+        }
+        let side = if pick < 0.5 * face_areas[axis] { 1.0 } else { -1.0 };
+
+        let (s, t) = sampler.next_2d();
+        let (s, t) = (2.0*s - 1.0, 2.0*t - 1.0);
+        let mut normal = Vector3::default();
+        let offset = match axis {
+            0 => { normal.x = side; Vector3 {x:side * d.x, y:s * d.y, z:t * d.z} },
+            1 => { normal.y = side; Vector3 {x:s * d.x, y:side * d.y, z:t * d.z} },
+            _ => { normal.z = side; Vector3 {x:s * d.x, y:t * d.y, z:side * d.z} },
+        };
+
+        let pos = self.pos + offset;
+        let (uv, dpdu) = self.surface_at(pos, normal);
+        HitRecord::on_surface(pos, normal, normal, uv, dpdu, self.material.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use snmath::Vector3;
+    use snmath::Ray;
+
+    use super::AABox;
+    use super::Hitable;
+    use super::material::Lambertian;
+    use super::texture::gray;
+
+    fn v(x: f32, y: f32, z: f32) -> Vector3 {
+        Vector3 {x, y, z}
+    }
+
+    fn unit_box() -> AABox {
+        AABox {pos: v(1.0, 2.0, 3.0), dims: v(0.5, 1.0, 2.0), material: Box::new(Lambertian {albedo: gray(0.5)})}
+    }
+
+    #[test]
+    fn box_is_hit_on_the_near_face_from_outside() {
+        let aabox = unit_box();
+        let hit = aabox.hit(&Ray {origin: v(-4.0, 2.0, 3.0), direction: v(1.0, 0.0, 0.0)}, 0.001, 100.0).unwrap();
+        assert!((hit.t - 4.5).abs() < 1e-5);
+        assert_eq!(hit.geometric_normal, v(-1.0, 0.0, 0.0));
+        assert!(hit.front_face);
+
+        let hit = aabox.hit(&Ray {origin: v(1.0, 2.0, 10.0), direction: v(0.0, 0.0, -2.0)}, 0.001, 100.0).unwrap();
+        assert!((hit.t - 2.5).abs() < 1e-5);
+        assert_eq!(hit.geometric_normal, v(0.0, 0.0, 1.0));
+
+        assert!(aabox.hit(&Ray {origin: v(-4.0, 5.0, 3.0), direction: v(1.0, 0.0, 0.0)}, 0.001, 100.0).is_none());
+        assert!(aabox.hit(&Ray {origin: v(-4.0, 2.0, 3.0), direction: v(1.0, 0.0, 0.0)}, 0.001, 4.0).is_none());
+        assert!(aabox.hit(&Ray {origin: v(4.0, 2.0, 3.0), direction: v(1.0, 0.0, 0.0)}, 0.001, 100.0).is_none());
+    }
+
+    #[test]
+    fn box_is_hit_on_the_way_out_from_inside() {
+        let aabox = unit_box();
+        let dirs = [v(1.0, 0.0, 0.0), v(0.0, -1.0, 0.0), v(0.0, 0.0, 1.0), v(0.3, 0.2, -1.0)];
+        let expected = [(0.5, v(1.0, 0.0, 0.0)), (1.0, v(0.0, -1.0, 0.0)), (2.0, v(0.0, 0.0, 1.0)), (0.5 / 0.3, v(1.0, 0.0, 0.0))];
+        for (dir, &(t, normal)) in dirs.iter().zip(&expected) {
+            let hit = aabox.hit(&Ray {origin: v(1.0, 2.0, 3.0), direction: *dir}, 0.001, 100.0).unwrap();
+            assert!((hit.t - t).abs() < 1e-5, "{:?}: {}", dir, hit.t);
+            assert_eq!(hit.geometric_normal, normal);
+            assert!(!hit.front_face);
+            assert_eq!(hit.facing_normal(), normal * -1.0);
+        }
     }
 }
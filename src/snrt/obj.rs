@@ -0,0 +1,567 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use snmath::Vector3;
+
+use snrt::material::Material;
+use snrt::material::Lambertian;
+use snrt::material::Metallic;
+use snrt::material::Dielectric;
+use snrt::material::Principled;
+use snrt::mesh::MeshData;
+use snrt::mesh::TriangleMesh;
+use snrt::texture::Texture;
+use snrt::texture::ImageTexture;
+use snrt::texture::ScaledTexture;
+use snrt::texture::WrapMode;
+use snrt::texture::solid;
+use snrt::texture::gray;
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io(PathBuf, io::Error),
+    Parse { path: PathBuf, line: usize, message: String },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ObjError::Io(ref path, ref e) => write!(f, "{}: {}", path.display(), e),
+            ObjError::Parse { ref path, line, ref message } => write!(f, "{}:{}: {}", path.display(), line, message),
+        }
+    }
+}
+
+impl Error for ObjError {}
+
+// tracks where we are so every error can point at the offending line
+struct LineContext<'a> {
+    path: &'a Path,
+    line: usize,
+}
+
+impl<'a> LineContext<'a> {
+    fn error<T>(&self, message: String) -> Result<T, ObjError> {
+        Err(ObjError::Parse { path: self.path.to_path_buf(), line: self.line, message })
+    }
+
+    fn floats(&self, keyword: &str, tokens: &[&str], min: usize, max: usize) -> Result<Vec<f32>, ObjError> {
+        if tokens.len() < min || tokens.len() > max {
+            return self.error(format!("'{}' expects {} to {} numbers, got {}", keyword, min, max, tokens.len()));
+        }
+        let mut values = Vec::with_capacity(tokens.len());
+        for token in tokens {
+            match token.parse::<f32>() {
+                Ok(v) if v.is_finite() => values.push(v),
+                Ok(_) => return self.error(format!("'{}' has a non-finite number '{}'", keyword, token)),
+                Err(_) => return self.error(format!("'{}' has an invalid number '{}'", keyword, token)),
+            }
+        }
+        Ok(values)
+    }
+
+    fn vector(&self, keyword: &str, tokens: &[&str]) -> Result<Vector3, ObjError> {
+        let v = self.floats(keyword, tokens, 3, 3)?;
+        Ok(Vector3 {x:v[0], y:v[1], z:v[2]})
+    }
+}
+
+fn read_lines(path: &Path) -> Result<Vec<String>, ObjError> {
+    let file = File::open(path).map_err(|e| ObjError::Io(path.to_path_buf(), e))?;
+    BufReader::new(file).lines().collect::<io::Result<Vec<String>>>().map_err(|e| ObjError::Io(path.to_path_buf(), e))
+}
+
+// statements can be continued onto the next line with a trailing backslash
+fn statements(lines: &[String]) -> Vec<(usize, String)> {
+    let mut out = Vec::new();
+    let mut pending: Option<(usize, String)> = None;
+    for (i, raw) in lines.iter().enumerate() {
+        let text = match raw.find('#') {
+            Some(c) => &raw[..c],
+            None => &raw[..],
+        };
+        let (start, mut joined) = pending.take().unwrap_or((i + 1, String::new()));
+        let text = text.trim_end();
+        if let Some(stripped) = text.strip_suffix('\\') {
+            joined.push_str(stripped);
+            joined.push(' ');
+            pending = Some((start, joined));
+        } else {
+            joined.push_str(text);
+            out.push((start, joined));
+        }
+    }
+    if let Some(p) = pending {
+        out.push(p);
+    }
+    out
+}
+
+#[derive(Clone)]
+struct MtlEntry {
+    diffuse: Vector3,
+    diffuse_map: Option<Arc<dyn Texture + Sync + Send>>,
+    specular: Vector3,
+    shininess: f32,
+    ior: f32,
+    dissolve: f32,
+    // the pbr extension some exporters write, any of it turns the material principled
+    pbr: bool,
+    roughness: f32,
+    roughness_map: Option<Arc<dyn Texture + Sync + Send>>,
+    metallic: f32,
+    metallic_map: Option<Arc<dyn Texture + Sync + Send>>,
+    sheen: f32,
+    clearcoat: f32,
+    clearcoat_roughness: f32,
+    anisotropy: f32,
+}
+
+impl Default for MtlEntry {
+    fn default() -> MtlEntry {
+        MtlEntry {
+            diffuse: Vector3 {x:0.8, y:0.8, z:0.8},
+            diffuse_map: None,
+            specular: Vector3::default(),
+            shininess: 0.0,
+            ior: 1.5,
+            dissolve: 1.0,
+            pbr: false,
+            roughness: 0.5,
+            roughness_map: None,
+            metallic: 0.0,
+            metallic_map: None,
+            sheen: 0.0,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.0,
+            anisotropy: 0.0,
+        }
+    }
+}
+
+fn luminance(c: &Vector3) -> f32 {
+    0.2126*c.x + 0.7152*c.y + 0.0722*c.z
+}
+
+impl MtlEntry {
+    // map_Kd is tinted by Kd
+    fn base_color(&self) -> Arc<dyn Texture + Sync + Send> {
+        match self.diffuse_map {
+            Some(ref map) => Arc::new(ScaledTexture {texture: map.clone(), scale: self.diffuse}),
+            None => solid(self.diffuse),
+        }
+    }
+
+    fn to_material(&self) -> Arc<dyn Material + Sync + Send> {
+        if self.pbr {
+            // Ni gives the reflectance at normal incidence, which specular is 0.08 at most of
+            let f0 = ((self.ior - 1.0) / (self.ior + 1.0)).powi(2);
+            return Arc::new(Principled {
+                base_color: self.base_color(),
+                metallic: self.metallic_map.clone().unwrap_or_else(|| gray(self.metallic)),
+                roughness: self.roughness_map.clone().unwrap_or_else(|| gray(self.roughness)),
+                specular: gray((f0 / 0.08).min(1.0)),
+                sheen: gray(self.sheen),
+                clearcoat: gray(self.clearcoat),
+                clearcoat_gloss: gray(1.0 - self.clearcoat_roughness),
+                transmission: gray(1.0 - self.dissolve),
+                anisotropy: gray(self.anisotropy),
+                ..Principled::default()
+            });
+        }
+        if self.dissolve < 1.0 {
+            return Arc::new(Dielectric {ior: self.ior});
+        }
+        if luminance(&self.specular) > luminance(&self.diffuse) {
+            // phong exponent to a rough guess at microfacet roughness
+            let roughness = (2.0 / (self.shininess + 2.0)).sqrt().min(1.0);
+            return Arc::new(Metallic {albedo: solid(self.specular), roughness: gray(roughness)});
+        }
+        Arc::new(Lambertian {albedo: self.base_color()})
+    }
+}
+
+// options like -bm come before the file name, which is always last
+fn load_map(ctx: &LineContext, tokens: &[&str], base_dir: &Path, srgb: bool, assets: &mut Vec<PathBuf>) -> Result<Arc<dyn Texture + Sync + Send>, ObjError> {
+    let path = match tokens.last() {
+        Some(f) if tokens.len() > 1 => base_dir.join(f),
+        _ => return ctx.error(format!("'{}' is missing a file name", tokens[0])),
+    };
+    assets.push(path.clone());
+    match ImageTexture::load(&path, WrapMode::Repeat, srgb) {
+        Ok(texture) => Ok(Arc::new(texture)),
+        Err(e) => ctx.error(format!("could not load texture {}: {}", path.display(), e)),
+    }
+}
+
+fn load_mtl(path: &Path, materials: &mut HashMap<String, Arc<dyn Material + Sync + Send>>, assets: &mut Vec<PathBuf>) -> Result<(), ObjError> {
+    assets.push(path.to_path_buf());
+    let lines = read_lines(path)?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut current: Option<(String, MtlEntry)> = None;
+    for (line, statement) in statements(&lines) {
+        let ctx = LineContext {path, line};
+        let tokens: Vec<&str> = statement.split_whitespace().collect();
+        if tokens.is_empty() {
+            continue;
+        }
+
+        if tokens[0] == "newmtl" {
+            if tokens.len() != 2 {
+                return ctx.error("'newmtl' expects a single name".to_string());
+            }
+            if let Some((name, entry)) = current.take() {
+                materials.insert(name, entry.to_material());
+            }
+            current = Some((tokens[1].to_string(), MtlEntry::default()));
+            continue;
+        }
+
+        let entry = match current {
+            Some((_, ref mut entry)) => entry,
+            None => return ctx.error(format!("'{}' before any 'newmtl'", tokens[0])),
+        };
+
+        match tokens[0] {
+            "Kd" => entry.diffuse = ctx.vector("Kd", &tokens[1..])?,
+            "Ks" => entry.specular = ctx.vector("Ks", &tokens[1..])?,
+            "Ns" => entry.shininess = ctx.floats("Ns", &tokens[1..], 1, 1)?[0],
+            "Ni" => {
+                let ior = ctx.floats("Ni", &tokens[1..], 1, 1)?[0];
+                if ior <= 0.0 {
+                    return ctx.error(format!("'Ni' must be positive, got {}", ior));
+                }
+                entry.ior = ior;
+            },
+            "d" => entry.dissolve = ctx.floats("d", &tokens[1..], 1, 1)?[0],
+            "Tr" => entry.dissolve = 1.0 - ctx.floats("Tr", &tokens[1..], 1, 1)?[0],
+            "map_Kd" => entry.diffuse_map = Some(load_map(&ctx, &tokens, base_dir, true, assets)?),
+            "Pr" | "Pm" | "Ps" | "Pc" | "Pcr" | "aniso" => {
+                let v = ctx.floats(tokens[0], &tokens[1..], 1, 1)?[0];
+                match tokens[0] {
+                    "Pr" => entry.roughness = v,
+                    "Pm" => entry.metallic = v,
+                    "Ps" => entry.sheen = v,
+                    "Pc" => entry.clearcoat = v,
+                    "Pcr" => entry.clearcoat_roughness = v,
+                    _ => entry.anisotropy = v,
+                }
+                entry.pbr = true;
+            },
+            // data rather than color, so not srgb encoded
+            "map_Pr" => {
+                entry.roughness_map = Some(load_map(&ctx, &tokens, base_dir, false, assets)?);
+                entry.pbr = true;
+            },
+            "map_Pm" => {
+                entry.metallic_map = Some(load_map(&ctx, &tokens, base_dir, false, assets)?);
+                entry.pbr = true;
+            },
+            _ => {},
+        }
+    }
+
+    if let Some((name, entry)) = current.take() {
+        materials.insert(name, entry.to_material());
+    }
+
+    Ok(())
+}
+
+// vertices are deduplicated per material since obj indexes positions, uvs and normals separately
+struct MeshBuilder {
+    material: Arc<dyn Material + Sync + Send>,
+    remap: HashMap<(usize, Option<usize>, Option<usize>), usize>,
+    positions: Vec<Vector3>,
+    normals: Vec<Option<Vector3>>,
+    uvs: Vec<Option<(f32, f32)>>,
+    indices: Vec<[usize; 3]>,
+}
+
+impl MeshBuilder {
+    fn new(material: Arc<dyn Material + Sync + Send>) -> MeshBuilder {
+        MeshBuilder {material, remap: HashMap::new(), positions: Vec::new(), normals: Vec::new(), uvs: Vec::new(), indices: Vec::new()}
+    }
+
+    fn build(self) -> TriangleMesh {
+        // a mesh only keeps normals and uvs when every vertex has them
+        let normals = self.normals.iter().cloned().collect::<Option<Vec<Vector3>>>().unwrap_or_default();
+        let uvs = self.uvs.iter().cloned().collect::<Option<Vec<(f32, f32)>>>().unwrap_or_default();
+
+        TriangleMesh::new(Arc::new(MeshData {positions: self.positions, normals, uvs, indices: self.indices}), self.material)
+    }
+}
+
+fn resolve_index(ctx: &LineContext, token: &str, count: usize, what: &str) -> Result<usize, ObjError> {
+    let idx = match token.parse::<i64>() {
+        Ok(i) => i,
+        Err(_) => return ctx.error(format!("invalid {} index '{}'", what, token)),
+    };
+    let resolved = if idx < 0 { count as i64 + idx } else { idx - 1 };
+    if resolved < 0 || resolved >= count as i64 {
+        return ctx.error(format!("{} index {} out of range (have {})", what, idx, count));
+    }
+    Ok(resolved as usize)
+}
+
+pub fn load_obj(path: &Path) -> Result<Vec<TriangleMesh>, ObjError> {
+    load_obj_with_assets(path, &mut Vec::new())
+}
+
+// also adds the obj, its material libraries and their texture maps to assets
+pub fn load_obj_with_assets(path: &Path, assets: &mut Vec<PathBuf>) -> Result<Vec<TriangleMesh>, ObjError> {
+    assets.push(path.to_path_buf());
+    let lines = read_lines(path)?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut positions: Vec<Vector3> = Vec::new();
+    let mut normals: Vec<Vector3> = Vec::new();
+    let mut uvs: Vec<(f32, f32)> = Vec::new();
+
+    let mut materials: HashMap<String, Arc<dyn Material + Sync + Send>> = HashMap::new();
+    let default_material: Arc<dyn Material + Sync + Send> = Arc::new(Lambertian {albedo: gray(0.8)});
+
+    let mut finished: Vec<MeshBuilder> = Vec::new();
+    let mut builders: HashMap<String, usize> = HashMap::new();
+    let mut current = 0;
+    finished.push(MeshBuilder::new(default_material));
+
+    for (line, statement) in statements(&lines) {
+        let ctx = LineContext {path, line};
+        let tokens: Vec<&str> = statement.split_whitespace().collect();
+        if tokens.is_empty() {
+            continue;
+        }
+
+        match tokens[0] {
+            "v" => {
+                let v = ctx.floats("v", &tokens[1..], 3, 4)?;
+                positions.push(Vector3 {x:v[0], y:v[1], z:v[2]});
+            },
+            "vn" => {
+                let n = ctx.vector("vn", &tokens[1..])?;
+                // normalizing it would give nan
+                if n.length_sq() == 0.0 {
+                    return ctx.error("'vn' has zero length".to_string());
+                }
+                normals.push(n.normalize());
+            },
+            "vt" => {
+                let v = ctx.floats("vt", &tokens[1..], 1, 3)?;
+                uvs.push((v[0], if v.len() > 1 { v[1] } else { 0.0 }));
+            },
+            "mtllib" => {
+                if tokens.len() < 2 {
+                    return ctx.error("'mtllib' is missing a file name".to_string());
+                }
+                for lib in &tokens[1..] {
+                    load_mtl(&base_dir.join(lib), &mut materials, assets)?;
+                }
+            },
+            "usemtl" => {
+                if tokens.len() != 2 {
+                    return ctx.error("'usemtl' expects a single name".to_string());
+                }
+                let name = tokens[1];
+                current = match builders.get(name) {
+                    Some(&idx) => idx,
+                    None => {
+                        let material = match materials.get(name) {
+                            Some(m) => m.clone(),
+                            None => return ctx.error(format!("unknown material '{}'", name)),
+                        };
+                        finished.push(MeshBuilder::new(material));
+                        builders.insert(name.to_string(), finished.len() - 1);
+                        finished.len() - 1
+                    }
+                };
+            },
+            "f" => {
+                if tokens.len() < 4 {
+                    return ctx.error(format!("'f' needs at least 3 vertices, got {}", tokens.len() - 1));
+                }
+
+                let builder = &mut finished[current];
+                let mut corners = Vec::with_capacity(tokens.len() - 1);
+                for corner in &tokens[1..] {
+                    let parts: Vec<&str> = corner.split('/').collect();
+                    if parts.len() > 3 {
+                        return ctx.error(format!("malformed face vertex '{}'", corner));
+                    }
+                    let vi = resolve_index(&ctx, parts[0], positions.len(), "position")?;
+                    let ti = match parts.get(1) {
+                        Some(t) if !t.is_empty() => Some(resolve_index(&ctx, t, uvs.len(), "uv")?),
+                        _ => None,
+                    };
+                    let ni = match parts.get(2) {
+                        Some(n) if !n.is_empty() => Some(resolve_index(&ctx, n, normals.len(), "normal")?),
+                        _ => None,
+                    };
+
+                    let next = builder.positions.len();
+                    let idx = *builder.remap.entry((vi, ti, ni)).or_insert(next);
+                    if idx == next {
+                        builder.positions.push(positions[vi]);
+                        builder.uvs.push(ti.map(|t| uvs[t]));
+                        builder.normals.push(ni.map(|n| normals[n]));
+                    }
+                    corners.push(idx);
+                }
+
+                // fan triangulation, fine for the convex polygons exporters write
+                for i in 1..corners.len()-1 {
+                    builder.indices.push([corners[0], corners[i], corners[i+1]]);
+                }
+            },
+            // groups, objects and smoothing groups don't change how we render
+            _ => {},
+        }
+    }
+
+    Ok(finished.into_iter().filter(|b| !b.indices.is_empty()).map(|b| b.build()).collect())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+
+    use snmath::Vector3;
+    use snrt::mesh::TriangleMesh;
+    use super::ObjError;
+    use super::load_obj;
+    use super::load_obj_with_assets;
+
+    fn write_file(name: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("rustrace-obj-{}-{}", process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn parse_error_line(result: Result<Vec<TriangleMesh>, ObjError>) -> (usize, String) {
+        match result {
+            Err(ObjError::Parse {line, message, ..}) => (line, message),
+            Err(e) => panic!("expected a parse error, got {}", e),
+            Ok(_) => panic!("expected a parse error"),
+        }
+    }
+
+    #[test]
+    fn triangulates_polygons_in_every_vertex_format() {
+        let path = write_file("formats.obj", "\
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 2
+f 1/1/1 2/2/1 3/3/1 4/4/1
+f 1//1 3//1 4//1
+f 1/1 2/2 3/3
+f 1 2 \\
+  4
+");
+        let meshes = load_obj(&path).unwrap();
+        assert_eq!(meshes.len(), 1);
+        let mesh = &meshes[0].mesh;
+        assert_eq!(mesh.indices.len(), 5);
+        assert_eq!(mesh.indices[0], [0, 1, 2]);
+        assert_eq!(mesh.indices[1], [0, 2, 3]);
+        // some corners have no normal or uv, so the mesh keeps neither
+        assert!(mesh.normals.is_empty() && mesh.uvs.is_empty());
+        assert_eq!(mesh.positions[2], Vector3 {x:1.0, y:1.0, z:0.0});
+    }
+
+    #[test]
+    fn keeps_normalized_normals_and_uvs_when_every_corner_has_them() {
+        let path = write_file("attributes.obj", "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0.5 0.25\nvn 0 0 2\nf 1/1/1 2/1/1 3/1/1\n");
+        let mesh = &load_obj(&path).unwrap()[0].mesh;
+        assert_eq!(mesh.normals, vec![Vector3 {x:0.0, y:0.0, z:1.0}; 3]);
+        assert_eq!(mesh.uvs, vec![(0.5, 0.25); 3]);
+    }
+
+    #[test]
+    fn negative_indices_count_back_from_the_latest_vertex() {
+        let relative = write_file("relative.obj", "v 0 0 0\nv 1 0 0\nv 0 1 0\nf -3 -2 -1\nv 5 5 5\nf -4 -2 -1\n");
+        let absolute = write_file("absolute.obj", "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\nv 5 5 5\nf 1 3 4\n");
+        let relative = &load_obj(&relative).unwrap()[0].mesh;
+        let absolute = &load_obj(&absolute).unwrap()[0].mesh;
+        assert_eq!(relative.indices, absolute.indices);
+        assert_eq!(relative.positions, absolute.positions);
+    }
+
+    #[test]
+    fn reports_the_line_of_bad_input() {
+        let path = write_file("range.obj", "v 0 0 0\nv 1 0 0\n\nf 1 2 3\n");
+        let (line, message) = parse_error_line(load_obj(&path));
+        assert_eq!(line, 4);
+        assert!(message.contains("out of range"), "{}", message);
+
+        let path = write_file("number.obj", "v 0 0 0\nv 1 zero 0\n");
+        assert_eq!(parse_error_line(load_obj(&path)).0, 2);
+
+        let path = write_file("material.obj", "v 0 0 0\nusemtl missing\n");
+        assert_eq!(parse_error_line(load_obj(&path)).0, 2);
+    }
+
+    #[test]
+    fn rejects_non_finite_numbers() {
+        for (i, bad) in ["nan", "inf", "-infinity"].iter().enumerate() {
+            let path = write_file(&format!("finite{}.obj", i), &format!("v 0 0 0\nv 1 {} 0\n", bad));
+            let (line, message) = parse_error_line(load_obj(&path));
+            assert_eq!(line, 2);
+            assert!(message.contains("non-finite"), "{}", message);
+        }
+    }
+
+    #[test]
+    fn rejects_zero_length_normals() {
+        let path = write_file("zero-normal.obj", "v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 1\nvn 0 0 0\nf 1//1 2//1 3//2\n");
+        let (line, message) = parse_error_line(load_obj(&path));
+        assert_eq!(line, 5);
+        assert!(message.contains("zero length"), "{}", message);
+    }
+
+    #[test]
+    fn rejects_an_ior_that_is_not_positive() {
+        for (i, bad) in ["0", "-1.5"].iter().enumerate() {
+            write_file(&format!("ior{}.mtl", i), &format!("newmtl glass\nd 0.5\nNi {}\n", bad));
+            let path = write_file(&format!("ior{}.obj", i), &format!("mtllib rustrace-obj-{}-ior{}.mtl\nv 0 0 0\n", process::id(), i));
+            match load_obj(&path) {
+                Err(ObjError::Parse {line, message, path}) => {
+                    assert_eq!(line, 3);
+                    assert!(message.contains("'Ni' must be positive"), "{}", message);
+                    assert!(path.to_string_lossy().ends_with(".mtl"));
+                },
+                _ => panic!("expected a parse error in the mtl file"),
+            }
+        }
+        write_file("ior.mtl", "newmtl glass\nd 0.5\nNi 1.33\n");
+        let path = write_file("ior.obj", &format!("mtllib rustrace-obj-{}-ior.mtl\nv 0 0 0\n", process::id()));
+        assert!(load_obj(&path).is_ok());
+    }
+
+    #[test]
+    fn records_the_files_a_mesh_was_loaded_from() {
+        let mtl = write_file("assets.mtl", "newmtl red\nKd 1 0 0\n");
+        let path = write_file("assets.obj", &format!("mtllib rustrace-obj-{}-assets.mtl\nusemtl red\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n", process::id()));
+        let mut assets = Vec::new();
+        load_obj_with_assets(&path, &mut assets).unwrap();
+        assert_eq!(assets, vec![path, mtl]);
+    }
+}
@@ -0,0 +1,685 @@
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+extern crate scoped_threadpool;
+use scoped_threadpool::Pool;
+
+use snmath::Vector3;
+use snmath::Ray;
+
+use snrt::Camera;
+use snrt::CameraSettings;
+use snrt::Hitable;
+use snrt::HitRecord;
+use snrt::adaptive::AdaptiveSettings;
+use snrt::checkpoint;
+use snrt::checkpoint::Checkpoint;
+use snrt::framebuffer::Framebuffer;
+use snrt::medium::HomogeneousMedium;
+use snrt::medium::henyey_greenstein;
+use snrt::medium::sample_henyey_greenstein;
+use snrt::sampler::Sampler;
+use snrt::sampler::SamplerKind;
+use snrt::scene::RenderSettings;
+use snrt::tiles::Tile;
+use snrt::tiles::split_into_tiles;
+use snrt::world::World;
+
+// the range along a ray that counts as a hit, anything past MAX_T is the environment
+const MIN_T: f32 = 0.001;
+const MAX_T: f32 = 100000.0;
+
+// power heuristic with beta = 2
+fn mis_weight(pdf: f32, other_pdf: f32) -> f32 {
+    let a = pdf*pdf;
+    let b = other_pdf*other_pdf;
+    if a + b == 0.0 {
+        return 0.0;
+    }
+    a / (a + b)
+}
+
+// solid angle pdf of light sampling having picked the point light_hit on emitter as seen from origin
+fn emitter_pdf(world: &World, emitter: &(dyn Hitable + Sync), origin: Vector3, light_hit: &HitRecord) -> f32 {
+    let to_light = light_hit.pos - origin;
+    let dist_sq = to_light.length_sq();
+    let cosine = light_hit.geometric_normal.dot(&to_light).abs() / dist_sq.sqrt();
+    // emitters without an area are never light sampled
+    let area = emitter.area();
+    if cosine == 0.0 || dist_sq == 0.0 || area <= 0.0 {
+        return 0.0;
+    }
+    world.emitter_pick_pdf() * dist_sq / (cosine * area)
+}
+
+// the medium a ray leaving hit in dir goes through, media don't nest so leaving one is back into the fog
+fn medium_after<'a>(world: &'a World, hit: &HitRecord<'a>, dir: Vector3, current: Option<&'a HomogeneousMedium>) -> Option<&'a HomogeneousMedium> {
+    match hit.medium {
+        Some(inside) if dir.dot(&hit.geometric_normal) < 0.0 => Some(inside),
+        Some(_) => world.fog.as_ref(),
+        None => current,
+    }
+}
+
+// how much of the light makes it dist along the unit vector dir, black when something is in the way.
+// steps through medium boundaries, dimming by whatever medium each stretch is in
+fn transmittance<'a>(world: &'a World, origin: Vector3, dir: Vector3, dist: f32, medium: Option<&'a HomogeneousMedium>, rays: &mut u64) -> Vector3 {
+    let mut tr = Vector3 {x:1.0, y:1.0, z:1.0};
+    let mut origin = origin;
+    let mut medium = medium;
+    let mut remaining = dist * 0.999;
+    loop {
+        *rays += 1;
+        let hit = world.trace(Ray {origin, direction:dir}, MIN_T, remaining);
+        if let Some(m) = medium {
+            tr = tr * m.transmittance(hit.as_ref().map_or(remaining, |h| h.0.t));
+        }
+        match hit {
+            Some((hit, _)) if hit.material.is_invisible() => {
+                medium = medium_after(world, &hit, dir, medium);
+                origin = hit.pos;
+                remaining -= hit.t;
+            },
+            Some(_) => return Vector3::default(),
+            None => return tr,
+        }
+    }
+}
+
+// what happens to light arriving at a path vertex, a surface's bsdf or a medium's phase function
+enum Scattering<'a, 'b> {
+    Surface(&'b Ray, &'b HitRecord<'a>),
+    // the direction the path was going and the phase function's g
+    Medium(Vector3, f32),
+}
+
+impl<'a, 'b> Scattering<'a, 'b> {
+    // what light from dir is multiplied by on its way out, cosine included
+    fn f(&self, dir: Vector3) -> Vector3 {
+        match *self {
+            Scattering::Surface(r, hit) => {
+                // light can only arrive on the side we are looking at
+                let cosine = hit.facing_normal().dot(&dir);
+                if cosine <= 0.0 {
+                    return Vector3::default();
+                }
+                hit.material.eval(r, hit, dir) * cosine
+            },
+            Scattering::Medium(d, g) => {
+                let p = henyey_greenstein(d.dot(&dir), g);
+                Vector3 {x:p, y:p, z:p}
+            },
+        }
+    }
+
+    // the chance the path would have gone towards dir by itself
+    fn pdf(&self, dir: Vector3) -> f32 {
+        match *self {
+            Scattering::Surface(r, hit) => hit.material.pdf(r, hit, dir),
+            Scattering::Medium(d, g) => henyey_greenstein(d.dot(&dir), g),
+        }
+    }
+}
+
+// next event estimation: every delta light plus one sample of one area light
+fn sample_lights<'a>(world: &'a World, pos: Vector3, scattering: &Scattering, medium: Option<&'a HomogeneousMedium>, sampler: &mut dyn Sampler, rays: &mut u64) -> Vector3 {
+    let mut direct = Vector3::default();
+    for light in &world.lights {
+        if let Some((dir, dist, li)) = light.sample_li(pos) {
+            let f = scattering.f(dir);
+            if f != Vector3::default() {
+                direct = direct + f*li*transmittance(world, pos, dir, dist, medium, rays);
+            }
+        }
+    }
+
+    // weighted against the chance that scatter would have found the same light
+    if let Some((emitter, _)) = world.pick_emitter(sampler) {
+        let mut light_hit = emitter.sample_surface(sampler);
+        let to_light = light_hit.pos - pos;
+        let dist = to_light.length();
+        let dir = to_light / dist;
+
+        if dist > 0.0 {
+            let shadow = Ray {origin:pos, direction:dir};
+            light_hit.set_face(&shadow);
+            let le = light_hit.material.emitted(&shadow, &light_hit);
+            let f = scattering.f(dir);
+            let light_pdf = emitter_pdf(world, emitter, pos, &light_hit);
+
+            if le != Vector3::default() && f != Vector3::default() && light_pdf > 0.0 {
+                let tr = transmittance(world, pos, dir, dist, medium, rays);
+                if tr != Vector3::default() {
+                    let weight = mis_weight(light_pdf, scattering.pdf(dir));
+                    direct = direct + f*le*tr*(weight / light_pdf);
+                }
+            }
+        }
+    }
+
+    // and one towards the environment, which might be holding the sun
+    if let Some((dir, le, env_pdf)) = world.environment.sample(sampler.next_2d()) {
+        if env_pdf > 0.0 && le != Vector3::default() {
+            let f = scattering.f(dir);
+            if f != Vector3::default() {
+                let tr = transmittance(world, pos, dir, MAX_T, medium, rays);
+                if tr != Vector3::default() {
+                    let weight = mis_weight(env_pdf, scattering.pdf(dir));
+                    direct = direct + f*le*tr*(weight / env_pdf);
+                }
+            }
+        }
+    }
+
+    direct
+}
+
+// bsdf_pdf is the pdf scatter had for r, 0 for camera rays and specular bounces which lights can't sample.
+// medium is whatever r starts out in, bounces_left counts r itself
+fn color<'a>(r: Ray, world: &'a World, medium: Option<&'a HomogeneousMedium>, bounces_left: u32, bsdf_pdf: f32, sampler: &mut dyn Sampler, rays: &mut u64) -> Vector3 {
+    if bounces_left == 0 {
+        return Vector3::default();
+    }
+
+    // medium boundaries with nothing on them are stepped over without counting as a bounce
+    let mut segment = r;
+    let mut medium = medium;
+    let mut throughput = Vector3 {x:1.0, y:1.0, z:1.0};
+    loop {
+        *rays += 1;
+        let hit = world.trace(segment, MIN_T, MAX_T);
+
+        if let Some(m) = medium {
+            let length = segment.direction.length();
+            let t_max = hit.as_ref().map_or(MAX_T, |h| h.0.t) * length;
+            let (scattered_at, weight) = m.sample_distance(t_max, sampler.next_2d());
+            throughput = throughput * weight;
+            if let Some(t) = scattered_at {
+                let dir = segment.direction / length;
+                let pos = segment.origin + dir * t;
+                let direct = sample_lights(world, pos, &Scattering::Medium(dir, m.g), medium, sampler, rays);
+                // the phase function is sampled exactly, so it cancels out of the weight
+                let wi = sample_henyey_greenstein(&dir, m.g, sampler.next_2d());
+                let pdf = henyey_greenstein(dir.dot(&wi), m.g);
+                return throughput * (direct + color(Ray {origin:pos, direction:wi}, world, medium, bounces_left-1, pdf, sampler, rays));
+            }
+        }
+
+        match hit {
+            Some((hit, _)) if hit.material.is_invisible() => {
+                medium = medium_after(world, &hit, segment.direction, medium);
+                segment = Ray {origin:hit.pos, direction:segment.direction};
+            },
+            Some((hit, entity)) => {
+                let material = hit.material;
+
+                // from where the path last scattered, not the last boundary it stepped over
+                let mut emitted = material.emitted(&segment, &hit);
+                if bsdf_pdf > 0.0 && emitted != Vector3::default() {
+                    emitted = emitted * mis_weight(bsdf_pdf, emitter_pdf(world, entity, r.origin, &hit));
+                }
+
+                let direct = sample_lights(world, hit.pos, &Scattering::Surface(&segment, &hit), medium, sampler, rays);
+
+                let scat = material.scatter(&segment, &hit, sampler);
+                if scat.0 {
+                    let pdf = material.pdf(&segment, &hit, scat.1.direction);
+                    let next = medium_after(world, &hit, scat.1.direction, medium);
+                    return throughput * (emitted + direct + scat.2*color(scat.1, world, next, bounces_left-1, pdf, sampler, rays));
+                }
+                return throughput * (emitted + direct);
+            },
+            None => {
+                let le = world.environment.radiance(&segment.direction);
+                if bsdf_pdf > 0.0 {
+                    return throughput * le * mis_weight(bsdf_pdf, world.environment.pdf(&segment.direction));
+                }
+                return throughput * le;
+            },
+        }
+    }
+}
+
+// stops a render early, either when cancel is called from any thread or once its deadline passes.
+// workers check it between samples so they finish up quickly after either
+#[derive(Clone,Default,Debug)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    // cancels itself once budget has passed from now
+    pub fn with_budget(budget: Duration) -> CancelToken {
+        CancelToken {cancelled: Arc::new(AtomicBool::new(false)), deadline: Instant::now().checked_add(budget)}
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed) || self.deadline.is_some_and(|d| Instant::now() >= d)
+    }
+}
+
+// what a render got done before finishing or being stopped
+pub struct RenderOutput {
+    pub image: Framebuffer,
+    // samples each pixel took, rows from the top like the image
+    pub sample_counts: Vec<u32>,
+    // false when it was cancelled before every pixel had all of its samples
+    pub complete: bool,
+}
+
+// what is left of an adaptive render's budget and how many pixels are still noisy
+fn unspent_budget(settings: &RenderSettings, state: &Checkpoint, adaptive: &AdaptiveSettings) -> (u64, u64) {
+    let taken: u64 = state.sample_counts.iter().map(|&c| u64::from(c)).sum();
+    let budget = u64::from(settings.samples) * state.sample_counts.len() as u64;
+    let noisy = state.variances.iter().filter(|v| !v.converged(adaptive)).count() as u64;
+    (budget.saturating_sub(taken), noisy)
+}
+
+// renders a world into a Checkpoint a pass at a time, or straight to a Framebuffer with render.
+// the world's bvh has to be built before it is handed over
+pub struct Renderer<'a> {
+    world: &'a World,
+    camera_settings: CameraSettings,
+    camera: Camera,
+    settings: RenderSettings,
+    seed: u64,
+    threads: u32,
+    tiles: Vec<Tile>,
+}
+
+impl<'a> Renderer<'a> {
+    // the same seed gives the same image whatever the thread count. tile and pass sizes change the
+    // order samples are summed in, so with them only float rounding differs
+    pub fn new(world: &'a World, camera_settings: &CameraSettings, settings: RenderSettings, seed: u64) -> Result<Renderer<'a>, String> {
+        settings.validate()?;
+        camera_settings.validate().map_err(|e| format!("camera {}", e))?;
+        world.build_bvh();
+        Ok(Renderer {
+            world,
+            camera_settings: *camera_settings,
+            camera: camera_settings.build(settings.width as f32 / settings.height as f32),
+            settings,
+            seed,
+            threads: thread::available_parallelism().map(|n| n.get() as u32).unwrap_or(1),
+            tiles: split_into_tiles(settings.width, settings.height, settings.tile_size, settings.tile_order),
+        })
+    }
+
+    // defaults to one per cpu
+    pub fn set_threads(&mut self, threads: u32) {
+        self.threads = threads.max(1);
+    }
+
+    pub fn settings(&self) -> &RenderSettings {
+        &self.settings
+    }
+
+    // identifies everything that changes what the samples come out as, scene holds whatever
+    // describes the world (the scene file, say) since the renderer can't see where it came from
+    pub fn fingerprint(&self, scene: &[u8]) -> u64 {
+        let s = &self.settings;
+        let mut description = scene.to_vec();
+        description.extend(format!("{:?}", (self.seed, s.width, s.height, s.max_bounces, s.sampler, s.filter, s.adaptive, self.camera_settings)).bytes());
+        if s.sampler == SamplerKind::Stratified {
+            // the strata are sized for the sample count
+            description.extend(s.samples.to_le_bytes());
+        }
+        checkpoint::fingerprint(&description)
+    }
+
+    // an empty state to accumulate passes in
+    pub fn start(&self, fingerprint: u64) -> Checkpoint {
+        Checkpoint::new(self.seed, fingerprint, self.settings.width, self.settings.height, self.settings.filter)
+    }
+
+    // how many more passes state needs to reach the sample count. adaptive renders carry on past
+    // it while pixels are still noisy and the samples converged ones left over aren't spent, for
+    // them this is only a guess
+    pub fn passes_left(&self, state: &Checkpoint) -> u32 {
+        let s = &self.settings;
+        let base = s.samples.saturating_sub(state.samples_done).div_ceil(s.pass_samples);
+        match s.adaptive {
+            Some(ref adaptive) if base == 0 => {
+                let (unspent, noisy) = unspent_budget(s, state, adaptive);
+                if unspent > 0 && noisy > 0 {
+                    s.max_pixel_samples().saturating_sub(state.samples_done).div_ceil(s.pass_samples)
+                } else {
+                    0
+                }
+            },
+            _ => base,
+        }
+    }
+
+    // takes the next pass_samples samples in every pixel that hasn't converged, calling
+    // tile_done with the pixels and rays of each tile as it finishes. returns false if cancel
+    // stopped it part way, the state is still consistent and carries on from where each pixel got to
+    pub fn render_pass(&self, state: &mut Checkpoint, cancel: &CancelToken, tile_done: &mut dyn FnMut(u64, u64)) -> bool {
+        let width = self.settings.width;
+        let f_width = width as f32;
+        let f_height = self.settings.height as f32;
+        let n_samples = self.settings.samples;
+        let max_bounces = self.settings.max_bounces;
+        let sampler_kind = self.settings.sampler;
+        let adaptive = self.settings.adaptive;
+        let seed = self.seed;
+        let world = self.world;
+        let cam = &self.camera;
+
+        if self.passes_left(state) == 0 {
+            return true;
+        }
+        let pass_start = state.samples_done;
+        // passes stop at the sample count first, so a render that doesn't go past it is the same either
+        // way. after that they only share out what is left of the budget between the noisy pixels
+        let pass_end = match adaptive {
+            Some(ref adaptive) if pass_start >= n_samples => {
+                let (unspent, noisy) = unspent_budget(&self.settings, state, adaptive);
+                let share = unspent.div_ceil(noisy).min(u64::from(self.settings.pass_samples)) as u32;
+                (pass_start + share).min(self.settings.max_pixel_samples())
+            },
+            _ => (pass_start + self.settings.pass_samples).min(n_samples),
+        };
+
+        // each job gets its tile's sample counts and variances and hands them back after the pass
+        let mut thread_jobs = Vec::new();
+        for tile in &self.tiles {
+            let film_tile = state.film.tile(tile.x0, tile.y0, tile.x1, tile.y1);
+            let mut pixels = Vec::with_capacity(tile.pixel_count() as usize);
+            for y in tile.y0..tile.y1 {
+                for x in tile.x0..tile.x1 {
+                    let i = (y * width + x) as usize;
+                    pixels.push((state.sample_counts[i], state.variances[i]));
+                }
+            }
+            thread_jobs.push((*tile, film_tile, pixels));
+        }
+
+        let mut complete = true;
+        let mut pool = Pool::new(self.threads);
+        let (done_tx, done_rx) = mpsc::channel();
+        pool.scoped( |scope| {
+            for e in &mut thread_jobs {
+                let done_tx = done_tx.clone();
+                scope.execute(move || {
+                    let tile = e.0;
+                    let mut sampler = sampler_kind.create(seed, n_samples);
+                    let mut rays = 0;
+                    // whether every pixel got to pass_end, a cancel that comes after that doesn't count
+                    let mut finished = true;
+                    'tile: for y in tile.y0..tile.y1 {
+                        for x in tile.x0..tile.x1 {
+                            let pixel = &mut e.2[((y - tile.y0) * (tile.x1 - tile.x0) + x - tile.x0) as usize];
+                            // from the pixel's own count, a cancelled pass can leave it anywhere before pass_end
+                            for sample in pixel.0..pass_end {
+                                if cancel.is_cancelled() {
+                                    finished = false;
+                                    break 'tile;
+                                }
+                                if let Some(ref adaptive) = adaptive {
+                                    if pixel.1.converged(adaptive) {
+                                        break;
+                                    }
+                                }
+
+                                // samples only depend on the pixel and index so the image doesn't depend on how work is split up
+                                sampler.start_sample(x, y, sample);
+                                let (jx, jy) = sampler.next_2d();
+                                // film space runs down from the top, the camera's v runs up
+                                let px = x as f32 + jx;
+                                let py = y as f32 + jy;
+                                let r = cam.get_ray(px / f_width, 1.0 - py / f_height, sampler.as_mut());
+                                let c = color(r, world, world.fog.as_ref(), max_bounces.saturating_add(1), 0.0, sampler.as_mut(), &mut rays);
+                                e.1.add_sample(px, py, c);
+
+                                pixel.1.add(c);
+                                pixel.0 = sample + 1;
+                            }
+                        }
+                    }
+                    let _ = done_tx.send((tile.pixel_count(), rays, finished));
+                });
+            }
+
+            // the workers hold the only senders now, so this ends when the last tile is done
+            drop(done_tx);
+            for (pixels, rays, finished) in done_rx.iter() {
+                complete &= finished;
+                tile_done(pixels, rays);
+            }
+        });
+
+        for e in &thread_jobs {
+            state.film.merge(&e.1);
+            let tile = e.0;
+            let mut pixels = e.2.iter();
+            for y in tile.y0..tile.y1 {
+                for x in tile.x0..tile.x1 {
+                    let i = (y * width + x) as usize;
+                    let &(count, variance) = pixels.next().unwrap();
+                    state.sample_counts[i] = count;
+                    state.variances[i] = variance;
+                }
+            }
+        }
+        if complete {
+            state.samples_done = pass_end;
+        }
+        complete
+    }
+
+    // every pass in one go, for when there's nothing to report or save along the way
+    pub fn render(&self) -> Framebuffer {
+        self.render_cancellable(&CancelToken::new()).image
+    }
+
+    // renders until done or cancelled, whichever comes first. by then the pool's threads have all
+    // been joined, and the image is whatever each pixel had converged to
+    pub fn render_cancellable(&self, cancel: &CancelToken) -> RenderOutput {
+        let mut state = self.start(0);
+        let mut complete = true;
+        while complete && self.passes_left(&state) > 0 {
+            complete = self.render_pass(&mut state, cancel, &mut |_, _| {});
+        }
+        RenderOutput {image: state.film.resolve(), sample_counts: state.sample_counts, complete}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use snmath::Vector3;
+
+    use snrt::CameraSettings;
+    use snrt::Sphere;
+    use snrt::material::Lambertian;
+    use snrt::material::Dielectric;
+    use snrt::material::Material;
+    use snrt::scene::RenderSettings;
+    use snmath::Ray;
+    use snrt::AABox;
+    use snrt::adaptive::AdaptiveSettings;
+    use snrt::environment::GradientSky;
+    use snrt::light::PointLight;
+    use snrt::material::Invisible;
+    use snrt::medium::HomogeneousMedium;
+    use snrt::medium::Volume;
+    use snrt::sampler::SamplerKind;
+    use snrt::texture::gray;
+    use snrt::world::World;
+    use super::CancelToken;
+    use super::Renderer;
+    use super::Scattering;
+    use super::color;
+    use super::sample_lights;
+    use super::transmittance;
+
+    fn world() -> World {
+        let mut world = World::new();
+        world.add(Box::new(Sphere {pos: Vector3 {x:0.0, y:-100.0, z:0.0}, radius: 100.0, material: Box::new(Lambertian {albedo: gray(0.5)})}));
+        world.add(Box::new(Sphere {pos: Vector3 {x:0.0, y:1.0, z:0.0}, radius: 1.0, material: Box::new(Dielectric {ior: 1.5})}));
+        world
+    }
+
+    #[test]
+    fn thread_count_does_not_change_the_image() {
+        let world = world();
+        let camera = CameraSettings {look_from: Vector3 {x:0.0, y:1.5, z:5.0}, look_at: Vector3 {x:0.0, y:1.0, z:0.0}, aperture: 0.0, ..CameraSettings::default()};
+        let settings = RenderSettings {width: 24, height: 16, samples: 8, tile_size: 4, ..RenderSettings::default()};
+
+        let mut images = Vec::new();
+        for &threads in &[1, 3] {
+            let mut renderer = Renderer::new(&world, &camera, settings, 42).unwrap();
+            renderer.set_threads(threads);
+            images.push(renderer.render().pixels);
+        }
+        assert!(images[0].iter().any(|p| *p != Vector3::default()));
+        assert!(images[0] == images[1]);
+    }
+
+    #[test]
+    fn adaptive_sampling_moves_samples_from_the_sky_to_the_noisy_pixels() {
+        let mut world = world();
+        world.environment = Box::new(GradientSky {horizon: Vector3 {x:0.5, y:0.5, z:0.5}, zenith: Vector3 {x:0.5, y:0.5, z:0.5}});
+        // the ground fills the bottom half of the image and the even sky the top
+        let camera = CameraSettings {look_from: Vector3 {x:0.0, y:1.0, z:5.0}, look_at: Vector3 {x:0.0, y:1.0, z:-5.0}, aperture: 0.0, ..CameraSettings::default()};
+        let counts = |max_samples| {
+            let adaptive = AdaptiveSettings {min_samples: 4, max_samples, threshold: 0.01};
+            let settings = RenderSettings {width: 16, height: 8, samples: 16, pass_samples: 4, adaptive: Some(adaptive), ..RenderSettings::default()};
+            Renderer::new(&world, &camera, settings, 5).unwrap().render_cancellable(&CancelToken::new()).sample_counts
+        };
+        let average = |counts: &[u32]| counts.iter().sum::<u32>() as f32 / counts.len() as f32;
+
+        // the noisy pixels run into their limit before the budget runs out
+        let capped = counts(None);
+        assert!(capped[..16].iter().all(|&c| c == 4), "{:?}", &capped[..16]);
+        assert!(capped.contains(&64));
+        assert!(capped.iter().all(|&c| c <= 64));
+        assert!(average(&capped) < 16.0);
+
+        // or share out what is left of it
+        let spent = counts(Some(4096));
+        assert!(spent.iter().any(|&c| c > 64));
+        let average = average(&spent);
+        assert!((16.0..=16.2).contains(&average), "{}", average);
+    }
+
+    #[test]
+    fn only_a_cancelled_render_is_incomplete() {
+        let world = world();
+        let camera = CameraSettings::default();
+        let settings = RenderSettings {width: 8, height: 8, samples: 4, tile_size: 4, ..RenderSettings::default()};
+        let renderer = Renderer::new(&world, &camera, settings, 1).unwrap();
+
+        let output = renderer.render_cancellable(&CancelToken::new());
+        assert!(output.complete);
+        assert!(output.sample_counts.iter().all(|&c| c == 4));
+        let output = renderer.render_cancellable(&CancelToken::with_budget(Duration::from_secs(3600)));
+        assert!(output.complete);
+
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let output = renderer.render_cancellable(&cancel);
+        assert!(!output.complete);
+        assert!(output.sample_counts.iter().all(|&c| c == 0));
+
+        // a pass stopped part way leaves the state where a later one picks it up
+        let mut state = renderer.start(0);
+        assert!(!renderer.render_pass(&mut state, &cancel, &mut |_, _| {}));
+        assert_eq!(state.samples_done, 0);
+        assert!(renderer.render_pass(&mut state, &CancelToken::new(), &mut |_, _| {}));
+        assert_eq!(state.samples_done, 4);
+    }
+
+    // a floor under a point light, in the dark, with maybe a ball in between
+    fn lit_floor(floor: Box<dyn Material + Sync + Send>, blocker: Option<Box<dyn Material + Sync + Send>>) -> World {
+        let black = Vector3::default();
+        let mut world = World::new();
+        world.environment = Box::new(GradientSky {horizon: black, zenith: black});
+        world.add(Box::new(Sphere {pos: Vector3 {x:0.0, y:-100.0, z:0.0}, radius: 100.0, material: floor}));
+        if let Some(material) = blocker {
+            world.add(Box::new(Sphere {pos: Vector3 {x:1.5, y:2.0, z:0.0}, radius: 0.5, material}));
+        }
+        world.add_light(Box::new(PointLight {pos: Vector3 {x:3.0, y:4.0, z:0.0}, intensity: Vector3 {x:10.0, y:10.0, z:10.0}}));
+        world
+    }
+
+    // next event estimation at the floor straight under (0, 1, 0)
+    fn direct_light(world: &World) -> Vector3 {
+        let mut sampler = SamplerKind::Independent.create(3, 1);
+        sampler.start_sample(0, 0, 0);
+        let r = Ray {origin: Vector3 {x:0.0, y:1.0, z:0.0}, direction: Vector3 {x:0.0, y:-1.0, z:0.0}};
+        let (hit, _) = world.trace(r, 0.001, 1000.0).unwrap();
+        let mut rays = 0;
+        sample_lights(world, hit.pos, &Scattering::Surface(&r, &hit), None, sampler.as_mut(), &mut rays)
+    }
+
+    #[test]
+    fn delta_lights_are_added_in_full_unless_something_is_in_the_way() {
+        let diffuse = || Box::new(Lambertian {albedo: gray(0.5)});
+        // the bsdf could have sampled the same direction, but a point light has no pdf to weight
+        // against so it's albedo/pi * cosine * intensity/distance² with nothing taken off
+        let expected = 0.5 / ::std::f32::consts::PI * 0.8 * 10.0 / 25.0;
+        let lit = direct_light(&lit_floor(diffuse(), None));
+        assert!((lit.x - expected).abs() < 1e-5, "{} vs {}", lit.x, expected);
+
+        assert_eq!(direct_light(&lit_floor(diffuse(), Some(diffuse()))), Vector3::default());
+        // a glass ball lets nothing through to a shadow ray either
+        assert_eq!(direct_light(&lit_floor(diffuse(), Some(Box::new(Dielectric {ior: 1.5})))), Vector3::default());
+    }
+
+    #[test]
+    fn specular_surfaces_get_nothing_from_delta_lights() {
+        assert_eq!(direct_light(&lit_floor(Box::new(Dielectric {ior: 1.5}), None)), Vector3::default());
+    }
+
+    // a box of absorbing medium with nothing on its surface, in front of a white environment
+    fn absorbing_box() -> World {
+        let mut world = World::new();
+        world.environment = Box::new(GradientSky {horizon: Vector3 {x:1.0, y:1.0, z:1.0}, zenith: Vector3 {x:1.0, y:1.0, z:1.0}});
+        let boundary = AABox {pos: Vector3::default(), dims: Vector3 {x:0.5, y:0.5, z:0.5}, material: Box::new(Invisible)};
+        let medium = HomogeneousMedium {sigma_a: Vector3 {x:0.1, y:0.1, z:0.1}, sigma_s: Vector3::default(), g: 0.0};
+        world.add(Box::new(Volume {boundary: Box::new(boundary), medium}));
+        world
+    }
+
+    #[test]
+    fn light_through_a_medium_filled_box_is_absorbed_on_the_way() {
+        let world = absorbing_box();
+        let expected = (-0.1f32).exp();
+        let mut rays = 0;
+
+        let tr = transmittance(&world, Vector3 {x:0.0, y:0.0, z:5.0}, Vector3 {x:0.0, y:0.0, z:-1.0}, 10.0, None, &mut rays);
+        assert!((tr.x - expected).abs() < 1e-4, "{}", tr.x);
+        // and from inside, only half of it
+        let inside = HomogeneousMedium {sigma_a: Vector3 {x:0.1, y:0.1, z:0.1}, sigma_s: Vector3::default(), g: 0.0};
+        let tr = transmittance(&world, Vector3::default(), Vector3 {x:1.0, y:0.0, z:0.0}, 10.0, Some(&inside), &mut rays);
+        assert!((tr.x - (-0.05f32).exp()).abs() < 1e-4, "{}", tr.x);
+
+        let mut sampler = SamplerKind::Independent.create(11, 1);
+        let count = 20000;
+        let mut total = 0.0;
+        for i in 0..count {
+            sampler.start_sample(0, 0, i);
+            let r = Ray {origin: Vector3 {x:0.1, y:-0.2, z:5.0}, direction: Vector3 {x:0.0, y:0.0, z:-1.0}};
+            total += color(r, &world, None, 8, 0.0, sampler.as_mut(), &mut rays).x;
+        }
+        let mean = total / count as f32;
+        assert!((mean - expected).abs() < 0.01, "{} vs {}", mean, expected);
+    }
+}
@@ -0,0 +1,560 @@
+use std::sync::OnceLock;
+
+extern crate rand;
+use rand::Rng;
+
+use snmath::SampleRng;
+use snmath::mix_seed;
+use snmath::seeded_rng;
+
+// hands out the random numbers for one camera sample a dimension at a time, so sequences
+// with good distributions in each dimension can replace plain uniform random numbers
+pub trait Sampler {
+    // called before each camera sample, this restarts the dimensions
+    fn start_sample(&mut self, x: u32, y: u32, index: u32);
+    fn next_1d(&mut self) -> f32;
+    fn next_2d(&mut self) -> (f32, f32);
+}
+
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum SamplerKind {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+    BlueNoise,
+}
+
+pub const SAMPLER_NAMES: &str = "independent, stratified, halton, sobol or blue-noise";
+
+impl SamplerKind {
+    pub fn from_name(name: &str) -> Option<SamplerKind> {
+        match name {
+            "independent" | "random" => Some(SamplerKind::Independent),
+            "stratified" | "jittered" => Some(SamplerKind::Stratified),
+            "halton" => Some(SamplerKind::Halton),
+            "sobol" => Some(SamplerKind::Sobol),
+            "blue-noise" | "bluenoise" => Some(SamplerKind::BlueNoise),
+            _ => None,
+        }
+    }
+
+    // samples_per_pixel lets the stratified sampler size its strata
+    pub fn create(&self, seed: u64, samples_per_pixel: u32) -> Box<dyn Sampler> {
+        match *self {
+            SamplerKind::Independent => Box::new(IndependentSampler {seed, rng: seeded_rng(seed)}),
+            SamplerKind::Stratified => Box::new(StratifiedSampler {seed, samples: samples_per_pixel.max(1), pixel_seed: seed, index: 0, dim: 0}),
+            SamplerKind::Halton => Box::new(HaltonSampler {seed, primes: first_primes(HALTON_DIMENSIONS), pixel_seed: seed, index: 0, dim: 0}),
+            SamplerKind::Sobol => Box::new(SobolSampler {seed, scramble_seed: seed, index: 0, dim: 0}),
+            SamplerKind::BlueNoise => Box::new(BlueNoiseSampler {
+                sequence: SobolSampler {seed, scramble_seed: seed, index: 0, dim: 0},
+                mask: blue_noise_mask(),
+                seed,
+                x: 0,
+                y: 0,
+                dim: 0,
+            }),
+        }
+    }
+}
+
+fn pixel_seed(seed: u64, x: u32, y: u32) -> u64 {
+    mix_seed(seed, (u64::from(y) << 32) | u64::from(x))
+}
+
+// top 24 bits of a hash as a float in [0, 1)
+fn hash_to_unit(h: u64) -> f32 {
+    (h >> 40) as f32 / (1u32 << 24) as f32
+}
+
+fn bits_to_unit(bits: u32) -> f32 {
+    (bits >> 8) as f32 / (1u32 << 24) as f32
+}
+
+// cranley-patterson rotation, shifts a point by an offset and wraps it back into [0, 1)
+fn rotate(v: f32, offset: f32) -> f32 {
+    let r = v + offset;
+    let r = if r >= 1.0 { r - 1.0 } else { r };
+    r.min(1.0 - f32::EPSILON)
+}
+
+
+pub struct IndependentSampler {
+    seed: u64,
+    rng: SampleRng,
+}
+
+impl Sampler for IndependentSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.rng = seeded_rng(mix_seed(pixel_seed(self.seed, x, y), u64::from(index)));
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        self.rng.gen_range::<f32>(0.0, 1.0)
+    }
+
+    fn next_2d(&mut self) -> (f32, f32) {
+        (self.rng.gen_range::<f32>(0.0, 1.0), self.rng.gen_range::<f32>(0.0, 1.0))
+    }
+}
+
+
+// kensler's hashed permutation of [0, len), a different shuffle for every p
+fn permute(i: u32, len: u32, p: u32) -> u32 {
+    let mut w = len - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    let mut i = i;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < len {
+            break;
+        }
+    }
+    (i.wrapping_add(p)) % len
+}
+
+// one jittered stratum per sample in every dimension, strata are shuffled per dimension so
+// the dimensions don't correlate with each other
+pub struct StratifiedSampler {
+    seed: u64,
+    samples: u32,
+    pixel_seed: u64,
+    index: u32,
+    dim: u64,
+}
+
+impl StratifiedSampler {
+    fn next_seed(&mut self) -> u64 {
+        self.dim += 1;
+        mix_seed(self.pixel_seed, self.dim)
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.pixel_seed = pixel_seed(self.seed, x, y);
+        self.index = index;
+        self.dim = 0;
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let dim_seed = self.next_seed();
+        let stratum = permute(self.index % self.samples, self.samples, dim_seed as u32);
+        let jitter = hash_to_unit(mix_seed(dim_seed, u64::from(self.index)));
+        (stratum as f32 + jitter) / self.samples as f32
+    }
+
+    fn next_2d(&mut self) -> (f32, f32) {
+        let dim_seed = self.next_seed();
+        let k = (self.samples as f32).sqrt().ceil() as u32;
+        let cell = permute(self.index % (k * k), k * k, dim_seed as u32);
+        let jitter_x = hash_to_unit(mix_seed(dim_seed, u64::from(self.index)));
+        let jitter_y = hash_to_unit(mix_seed(dim_seed ^ 1, u64::from(self.index)));
+        (((cell % k) as f32 + jitter_x) / k as f32, ((cell / k) as f32 + jitter_y) / k as f32)
+    }
+}
+
+
+// dimensions past this fall back to hashed random numbers
+const HALTON_DIMENSIONS: usize = 256;
+
+fn first_primes(count: usize) -> Vec<u32> {
+    let mut primes: Vec<u32> = Vec::with_capacity(count);
+    let mut n = 2;
+    while primes.len() < count {
+        if primes.iter().take_while(|&&p| p * p <= n).all(|&p| n % p != 0) {
+            primes.push(n);
+        }
+        n += 1;
+    }
+    primes
+}
+
+// the radical inverse with every digit put through its own permutation, without that the
+// dimensions with large neighbouring primes line up on each other. the leading zeros are
+// permuted too, down to where the digits are past float precision
+fn scrambled_radical_inverse(base: u32, index: u32, seed: u64) -> f32 {
+    let inv_base = 1.0 / f64::from(base);
+    let mut inv = inv_base;
+    let mut result = 0.0;
+    let mut i = index;
+    let mut digit = 0;
+    while inv > 1e-8 {
+        let p = mix_seed(seed, digit) as u32;
+        result += f64::from(permute(i % base, base, p)) * inv;
+        i /= base;
+        inv *= inv_base;
+        digit += 1;
+    }
+    (result as f32).min(1.0 - f32::EPSILON)
+}
+
+// the halton sequence with a prime base per dimension, digit scrambled and randomly rotated
+// per pixel
+pub struct HaltonSampler {
+    seed: u64,
+    primes: Vec<u32>,
+    pixel_seed: u64,
+    index: u32,
+    dim: usize,
+}
+
+impl Sampler for HaltonSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.pixel_seed = pixel_seed(self.seed, x, y);
+        self.index = index;
+        self.dim = 0;
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let dim = self.dim;
+        self.dim += 1;
+        let dim_seed = mix_seed(self.pixel_seed, dim as u64);
+        match self.primes.get(dim) {
+            Some(&base) => rotate(scrambled_radical_inverse(base, self.index, dim_seed ^ 1), hash_to_unit(dim_seed)),
+            None => hash_to_unit(mix_seed(dim_seed, u64::from(self.index))),
+        }
+    }
+
+    fn next_2d(&mut self) -> (f32, f32) {
+        (self.next_1d(), self.next_1d())
+    }
+}
+
+
+// the first two sobol dimensions, as fixed point fractions
+fn sobol_bits(index: u32, dim: u32) -> u32 {
+    if dim == 0 {
+        return index.reverse_bits();
+    }
+    let mut result = 0;
+    let mut v = 1u32 << 31;
+    let mut i = index;
+    while i != 0 {
+        if i & 1 != 0 {
+            result ^= v;
+        }
+        i >>= 1;
+        v ^= v >> 1;
+    }
+    result
+}
+
+fn laine_karras_permutation(x: u32, seed: u32) -> u32 {
+    let mut x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+// owen scrambling by hashing, burley's "practical hash-based owen scrambling"
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+// a 2d owen scrambled sobol sequence, padded to any number of dimensions by shuffling and
+// scrambling it differently for every pair
+pub struct SobolSampler {
+    seed: u64,
+    scramble_seed: u64,
+    index: u32,
+    dim: u64,
+}
+
+impl SobolSampler {
+    fn sample(&mut self, dims: u32) -> (f32, f32) {
+        self.dim += 1;
+        let dim_seed = mix_seed(self.scramble_seed, self.dim);
+        let shuffled = nested_uniform_scramble(self.index, dim_seed as u32);
+        let x = nested_uniform_scramble(sobol_bits(shuffled, 0), (dim_seed >> 32) as u32);
+        let y = if dims > 1 { nested_uniform_scramble(sobol_bits(shuffled, 1), mix_seed(dim_seed, 1) as u32) } else { 0 };
+        (bits_to_unit(x), bits_to_unit(y))
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.scramble_seed = pixel_seed(self.seed, x, y);
+        self.index = index;
+        self.dim = 0;
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        self.sample(1).0
+    }
+
+    fn next_2d(&mut self) -> (f32, f32) {
+        self.sample(2)
+    }
+}
+
+
+const BLUE_NOISE_SIZE: usize = 64;
+
+// every pixel shares one scrambled sobol sequence, rotated by a blue noise mask so the error
+// left at low sample counts is spread as high frequency noise instead of clumps
+pub struct BlueNoiseSampler {
+    sequence: SobolSampler,
+    mask: &'static [f32],
+    seed: u64,
+    x: u32,
+    y: u32,
+    dim: u64,
+}
+
+impl BlueNoiseSampler {
+    // each dimension reads the mask at its own toroidal shift
+    fn offset(&mut self) -> f32 {
+        self.dim += 1;
+        let shift = mix_seed(self.seed, self.dim);
+        let mx = (self.x as usize + (shift & 0xffff) as usize) % BLUE_NOISE_SIZE;
+        let my = (self.y as usize + ((shift >> 16) & 0xffff) as usize) % BLUE_NOISE_SIZE;
+        self.mask[my * BLUE_NOISE_SIZE + mx]
+    }
+}
+
+impl Sampler for BlueNoiseSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.sequence.start_sample(0, 0, index);
+        self.x = x;
+        self.y = y;
+        self.dim = 0;
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let v = self.sequence.next_1d();
+        rotate(v, self.offset())
+    }
+
+    fn next_2d(&mut self) -> (f32, f32) {
+        let (u, v) = self.sequence.next_2d();
+        (rotate(u, self.offset()), rotate(v, self.offset()))
+    }
+}
+
+fn blue_noise_mask() -> &'static [f32] {
+    static MASK: OnceLock<Vec<f32>> = OnceLock::new();
+    MASK.get_or_init(|| void_and_cluster(BLUE_NOISE_SIZE, &mut seeded_rng(0)))
+}
+
+// ulichney's void and cluster, ranks every pixel of a tileable size x size mask
+fn void_and_cluster(size: usize, rng: &mut SampleRng) -> Vec<f32> {
+    let n = size * size;
+
+    // gaussian energy over toroidal offsets
+    let sigma = 1.5f32;
+    let mut kernel = vec![0.0f32; n];
+    for dy in 0..size {
+        for dx in 0..size {
+            let wx = dx.min(size - dx) as f32;
+            let wy = dy.min(size - dy) as f32;
+            kernel[dy * size + dx] = (-(wx * wx + wy * wy) / (2.0 * sigma * sigma)).exp();
+        }
+    }
+
+    let toggle = |energy: &mut Vec<f32>, p: usize, sign: f32| {
+        let (px, py) = (p % size, p / size);
+        for qy in 0..size {
+            let dy = (qy + size - py) % size;
+            for qx in 0..size {
+                let dx = (qx + size - px) % size;
+                energy[qy * size + qx] += sign * kernel[dy * size + dx];
+            }
+        }
+    };
+    let tightest_cluster = |energy: &[f32], pattern: &[bool]| {
+        (0..n).filter(|&i| pattern[i]).max_by(|&a, &b| energy[a].partial_cmp(&energy[b]).unwrap()).unwrap()
+    };
+    let largest_void = |energy: &[f32], pattern: &[bool]| {
+        (0..n).filter(|&i| !pattern[i]).min_by(|&a, &b| energy[a].partial_cmp(&energy[b]).unwrap()).unwrap()
+    };
+
+    // a random starting pattern with a tenth of the pixels set
+    let mut pattern = vec![false; n];
+    let mut energy = vec![0.0f32; n];
+    let ones = n / 10;
+    let mut placed = 0;
+    while placed < ones {
+        let p = rng.gen_range(0, n);
+        if !pattern[p] {
+            pattern[p] = true;
+            toggle(&mut energy, p, 1.0);
+            placed += 1;
+        }
+    }
+
+    // even it out by moving points from the tightest cluster into the largest void
+    loop {
+        let cluster = tightest_cluster(&energy, &pattern);
+        pattern[cluster] = false;
+        toggle(&mut energy, cluster, -1.0);
+        let void = largest_void(&energy, &pattern);
+        pattern[void] = true;
+        toggle(&mut energy, void, 1.0);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut rank = vec![0usize; n];
+
+    // the initial points are ranked by taking them away from the tightest clusters
+    let mut removing = pattern.clone();
+    let mut removing_energy = energy.clone();
+    for r in (0..ones).rev() {
+        let cluster = tightest_cluster(&removing_energy, &removing);
+        removing[cluster] = false;
+        toggle(&mut removing_energy, cluster, -1.0);
+        rank[cluster] = r;
+    }
+
+    // and the rest by filling the largest voids
+    for r in ones..n {
+        let void = largest_void(&energy, &pattern);
+        pattern[void] = true;
+        toggle(&mut energy, void, 1.0);
+        rank[void] = r;
+    }
+
+    rank.iter().map(|&r| (r as f32 + 0.5) / n as f32).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Sampler;
+    use super::SamplerKind;
+    use super::blue_noise_mask;
+    use super::BLUE_NOISE_SIZE;
+
+    const KINDS: [SamplerKind; 5] = [
+        SamplerKind::Independent,
+        SamplerKind::Stratified,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+        SamplerKind::BlueNoise,
+    ];
+
+    fn draw(sampler: &mut dyn Sampler, x: u32, y: u32, index: u32) -> Vec<f32> {
+        sampler.start_sample(x, y, index);
+        let mut values = Vec::new();
+        for _ in 0..10 {
+            values.push(sampler.next_1d());
+            let (u, v) = sampler.next_2d();
+            values.push(u);
+            values.push(v);
+        }
+        values
+    }
+
+    #[test]
+    fn every_sampler_stays_in_the_unit_interval_and_repeats() {
+        for kind in &KINDS {
+            let mut a = kind.create(7, 64);
+            let mut b = kind.create(7, 64);
+            for &(x, y) in &[(0, 0), (3, 9), (200, 64)] {
+                for index in 0..64 {
+                    let values = draw(&mut *a, x, y, index);
+                    assert!(values.iter().all(|v| (0.0..1.0).contains(v)), "{:?}", kind);
+                    assert_eq!(values, draw(&mut *b, x, y, index), "{:?}", kind);
+                }
+            }
+        }
+    }
+
+    // the later pairs are where unscrambled halton with neighbouring primes like 71 and 73
+    // falls apart
+    #[test]
+    fn every_sampler_is_uniform_in_every_dimension() {
+        let count = 4096;
+        for kind in &KINDS {
+            let mut sampler = kind.create(11, count);
+            let mut sums = [0.0f64; 30];
+            let mut cells = [[0u32; 16]; 10];
+            for index in 0..count {
+                let values = draw(&mut *sampler, 5, 8, index);
+                for (sum, &v) in sums.iter_mut().zip(values.iter()) {
+                    *sum += f64::from(v);
+                }
+                for (d, cell) in cells.iter_mut().enumerate() {
+                    let (u, v) = (values[d * 3 + 1], values[d * 3 + 2]);
+                    cell[(u * 4.0) as usize + 4 * (v * 4.0) as usize] += 1;
+                }
+            }
+            for (d, sum) in sums.iter().enumerate() {
+                assert!((sum / f64::from(count) - 0.5).abs() < 0.02, "{:?} dimension {} {}", kind, d, sum / f64::from(count));
+            }
+            for (d, cell) in cells.iter().enumerate() {
+                for &hits in cell.iter() {
+                    assert!((hits as f32 / count as f32 - 1.0 / 16.0).abs() < 0.015, "{:?} pair {} {:?}", kind, d, cell);
+                }
+            }
+        }
+    }
+
+    // a pixel's first n samples should land in n different strata, which is the point of
+    // everything but the independent sampler
+    fn strata_hit(kind: SamplerKind, n: u32, x: u32, y: u32) -> (Vec<Vec<u32>>, Vec<Vec<u32>>) {
+        let k = (n as f32).sqrt() as u32;
+        let mut sampler = kind.create(3, n);
+        let mut strata_1d = vec![vec![0; n as usize]; 10];
+        let mut strata_2d = vec![vec![0; n as usize]; 10];
+        for index in 0..n {
+            let values = draw(&mut *sampler, x, y, index);
+            for d in 0..10 {
+                strata_1d[d][(values[d * 3] * n as f32) as usize] += 1;
+                let (u, v) = (values[d * 3 + 1], values[d * 3 + 2]);
+                strata_2d[d][((u * k as f32) as u32 + k * (v * k as f32) as u32) as usize] += 1;
+            }
+        }
+        (strata_1d, strata_2d)
+    }
+
+    #[test]
+    fn stratified_and_sobol_samples_fill_every_stratum() {
+        // blue noise shifts every point of a pixel by the same offset, which keeps the sequence
+        // uniform but not lined up with the strata
+        for kind in &[SamplerKind::Stratified, SamplerKind::Sobol] {
+            for &(x, y) in &[(0, 0), (17, 4)] {
+                let (strata_1d, strata_2d) = strata_hit(*kind, 16, x, y);
+                for strata in strata_1d.iter().chain(strata_2d.iter()) {
+                    assert!(strata.iter().all(|&hits| hits == 1), "{:?} {:?}", kind, strata);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn blue_noise_mask_ranks_every_pixel_once() {
+        let mut mask = blue_noise_mask().to_vec();
+        let n = BLUE_NOISE_SIZE * BLUE_NOISE_SIZE;
+        assert_eq!(mask.len(), n);
+        mask.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        for (r, &v) in mask.iter().enumerate() {
+            assert_eq!(v, (r as f32 + 0.5) / n as f32);
+        }
+    }
+}
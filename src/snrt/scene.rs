@@ -0,0 +1,989 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+extern crate toml;
+
+use snmath::Transform;
+use snmath::Vector3;
+use snmath::seeded_rng;
+
+use snrt::CameraSettings;
+use snrt::Sphere;
+use snrt::AABox;
+use snrt::Hitable;
+use snrt::instance::TransformedInstance;
+use snrt::material::Material;
+use snrt::material::Lambertian;
+use snrt::material::Metallic;
+use snrt::material::Dielectric;
+use snrt::material::Conductor;
+use snrt::material::RoughDielectric;
+use snrt::material::Principled;
+use snrt::material::Metal;
+use snrt::material::METAL_NAMES;
+use snrt::material::DiffuseLight;
+use snrt::material::Invisible;
+use snrt::medium::HomogeneousMedium;
+use snrt::medium::Volume;
+use snrt::light::Light;
+use snrt::light::PointLight;
+use snrt::light::SpotLight;
+use snrt::light::DirectionalLight;
+use snrt::mesh::TriangleMesh;
+use snrt::obj::ObjError;
+use snrt::obj::load_obj_with_assets;
+use snrt::texture::Texture;
+use snrt::texture::CheckerTexture;
+use snrt::texture::CheckerSpace;
+use snrt::texture::ImageTexture;
+use snrt::texture::WrapMode;
+use snrt::texture::Perlin;
+use snrt::texture::NoiseTexture;
+use snrt::texture::MarbleTexture;
+use snrt::texture::solid;
+use snrt::texture::gray;
+use snrt::environment::Environment;
+use snrt::environment::GradientSky;
+use snrt::environment::ImageEnvironment;
+use snrt::sky::PreethamSky;
+use snrt::world::World;
+use snrt::adaptive::AdaptiveSettings;
+use snrt::film::Filter;
+use snrt::film::FilterKind;
+use snrt::film::FILTER_NAMES;
+use snrt::sampler::SamplerKind;
+use snrt::sampler::SAMPLER_NAMES;
+use snrt::tiles::TileOrder;
+use snrt::tiles::TILE_ORDER_NAMES;
+use snrt::tonemap::DisplayTransform;
+use snrt::tonemap::ToneMapper;
+use snrt::tonemap::TONE_MAPPER_NAMES;
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(PathBuf, String),
+    Mesh(ObjError),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SceneError::Io(ref path, ref e) => write!(f, "{}: {}", path.display(), e),
+            SceneError::Parse(ref path, ref e) => write!(f, "{}: {}", path.display(), e),
+            SceneError::Invalid(ref path, ref message) => write!(f, "{}: {}", path.display(), message),
+            SceneError::Mesh(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for SceneError {}
+
+impl From<ObjError> for SceneError {
+    fn from(e: ObjError) -> SceneError {
+        SceneError::Mesh(e)
+    }
+}
+
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub struct RenderSettings {
+    pub width: u32,
+    pub height: u32,
+    pub samples: u32,
+    pub max_bounces: u32,
+    // samples is the average over the image rather than every pixel's count when this is set
+    pub adaptive: Option<AdaptiveSettings>,
+    pub sampler: SamplerKind,
+    pub tile_size: u32,
+    pub tile_order: TileOrder,
+    // samples each pixel takes per progressive pass, the image can be saved between passes
+    pub pass_samples: u32,
+    pub filter: Filter,
+    pub display: DisplayTransform,
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings {width: 256, height: 256, samples: 150, max_bounces: 50, adaptive: None, sampler: SamplerKind::Sobol, tile_size: 32, tile_order: TileOrder::Spiral, pass_samples: 16, filter: Filter::default(), display: DisplayTransform::default()}
+    }
+}
+
+impl RenderSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.width == 0 || self.height == 0 {
+            return Err("width and height must be at least 1".to_string());
+        }
+        if self.samples == 0 {
+            return Err("samples must be at least 1".to_string());
+        }
+        if self.tile_size == 0 {
+            return Err("tile size must be at least 1".to_string());
+        }
+        if self.pass_samples == 0 {
+            return Err("pass samples must be at least 1".to_string());
+        }
+        if let Some(ref adaptive) = self.adaptive {
+            adaptive.validate(self.samples)?;
+        }
+        self.filter.validate()?;
+        self.display.validate()
+    }
+
+    // the most samples any one pixel takes
+    pub fn max_pixel_samples(&self) -> u32 {
+        match self.adaptive {
+            Some(ref adaptive) => adaptive.sample_limit(self.samples),
+            None => self.samples,
+        }
+    }
+}
+
+pub struct Scene {
+    pub world: World,
+    pub camera: CameraSettings,
+    pub settings: RenderSettings,
+}
+
+// the file layout, everything is validated and turned into a World afterwards
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDesc {
+    #[serde(default)]
+    render: RenderDesc,
+    camera: CameraDesc,
+    #[serde(default)]
+    sky: Option<SkyDesc>,
+    environment: Option<EnvironmentDesc>,
+    #[serde(default)]
+    textures: HashMap<String, TextureDesc>,
+    #[serde(default)]
+    materials: HashMap<String, MaterialDesc>,
+    #[serde(default)]
+    media: HashMap<String, MediumDesc>,
+    // a medium that fills everything outside of shapes with their own
+    fog: Option<MediumDesc>,
+    #[serde(default)]
+    spheres: Vec<SphereDesc>,
+    #[serde(default)]
+    boxes: Vec<BoxDesc>,
+    #[serde(default)]
+    meshes: Vec<MeshDesc>,
+    #[serde(default)]
+    lights: Vec<LightDesc>,
+}
+
+#[derive(Deserialize,Default)]
+#[serde(deny_unknown_fields)]
+struct RenderDesc {
+    width: Option<u32>,
+    height: Option<u32>,
+    samples: Option<u32>,
+    max_bounces: Option<u32>,
+    adaptive_threshold: Option<f32>,
+    min_samples: Option<u32>,
+    max_samples: Option<u32>,
+    sampler: Option<String>,
+    tile_size: Option<u32>,
+    tile_order: Option<String>,
+    pass_samples: Option<u32>,
+    filter: Option<String>,
+    filter_radius: Option<f32>,
+    tonemap: Option<String>,
+    exposure: Option<f32>,
+    white_point: Option<f32>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    look_from: [f32; 3],
+    look_at: [f32; 3],
+    up: Option<[f32; 3]>,
+    fov: f32,
+    aperture: Option<f32>,
+    focus_distance: Option<f32>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SkyDesc {
+    horizon: [f32; 3],
+    zenith: [f32; 3],
+}
+
+// [sky] is a shorthand for a gradient environment
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum EnvironmentDesc {
+    Gradient { horizon: [f32; 3], zenith: [f32; 3] },
+    // an equirectangular .hdr, rotation in degrees about the vertical axis
+    Image { file: String, rotation: Option<f32>, intensity: Option<f32> },
+    // analytic daylight, the sun's elevation above the horizon and azimuth from +x towards +z in degrees
+    Sky {
+        sun_elevation: f32,
+        sun_azimuth: Option<f32>,
+        turbidity: Option<f32>,
+        ground_albedo: Option<[f32; 3]>,
+        intensity: Option<f32>,
+    },
+}
+
+impl EnvironmentDesc {
+    fn build(&self, base_dir: &Path) -> Result<Box<dyn Environment + Sync + Send>, String> {
+        match *self {
+            EnvironmentDesc::Gradient {horizon, zenith} => {
+                if !is_color(horizon) || !is_color(zenith) {
+                    return Err("gradient colors must be non-negative".to_string());
+                }
+                Ok(Box::new(GradientSky {horizon: vec3(horizon), zenith: vec3(zenith)}))
+            },
+            EnvironmentDesc::Image {ref file, rotation, intensity} => {
+                let intensity = intensity.unwrap_or(1.0);
+                if intensity < 0.0 || !intensity.is_finite() {
+                    return Err("intensity must not be negative".to_string());
+                }
+                if !rotation.unwrap_or(0.0).is_finite() {
+                    return Err("rotation must be finite".to_string());
+                }
+                let path = base_dir.join(file);
+                match ImageEnvironment::load(&path, rotation.unwrap_or(0.0), intensity) {
+                    Ok(env) => Ok(Box::new(env)),
+                    Err(e) => Err(format!("could not load {}: {}", path.display(), e)),
+                }
+            },
+            EnvironmentDesc::Sky {sun_elevation, sun_azimuth, turbidity, ground_albedo, intensity} => {
+                // the model isn't fitted for a sun below the horizon
+                if !(0.0..=90.0).contains(&sun_elevation) {
+                    return Err("sun_elevation must be between 0 and 90 degrees".to_string());
+                }
+                if !sun_azimuth.unwrap_or(0.0).is_finite() {
+                    return Err("sun_azimuth must be finite".to_string());
+                }
+                let turbidity = turbidity.unwrap_or(3.0);
+                if !(1.7..=10.0).contains(&turbidity) {
+                    return Err("turbidity must be between 1.7 and 10".to_string());
+                }
+                let ground_albedo = ground_albedo.unwrap_or([0.3, 0.3, 0.3]);
+                if !is_color(ground_albedo) || ground_albedo.iter().any(|c| *c > 1.0) {
+                    return Err("ground_albedo must be between 0 and 1".to_string());
+                }
+                let intensity = intensity.unwrap_or(1.0);
+                if intensity < 0.0 || !intensity.is_finite() {
+                    return Err("intensity must not be negative".to_string());
+                }
+                let sun = PreethamSky::sun_at(sun_elevation, sun_azimuth.unwrap_or(0.0));
+                Ok(Box::new(PreethamSky::new(sun, turbidity, vec3(ground_albedo), intensity)))
+            },
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum TextureDesc {
+    Checker { even: [f32; 3], odd: [f32; 3], scale: f32, space: Option<String> },
+    Image { file: String, wrap: Option<String>, srgb: Option<bool> },
+    Noise { color: Option<[f32; 3]>, scale: f32, seed: Option<u64> },
+    Marble { color: Option<[f32; 3]>, scale: f32, turbulence: Option<f32>, octaves: Option<u32>, seed: Option<u64> },
+}
+
+// material parameters are either a value or the name of a texture
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColorDesc {
+    Value([f32; 3]),
+    Texture(String),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScalarDesc {
+    Value(f32),
+    Texture(String),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum MaterialDesc {
+    Lambertian { albedo: ColorDesc },
+    Metallic { albedo: ColorDesc, roughness: ScalarDesc },
+    Dielectric { ior: f32 },
+    // a named metal, or its complex ior given as eta and k
+    Conductor { metal: Option<String>, eta: Option<[f32; 3]>, k: Option<[f32; 3]>, roughness: ScalarDesc },
+    #[serde(rename = "rough_dielectric")]
+    RoughDielectric { ior: f32, roughness: ScalarDesc },
+    // anything left out takes the default from Principled
+    Principled {
+        base_color: Option<ColorDesc>,
+        metallic: Option<ScalarDesc>,
+        roughness: Option<ScalarDesc>,
+        specular: Option<ScalarDesc>,
+        specular_tint: Option<ScalarDesc>,
+        sheen: Option<ScalarDesc>,
+        sheen_tint: Option<ScalarDesc>,
+        clearcoat: Option<ScalarDesc>,
+        clearcoat_gloss: Option<ScalarDesc>,
+        transmission: Option<ScalarDesc>,
+        anisotropy: Option<ScalarDesc>,
+    },
+    Emissive { emit: ColorDesc },
+}
+
+// coefficients per unit of distance, g is the henyey-greenstein asymmetry
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MediumDesc {
+    absorption: [f32; 3],
+    scattering: [f32; 3],
+    g: Option<f32>,
+}
+
+impl MediumDesc {
+    fn build(&self) -> Result<HomogeneousMedium, String> {
+        let medium = HomogeneousMedium {sigma_a: vec3(self.absorption), sigma_s: vec3(self.scattering), g: self.g.unwrap_or(0.0)};
+        medium.validate()?;
+        Ok(medium)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum LightDesc {
+    Point { position: [f32; 3], intensity: [f32; 3] },
+    Spot { position: [f32; 3], direction: [f32; 3], intensity: [f32; 3], inner_angle: Option<f32>, outer_angle: f32 },
+    Directional { direction: [f32; 3], radiance: [f32; 3] },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SphereDesc {
+    center: [f32; 3],
+    radius: f32,
+    // either can be left out, but not both
+    material: Option<String>,
+    medium: Option<String>,
+    transform: Option<TransformDesc>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BoxDesc {
+    center: [f32; 3],
+    half_size: [f32; 3],
+    material: Option<String>,
+    medium: Option<String>,
+    transform: Option<TransformDesc>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MeshDesc {
+    file: String,
+    material: Option<String>,
+    transform: Option<TransformDesc>,
+    // copies of the mesh that all share its triangles
+    #[serde(default)]
+    instances: Vec<TransformDesc>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScaleDesc {
+    Uniform(f32),
+    PerAxis([f32; 3]),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TransformDesc {
+    scale: Option<ScaleDesc>,
+    // degrees about x, then y, then z
+    rotate: Option<[f32; 3]>,
+    translate: Option<[f32; 3]>,
+}
+
+impl TransformDesc {
+    // scaled first, then rotated, then moved
+    fn build(&self) -> Result<Transform, String> {
+        let mut transform = Transform::default();
+        if let Some(ref scale) = self.scale {
+            let s = match *scale {
+                ScaleDesc::Uniform(s) => Vector3 {x:s, y:s, z:s},
+                ScaleDesc::PerAxis(s) => vec3(s),
+            };
+            if [s.x, s.y, s.z].iter().any(|c| *c == 0.0 || !c.is_finite()) {
+                return Err("scale must be non-zero on every axis".to_string());
+            }
+            transform = transform.then(&Transform::scale(s));
+        }
+        if self.rotate.is_some_and(|r| !is_finite(r)) || self.translate.is_some_and(|t| !is_finite(t)) {
+            return Err("rotate and translate must be finite".to_string());
+        }
+        if let Some(r) = self.rotate {
+            transform = transform
+                .then(&Transform::rotate(Vector3 {x:1.0, y:0.0, z:0.0}, r[0]))
+                .then(&Transform::rotate(Vector3 {x:0.0, y:1.0, z:0.0}, r[1]))
+                .then(&Transform::rotate(Vector3 {x:0.0, y:0.0, z:1.0}, r[2]));
+        }
+        if let Some(offset) = self.translate {
+            transform = transform.then(&Transform::translate(vec3(offset)));
+        }
+        Ok(transform)
+    }
+}
+
+fn vec3(v: [f32; 3]) -> Vector3 {
+    Vector3 {x:v[0], y:v[1], z:v[2]}
+}
+
+// positions and directions
+fn is_finite(v: [f32; 3]) -> bool {
+    vec3(v).is_finite()
+}
+
+fn is_color(v: [f32; 3]) -> bool {
+    v.iter().all(|c| c.is_finite() && *c >= 0.0)
+}
+
+impl TextureDesc {
+    fn build(&self, base_dir: &Path) -> Result<Arc<dyn Texture + Sync + Send>, String> {
+        match *self {
+            TextureDesc::Checker { even, odd, scale, ref space } => {
+                if !is_color(even) || !is_color(odd) {
+                    return Err("colors must be non-negative".to_string());
+                }
+                if scale <= 0.0 || !scale.is_finite() {
+                    return Err("scale must be positive".to_string());
+                }
+                let space = match space.as_ref().map(|s| s.as_str()) {
+                    None | Some("uv") => CheckerSpace::Uv,
+                    Some("solid") => CheckerSpace::Solid,
+                    Some(other) => return Err(format!("unknown space '{}', expected uv or solid", other)),
+                };
+                Ok(Arc::new(CheckerTexture {even: solid(vec3(even)), odd: solid(vec3(odd)), scale, space}))
+            },
+            TextureDesc::Image { ref file, ref wrap, srgb } => {
+                let wrap = match *wrap {
+                    Some(ref name) => WrapMode::from_name(name).ok_or_else(|| format!("unknown wrap mode '{}', expected repeat, clamp or mirror", name))?,
+                    None => WrapMode::Repeat,
+                };
+                let path = base_dir.join(file);
+                match ImageTexture::load(&path, wrap, srgb.unwrap_or(true)) {
+                    Ok(texture) => Ok(Arc::new(texture)),
+                    Err(e) => Err(format!("could not load {}: {}", path.display(), e)),
+                }
+            },
+            TextureDesc::Noise { color, scale, seed } => {
+                let color = color.unwrap_or([1.0, 1.0, 1.0]);
+                if !is_color(color) {
+                    return Err("color must be non-negative".to_string());
+                }
+                if scale <= 0.0 || !scale.is_finite() {
+                    return Err("scale must be positive".to_string());
+                }
+                Ok(Arc::new(NoiseTexture {noise: Perlin::new(&mut seeded_rng(seed.unwrap_or(0))), color: vec3(color), scale}))
+            },
+            TextureDesc::Marble { color, scale, turbulence, octaves, seed } => {
+                let color = color.unwrap_or([1.0, 1.0, 1.0]);
+                if !is_color(color) {
+                    return Err("color must be non-negative".to_string());
+                }
+                if scale <= 0.0 || !scale.is_finite() {
+                    return Err("scale must be positive".to_string());
+                }
+                if !turbulence.unwrap_or(0.0).is_finite() {
+                    return Err("turbulence must be finite".to_string());
+                }
+                Ok(Arc::new(MarbleTexture {
+                    noise: Perlin::new(&mut seeded_rng(seed.unwrap_or(0))),
+                    color: vec3(color),
+                    scale,
+                    turbulence: turbulence.unwrap_or(10.0),
+                    octaves: octaves.unwrap_or(7),
+                }))
+            },
+        }
+    }
+}
+
+type Textures = HashMap<String, Arc<dyn Texture + Sync + Send>>;
+
+fn lookup_texture(textures: &Textures, name: &str) -> Result<Arc<dyn Texture + Sync + Send>, String> {
+    match textures.get(name) {
+        Some(t) => Ok(t.clone()),
+        None => Err(format!("unknown texture '{}'", name)),
+    }
+}
+
+impl ColorDesc {
+    fn build(&self, what: &str, textures: &Textures) -> Result<Arc<dyn Texture + Sync + Send>, String> {
+        match *self {
+            ColorDesc::Value(c) if !is_color(c) => Err(format!("{} must be non-negative", what)),
+            ColorDesc::Value(c) => Ok(solid(vec3(c))),
+            ColorDesc::Texture(ref name) => lookup_texture(textures, name),
+        }
+    }
+}
+
+impl ScalarDesc {
+    fn build(&self, what: &str, textures: &Textures) -> Result<Arc<dyn Texture + Sync + Send>, String> {
+        match *self {
+            ScalarDesc::Value(v) if !(0.0..=1.0).contains(&v) => Err(format!("{} must be between 0 and 1", what)),
+            ScalarDesc::Value(v) => Ok(gray(v)),
+            ScalarDesc::Texture(ref name) => lookup_texture(textures, name),
+        }
+    }
+}
+
+impl MaterialDesc {
+    fn build(&self, textures: &Textures) -> Result<Box<dyn Material + Sync + Send>, String> {
+        match *self {
+            MaterialDesc::Lambertian { ref albedo } => Ok(Box::new(Lambertian {albedo: albedo.build("albedo", textures)?})),
+            MaterialDesc::Metallic { ref albedo, ref roughness } => Ok(Box::new(Metallic {
+                albedo: albedo.build("albedo", textures)?,
+                roughness: roughness.build("roughness", textures)?,
+            })),
+            MaterialDesc::Dielectric { ior } if ior <= 0.0 || !ior.is_finite() => Err("ior must be positive".to_string()),
+            MaterialDesc::Dielectric { ior } => Ok(Box::new(Dielectric {ior})),
+            MaterialDesc::Conductor { ref metal, eta, k, ref roughness } => {
+                let (eta, k) = match (metal, eta, k) {
+                    (Some(name), None, None) => match Metal::from_name(name) {
+                        Some(metal) => metal.eta_k(),
+                        None => return Err(format!("unknown metal '{}', expected {}", name, METAL_NAMES)),
+                    },
+                    (None, Some(eta), Some(k)) => {
+                        if !is_color(eta) || !is_color(k) {
+                            return Err("eta and k must be non-negative".to_string());
+                        }
+                        (vec3(eta), vec3(k))
+                    },
+                    _ => return Err("give either a metal or both eta and k".to_string()),
+                };
+                Ok(Box::new(Conductor {eta, k, roughness: roughness.build("roughness", textures)?}))
+            },
+            MaterialDesc::RoughDielectric { ior, .. } if ior <= 0.0 || !ior.is_finite() => Err("ior must be positive".to_string()),
+            MaterialDesc::RoughDielectric { ior, ref roughness } => Ok(Box::new(RoughDielectric {ior, roughness: roughness.build("roughness", textures)?})),
+            MaterialDesc::Principled { ref base_color, ref metallic, ref roughness, ref specular, ref specular_tint, ref sheen, ref sheen_tint,
+                                       ref clearcoat, ref clearcoat_gloss, ref transmission, ref anisotropy } => {
+                let mut m = Principled::default();
+                let scalars = [
+                    (&mut m.metallic, metallic, "metallic"),
+                    (&mut m.roughness, roughness, "roughness"),
+                    (&mut m.specular, specular, "specular"),
+                    (&mut m.specular_tint, specular_tint, "specular_tint"),
+                    (&mut m.sheen, sheen, "sheen"),
+                    (&mut m.sheen_tint, sheen_tint, "sheen_tint"),
+                    (&mut m.clearcoat, clearcoat, "clearcoat"),
+                    (&mut m.clearcoat_gloss, clearcoat_gloss, "clearcoat_gloss"),
+                    (&mut m.transmission, transmission, "transmission"),
+                    (&mut m.anisotropy, anisotropy, "anisotropy"),
+                ];
+                for (field, desc, what) in scalars {
+                    if let Some(desc) = desc {
+                        *field = desc.build(what, textures)?;
+                    }
+                }
+                if let Some(base_color) = base_color {
+                    m.base_color = base_color.build("base_color", textures)?;
+                }
+                Ok(Box::new(m))
+            },
+            MaterialDesc::Emissive { ref emit } => Ok(Box::new(DiffuseLight {emit: emit.build("emit", textures)?})),
+        }
+    }
+}
+
+impl LightDesc {
+    fn build(&self) -> Result<Box<dyn Light + Sync>, String> {
+        match *self {
+            LightDesc::Point { position, intensity } => {
+                if !is_color(intensity) {
+                    return Err("intensity must be non-negative".to_string());
+                }
+                if !is_finite(position) {
+                    return Err("position must be finite".to_string());
+                }
+                Ok(Box::new(PointLight {pos: vec3(position), intensity: vec3(intensity)}))
+            },
+            LightDesc::Spot { position, direction, intensity, inner_angle, outer_angle } => {
+                let inner_angle = inner_angle.unwrap_or(outer_angle);
+                if !is_color(intensity) {
+                    return Err("intensity must be non-negative".to_string());
+                }
+                if !is_finite(position) {
+                    return Err("position must be finite".to_string());
+                }
+                if !is_finite(direction) || vec3(direction).length_sq() == 0.0 {
+                    return Err("direction must be finite and not zero".to_string());
+                }
+                if outer_angle == 0.0 || !(0.0..=180.0).contains(&outer_angle) || !(0.0..=outer_angle).contains(&inner_angle) {
+                    return Err("angles must satisfy 0 <= inner_angle <= outer_angle <= 180".to_string());
+                }
+                Ok(Box::new(SpotLight {pos: vec3(position), direction: vec3(direction), intensity: vec3(intensity), inner_angle, outer_angle}))
+            },
+            LightDesc::Directional { direction, radiance } => {
+                if !is_color(radiance) {
+                    return Err("radiance must be non-negative".to_string());
+                }
+                if !is_finite(direction) || vec3(direction).length_sq() == 0.0 {
+                    return Err("direction must be finite and not zero".to_string());
+                }
+                Ok(Box::new(DirectionalLight {direction: vec3(direction), radiance: vec3(radiance)}))
+            },
+        }
+    }
+}
+
+impl Scene {
+    pub fn load(path: &Path) -> Result<Scene, SceneError> {
+        let text = fs::read_to_string(path).map_err(|e| SceneError::Io(path.to_path_buf(), e))?;
+        let desc: SceneDesc = toml::from_str(&text).map_err(|e| SceneError::Parse(path.to_path_buf(), e))?;
+        let invalid = |message: String| SceneError::Invalid(path.to_path_buf(), message);
+
+        let defaults = RenderSettings::default();
+        let settings = RenderSettings {
+            width: desc.render.width.unwrap_or(defaults.width),
+            height: desc.render.height.unwrap_or(defaults.height),
+            samples: desc.render.samples.unwrap_or(defaults.samples),
+            max_bounces: desc.render.max_bounces.unwrap_or(defaults.max_bounces),
+            adaptive: desc.render.adaptive_threshold.map(|threshold| AdaptiveSettings {
+                min_samples: desc.render.min_samples.unwrap_or(AdaptiveSettings::default().min_samples),
+                max_samples: desc.render.max_samples,
+                threshold,
+            }),
+            sampler: match desc.render.sampler {
+                Some(ref name) => SamplerKind::from_name(name)
+                    .ok_or_else(|| invalid(format!("render: unknown sampler '{}', expected {}", name, SAMPLER_NAMES)))?,
+                None => defaults.sampler,
+            },
+            tile_size: desc.render.tile_size.unwrap_or(defaults.tile_size),
+            tile_order: match desc.render.tile_order {
+                Some(ref name) => TileOrder::from_name(name)
+                    .ok_or_else(|| invalid(format!("render: unknown tile order '{}', expected {}", name, TILE_ORDER_NAMES)))?,
+                None => defaults.tile_order,
+            },
+            pass_samples: desc.render.pass_samples.unwrap_or(defaults.pass_samples),
+            filter: match desc.render.filter {
+                Some(ref name) => {
+                    let kind = FilterKind::from_name(name)
+                        .ok_or_else(|| invalid(format!("render: unknown filter '{}', expected {}", name, FILTER_NAMES)))?;
+                    Filter {kind, radius: desc.render.filter_radius.unwrap_or_else(|| kind.default_radius())}
+                },
+                None => Filter {radius: desc.render.filter_radius.unwrap_or(defaults.filter.radius), ..defaults.filter},
+            },
+            display: DisplayTransform {
+                tone_mapper: match desc.render.tonemap {
+                    Some(ref name) => ToneMapper::from_name(name)
+                        .ok_or_else(|| invalid(format!("render: unknown tonemap '{}', expected {}", name, TONE_MAPPER_NAMES)))?,
+                    None => defaults.display.tone_mapper,
+                },
+                exposure: desc.render.exposure.unwrap_or(defaults.display.exposure),
+                white_point: desc.render.white_point.unwrap_or(defaults.display.white_point),
+            },
+        };
+        settings.validate().map_err(|e| invalid(format!("render: {}", e)))?;
+
+        let cam = &desc.camera;
+        let camera = CameraSettings {
+            look_from: vec3(cam.look_from),
+            look_at: vec3(cam.look_at),
+            up: vec3(cam.up.unwrap_or([0.0, 1.0, 0.0])),
+            v_fov: cam.fov,
+            aperture: cam.aperture.unwrap_or(0.0),
+            focal_dist: cam.focus_distance,
+        };
+        camera.validate().map_err(|e| invalid(format!("camera: {}", e)))?;
+
+        // textures and mesh files are relative to the scene file
+        let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let mut textures = Textures::new();
+        for (name, tex) in &desc.textures {
+            textures.insert(name.clone(), tex.build(base_dir).map_err(|e| invalid(format!("texture '{}': {}", name, e)))?);
+        }
+
+        // every material is built once here so mistakes in unused ones are still reported
+        for (name, mat) in &desc.materials {
+            mat.build(&textures).map_err(|e| invalid(format!("material '{}': {}", name, e)))?;
+        }
+        let material = |name: &str, what: String| match desc.materials.get(name) {
+            Some(m) => m.build(&textures).map_err(|e| invalid(format!("material '{}': {}", name, e))),
+            None => Err(invalid(format!("{} uses unknown material '{}'", what, name))),
+        };
+
+        for (name, medium) in &desc.media {
+            medium.build().map_err(|e| invalid(format!("medium '{}': {}", name, e)))?;
+        }
+        let medium = |name: &str, what: &str| match desc.media.get(name) {
+            Some(m) => m.build().map_err(|e| invalid(format!("medium '{}': {}", name, e))),
+            None => Err(invalid(format!("{} uses unknown medium '{}'", what, name))),
+        };
+        // a shape with only a medium has no surface of its own, light passes its boundary untouched
+        let shape_material = |mat: &Option<String>, med: &Option<String>, what: String| -> Result<Box<dyn Material + Sync + Send>, SceneError> {
+            match (mat, med) {
+                (Some(name), _) => material(name, what),
+                (None, Some(_)) => Ok(Box::new(Invisible)),
+                (None, None) => Err(invalid(format!("{} needs a material, a medium or both", what))),
+            }
+        };
+        let fill = |shape: Box<dyn Hitable + Sync>, med: &Option<String>, what: String| -> Result<Box<dyn Hitable + Sync>, SceneError> {
+            match *med {
+                Some(ref name) => Ok(Box::new(Volume {boundary: shape, medium: medium(name, &what)?})),
+                None => Ok(shape),
+            }
+        };
+
+        let mut world = World::new();
+        if let Some(ref fog) = desc.fog {
+            world.fog = Some(fog.build().map_err(|e| invalid(format!("fog: {}", e)))?);
+        }
+        if let Some(ref sky) = desc.sky {
+            if desc.environment.is_some() {
+                return Err(invalid("give either a [sky] or an [environment], not both".to_string()));
+            }
+            if !is_color(sky.horizon) || !is_color(sky.zenith) {
+                return Err(invalid("sky colors must be non-negative".to_string()));
+            }
+            world.environment = Box::new(GradientSky {horizon: vec3(sky.horizon), zenith: vec3(sky.zenith)});
+        }
+        if let Some(ref env) = desc.environment {
+            world.environment = env.build(base_dir).map_err(|e| invalid(format!("environment: {}", e)))?;
+        }
+        for texture in desc.textures.values() {
+            if let TextureDesc::Image {ref file, ..} = *texture {
+                world.assets.push(base_dir.join(file));
+            }
+        }
+        if let Some(EnvironmentDesc::Image {ref file, ..}) = desc.environment {
+            world.assets.push(base_dir.join(file));
+        }
+
+        for (i, s) in desc.spheres.iter().enumerate() {
+            if s.radius <= 0.0 || !s.radius.is_finite() {
+                return Err(invalid(format!("sphere {} must have a positive radius", i)));
+            }
+            if !is_finite(s.center) {
+                return Err(invalid(format!("sphere {} must have a finite center", i)));
+            }
+            let mat = shape_material(&s.material, &s.medium, format!("sphere {}", i))?;
+            let sphere = Sphere {pos: vec3(s.center), radius: s.radius, material: mat};
+            let shape: Box<dyn Hitable + Sync> = match s.transform {
+                Some(ref t) => {
+                    let transform = t.build().map_err(|e| invalid(format!("sphere {}: {}", i, e)))?;
+                    Box::new(TransformedInstance::new(Arc::new(sphere), transform))
+                },
+                None => Box::new(sphere),
+            };
+            world.add(fill(shape, &s.medium, format!("sphere {}", i))?);
+        }
+
+        for (i, b) in desc.boxes.iter().enumerate() {
+            if b.half_size.iter().any(|d| *d <= 0.0 || !d.is_finite()) {
+                return Err(invalid(format!("box {} must have a positive half_size", i)));
+            }
+            if !is_finite(b.center) {
+                return Err(invalid(format!("box {} must have a finite center", i)));
+            }
+            let mat = shape_material(&b.material, &b.medium, format!("box {}", i))?;
+            let aabox = AABox {pos: vec3(b.center), dims: vec3(b.half_size), material: mat};
+            let shape: Box<dyn Hitable + Sync> = match b.transform {
+                Some(ref t) => {
+                    let transform = t.build().map_err(|e| invalid(format!("box {}: {}", i, e)))?;
+                    Box::new(TransformedInstance::new(Arc::new(aabox), transform))
+                },
+                None => Box::new(aabox),
+            };
+            world.add(fill(shape, &b.medium, format!("box {}", i))?);
+        }
+
+        for (i, m) in desc.meshes.iter().enumerate() {
+            let override_mat: Option<Arc<dyn Material + Sync + Send>> = match m.material {
+                Some(ref name) => Some(Arc::from(material(name, format!("mesh {}", i))?)),
+                None => None,
+            };
+            if m.transform.is_some() && !m.instances.is_empty() {
+                return Err(invalid(format!("mesh {} has both a transform and instances, give each instance its own transform", i)));
+            }
+            let transforms = m.transform.iter().chain(&m.instances)
+                .map(|t| t.build())
+                .collect::<Result<Vec<Transform>, String>>()
+                .map_err(|e| invalid(format!("mesh {}: {}", i, e)))?;
+
+            for mesh in load_obj_with_assets(&base_dir.join(&m.file), &mut world.assets)? {
+                let mesh = match override_mat {
+                    Some(ref mat) => TriangleMesh::new(mesh.mesh.clone(), mat.clone()),
+                    None => mesh,
+                };
+                if transforms.is_empty() {
+                    for tri in mesh.triangles() {
+                        world.add(Box::new(tri));
+                    }
+                } else {
+                    let shared: Arc<dyn Hitable + Sync + Send> = Arc::new(mesh);
+                    for transform in &transforms {
+                        world.add(Box::new(TransformedInstance::new(shared.clone(), *transform)));
+                    }
+                }
+            }
+        }
+
+        for (i, l) in desc.lights.iter().enumerate() {
+            world.add_light(l.build().map_err(|e| invalid(format!("light {}: {}", i, e)))?);
+        }
+
+        Ok(Scene {world, camera, settings})
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+    use std::process;
+
+    use snmath::Ray;
+    use snmath::Vector3;
+
+    use super::toml;
+
+    use super::Scene;
+    use super::SceneError;
+    use super::TextureDesc;
+
+    fn build(source: &str) -> Result<(), String> {
+        let desc: TextureDesc = toml::from_str(source).map_err(|e| e.to_string())?;
+        desc.build(Path::new("")).map(|_| ())
+    }
+
+    #[test]
+    fn procedural_textures_need_a_positive_finite_scale() {
+        for kind in &["noise", "marble"] {
+            assert!(build(&format!("type = \"{}\"\nscale = 4.0", kind)).is_ok());
+            for bad in &["0.0", "-2.0", "nan", "inf"] {
+                assert_eq!(build(&format!("type = \"{}\"\nscale = {}", kind, bad)), Err("scale must be positive".to_string()), "{} {}", kind, bad);
+            }
+        }
+        assert!(build("type = \"checker\"\neven = [1.0, 1.0, 1.0]\nodd = [0.0, 0.0, 0.0]\nscale = inf").is_err());
+    }
+
+    const CAMERA: &str = "[camera]\nlook_from = [0.0, 1.0, 5.0]\nlook_at = [0.0, 1.0, 0.0]\nfov = 40.0\n";
+    const GRAY: &str = "[materials.gray]\ntype = \"lambertian\"\nalbedo = [0.5, 0.5, 0.5]\n";
+
+    fn load(name: &str, source: &str) -> Result<Scene, SceneError> {
+        let path = env::temp_dir().join(format!("rustrace-scene-{}-{}.toml", process::id(), name));
+        fs::write(&path, source).unwrap();
+        let scene = Scene::load(&path);
+        let _ = fs::remove_file(&path);
+        scene
+    }
+
+    // the message without the path in front
+    fn invalid(name: &str, source: &str) -> String {
+        match load(name, source) {
+            Err(SceneError::Invalid(_, message)) => message,
+            Err(e) => panic!("{}: expected a validation error, got {}", name, e),
+            Ok(_) => panic!("{}: loaded", name),
+        }
+    }
+
+    #[test]
+    fn a_small_scene_loads() {
+        let scene = load("small", &format!("{}{}[[spheres]]\ncenter = [0.0, 1.0, 0.0]\nradius = 1.0\nmaterial = \"gray\"\n", CAMERA, GRAY)).unwrap();
+        assert_eq!(scene.camera.v_fov, 40.0);
+        assert!(scene.world.trace(Ray {origin: scene.camera.look_from, direction: Vector3 {x:0.0, y:0.0, z:-1.0}}, 0.001, 100.0).is_some());
+    }
+
+    #[test]
+    fn meshes_are_recorded_as_assets() {
+        let mesh = env::temp_dir().join(format!("rustrace-scene-{}-tri.obj", process::id()));
+        fs::write(&mesh, "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+        let scene = load("mesh", &format!("{}[[meshes]]\nfile = \"rustrace-scene-{}-tri.obj\"\n", CAMERA, process::id())).unwrap();
+        assert_eq!(scene.world.assets, vec![mesh.clone()]);
+        let _ = fs::remove_file(&mesh);
+    }
+
+    #[test]
+    fn broken_files_are_reported_as_such() {
+        match Scene::load(Path::new("/nonexistent/scene.toml")) {
+            Err(SceneError::Io(..)) => {},
+            _ => panic!("expected an io error"),
+        }
+        for (name, source) in &[
+            ("syntax", format!("{}[[spheres]\n", CAMERA)),
+            ("no-camera", GRAY.to_string()),
+            ("no-fov", "[camera]\nlook_from = [0.0, 1.0, 5.0]\nlook_at = [0.0, 1.0, 0.0]\n".to_string()),
+            ("no-radius", format!("{}{}[[spheres]]\ncenter = [0.0, 1.0, 0.0]\nmaterial = \"gray\"\n", CAMERA, GRAY)),
+            ("unknown-field", format!("{}{}[[spheres]]\ncenter = [0.0, 1.0, 0.0]\nradius = 1.0\nradious = 1.0\nmaterial = \"gray\"\n", CAMERA, GRAY)),
+            ("unknown-type", format!("{}[materials.odd]\ntype = \"velvet\"\nalbedo = [0.5, 0.5, 0.5]\n", CAMERA)),
+            ("wrong-type", format!("{}[materials.glass]\ntype = \"dielectric\"\nior = \"high\"\n", CAMERA)),
+        ] {
+            match load(name, source) {
+                Err(SceneError::Parse(..)) => {},
+                Err(e) => panic!("{}: expected a parse error, got {}", name, e),
+                Ok(_) => panic!("{}: loaded", name),
+            }
+        }
+    }
+
+    #[test]
+    fn references_must_name_something_defined() {
+        let sphere = |material: &str| format!("[[spheres]]\ncenter = [0.0, 1.0, 0.0]\nradius = 1.0\n{}\n", material);
+        assert_eq!(invalid("no-material", &format!("{}{}{}", CAMERA, GRAY, sphere("material = \"grey\""))), "sphere 0 uses unknown material 'grey'");
+        assert_eq!(invalid("no-medium", &format!("{}{}{}", CAMERA, GRAY, sphere("medium = \"smoke\""))), "sphere 0 uses unknown medium 'smoke'");
+        assert_eq!(invalid("bare", &format!("{}{}", CAMERA, sphere(""))), "sphere 0 needs a material, a medium or both");
+        assert_eq!(invalid("no-texture", &format!("{}[materials.wood]\ntype = \"lambertian\"\nalbedo = \"grain\"\n", CAMERA)), "material 'wood': unknown texture 'grain'");
+        assert_eq!(invalid("no-metal", &format!("{}[materials.m]\ntype = \"conductor\"\nmetal = \"mithril\"\nroughness = 0.2\n", CAMERA)),
+                   "material 'm': unknown metal 'mithril', expected gold, silver, copper, aluminum or iron");
+        assert!(invalid("no-sampler", &format!("[render]\nsampler = \"magic\"\n{}", CAMERA)).starts_with("render: unknown sampler 'magic'"));
+    }
+
+    #[test]
+    fn nan_and_infinity_are_not_let_through() {
+        let shapes = [
+            ("radius", "[[spheres]]\ncenter = [0.0, 1.0, 0.0]\nradius = nan\nmaterial = \"gray\"\n", "sphere 0 must have a positive radius"),
+            ("radius-inf", "[[spheres]]\ncenter = [0.0, 1.0, 0.0]\nradius = inf\nmaterial = \"gray\"\n", "sphere 0 must have a positive radius"),
+            ("center", "[[spheres]]\ncenter = [0.0, nan, 0.0]\nradius = 1.0\nmaterial = \"gray\"\n", "sphere 0 must have a finite center"),
+            ("half-size", "[[boxes]]\ncenter = [0.0, 1.0, 0.0]\nhalf_size = [1.0, nan, 1.0]\nmaterial = \"gray\"\n", "box 0 must have a positive half_size"),
+            ("box-center", "[[boxes]]\ncenter = [inf, 1.0, 0.0]\nhalf_size = [1.0, 1.0, 1.0]\nmaterial = \"gray\"\n", "box 0 must have a finite center"),
+            ("translate", "[[spheres]]\ncenter = [0.0, 1.0, 0.0]\nradius = 1.0\nmaterial = \"gray\"\ntransform = {translate = [0.0, nan, 0.0]}\n",
+             "sphere 0: rotate and translate must be finite"),
+            ("ior", "[materials.glass]\ntype = \"dielectric\"\nior = nan\n", "material 'glass': ior must be positive"),
+            ("rough-ior", "[materials.glass]\ntype = \"rough_dielectric\"\nior = inf\nroughness = 0.2\n", "material 'glass': ior must be positive"),
+            ("albedo", "[materials.bad]\ntype = \"lambertian\"\nalbedo = [0.5, nan, 0.5]\n", "material 'bad': albedo must be non-negative"),
+            ("point", "[[lights]]\ntype = \"point\"\nposition = [nan, 1.0, 0.0]\nintensity = [1.0, 1.0, 1.0]\n", "light 0: position must be finite"),
+            ("intensity", "[[lights]]\ntype = \"point\"\nposition = [0.0, 1.0, 0.0]\nintensity = [1.0, inf, 1.0]\n", "light 0: intensity must be non-negative"),
+            ("spot", "[[lights]]\ntype = \"spot\"\nposition = [0.0, 1.0, 0.0]\ndirection = [0.0, nan, 0.0]\nintensity = [1.0, 1.0, 1.0]\nouter_angle = 30.0\n",
+             "light 0: direction must be finite and not zero"),
+            ("spot-angle", "[[lights]]\ntype = \"spot\"\nposition = [0.0, 1.0, 0.0]\ndirection = [0.0, -1.0, 0.0]\nintensity = [1.0, 1.0, 1.0]\nouter_angle = nan\n",
+             "light 0: angles must satisfy 0 <= inner_angle <= outer_angle <= 180"),
+            ("inner-angle", "[[lights]]\ntype = \"spot\"\nposition = [0.0, 1.0, 0.0]\ndirection = [0.0, -1.0, 0.0]\nintensity = [1.0, 1.0, 1.0]\ninner_angle = nan\nouter_angle = 30.0\n",
+             "light 0: angles must satisfy 0 <= inner_angle <= outer_angle <= 180"),
+            ("sun", "[[lights]]\ntype = \"directional\"\ndirection = [0.0, -inf, 0.0]\nradiance = [1.0, 1.0, 1.0]\n", "light 0: direction must be finite and not zero"),
+            ("sky", "[sky]\nhorizon = [1.0, 1.0, 1.0]\nzenith = [nan, 0.5, 1.0]\n", "sky colors must be non-negative"),
+            ("azimuth", "[environment]\ntype = \"sky\"\nsun_elevation = 30.0\nsun_azimuth = nan\n", "environment: sun_azimuth must be finite"),
+            ("elevation", "[environment]\ntype = \"sky\"\nsun_elevation = nan\n", "environment: sun_elevation must be between 0 and 90 degrees"),
+        ];
+        for &(name, source, message) in &shapes {
+            assert_eq!(invalid(name, &format!("{}{}{}", CAMERA, GRAY, source)), message, "{}", name);
+        }
+
+        let cameras = [
+            ("look-from", "[camera]\nlook_from = [0.0, nan, 5.0]\nlook_at = [0.0, 1.0, 0.0]\nfov = 40.0\n", "camera: look_from, look_at and up must be finite"),
+            ("fov", "[camera]\nlook_from = [0.0, 1.0, 5.0]\nlook_at = [0.0, 1.0, 0.0]\nfov = nan\n", "camera: fov must be between 0 and 180 degrees, got NaN"),
+            ("aperture", "[camera]\nlook_from = [0.0, 1.0, 5.0]\nlook_at = [0.0, 1.0, 0.0]\nfov = 40.0\naperture = inf\n", "camera: aperture must not be negative"),
+            ("focus", "[camera]\nlook_from = [0.0, 1.0, 5.0]\nlook_at = [0.0, 1.0, 0.0]\nfov = 40.0\nfocus_distance = nan\n", "camera: focus distance must be positive"),
+        ];
+        for &(name, source, message) in &cameras {
+            assert_eq!(invalid(name, source), message, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use std::f32;
+
+use snmath::Vector3;
+
+use snrt::environment::Environment;
+
+// the sun's angular radius as seen from the earth
+const SUN_RADIUS_DEGREES: f32 = 0.2665;
+// the sun's luminance above the atmosphere, in the kcd/m^2 the sky model works in
+const SUN_LUMINANCE: f32 = 1.6e6;
+// kcd/m^2 to radiance, so a clear midday sky lights a white surface to about 1
+const LUMINANCE_SCALE: f32 = 0.014;
+
+// coefficients of the perez distribution for luminance and the x and y chromaticities
+fn perez_coefficients(t: f32) -> [[f32; 5]; 3] {
+    [
+        [0.1787*t - 1.4630, -0.3554*t + 0.4275, -0.0227*t + 5.3251, 0.1206*t - 2.5771, -0.0670*t + 0.3703],
+        [-0.0193*t - 0.2592, -0.0665*t + 0.0008, -0.0004*t + 0.2125, -0.0641*t - 0.8989, -0.0033*t + 0.0452],
+        [-0.0167*t - 0.2608, -0.0950*t + 0.0092, -0.0079*t + 0.2102, -0.0441*t - 1.6537, -0.0109*t + 0.0529],
+    ]
+}
+
+fn perez(c: &[f32; 5], cos_theta: f32, gamma: f32) -> f32 {
+    let cos_gamma = gamma.cos();
+    (1.0 + c[0]*(c[1]/cos_theta).exp()) * (1.0 + c[2]*(c[3]*gamma).exp() + c[4]*cos_gamma*cos_gamma)
+}
+
+// luminance and chromaticity straight up, theta_s is the sun's angle from the zenith
+fn zenith_values(t: f32, theta_s: f32) -> [f32; 3] {
+    let chi = (4.0/9.0 - t/120.0) * (f32::consts::PI - 2.0*theta_s);
+    let luminance = (4.0453*t - 4.9710)*chi.tan() - 0.2155*t + 2.4192;
+
+    let (t2, th, th2, th3) = (t*t, theta_s, theta_s*theta_s, theta_s*theta_s*theta_s);
+    let x = t2*(0.00166*th3 - 0.00375*th2 + 0.00209*th)
+        + t*(-0.02903*th3 + 0.06377*th2 - 0.03202*th + 0.00394)
+        + (0.11693*th3 - 0.21196*th2 + 0.06052*th + 0.25886);
+    let y = t2*(0.00275*th3 - 0.00610*th2 + 0.00317*th)
+        + t*(-0.04214*th3 + 0.08970*th2 - 0.04153*th + 0.00516)
+        + (0.15346*th3 - 0.26756*th2 + 0.06670*th + 0.26688);
+    [luminance.max(0.0), x, y]
+}
+
+// cie xyY to linear srgb
+fn xyy_to_rgb(x: f32, y: f32, luminance: f32) -> Vector3 {
+    if y <= 0.0 {
+        return Vector3::default();
+    }
+    let cx = x / y * luminance;
+    let cz = (1.0 - x - y) / y * luminance;
+    Vector3 {
+        x: 3.2406*cx - 1.5372*luminance - 0.4986*cz,
+        y: -0.9689*cx + 1.8758*luminance + 0.0415*cz,
+        z: 0.0557*cx - 0.2040*luminance + 1.0570*cz,
+    }.component_max(&Vector3::default())
+}
+
+// what is left of sunlight after rayleigh and aerosol scattering on its way down, per channel
+fn sun_transmittance(t: f32, theta_s: f32) -> Vector3 {
+    // relative optical mass, how much more air there is along the path than straight up
+    let mass = 1.0 / (theta_s.cos() + 0.15 * (93.885 - theta_s.to_degrees()).powf(-1.253));
+    let beta = 0.04608*t - 0.04586;
+    let channel = |lambda: f32| {
+        let rayleigh = (-0.008735 * lambda.powf(-4.08) * mass).exp();
+        let aerosol = (-beta * lambda.powf(-1.3) * mass).exp();
+        rayleigh * aerosol
+    };
+    // wavelengths in micrometers for red, green and blue
+    Vector3 {x:channel(0.65), y:channel(0.57), z:channel(0.475)}
+}
+
+// preetham, shirley and smits' analytic daylight. the sky dome comes from the model, the sun
+// is a disk of the right size that shadow rays can aim at, and below the horizon is a flat
+// ground lit by both
+pub struct PreethamSky {
+    sun_direction: Vector3,
+    intensity: f32,
+    coefficients: [[f32; 5]; 3],
+    zenith: [f32; 3],
+    // the perez function towards the zenith, which the zenith values are relative to
+    zenith_perez: [f32; 3],
+    sun_radiance: Vector3,
+    cos_sun_radius: f32,
+    ground: Vector3,
+}
+
+impl Default for PreethamSky {
+    fn default() -> PreethamSky {
+        PreethamSky::new(PreethamSky::sun_at(40.0, 60.0), 3.0, Vector3 {x:0.3, y:0.3, z:0.3}, 1.0)
+    }
+}
+
+impl PreethamSky {
+    // the direction towards a sun elevation degrees above the horizon, azimuth degrees around from +x towards +z
+    pub fn sun_at(elevation: f32, azimuth: f32) -> Vector3 {
+        let (el, az) = (elevation.to_radians(), azimuth.to_radians());
+        Vector3 {x:el.cos()*az.cos(), y:el.sin(), z:el.cos()*az.sin()}
+    }
+
+    // turbidity is the haziness, 2 for very clear air to 10 for haze. the sun has to be above the horizon
+    pub fn new(sun_direction: Vector3, turbidity: f32, ground_albedo: Vector3, intensity: f32) -> PreethamSky {
+        let sun_direction = sun_direction.normalize();
+        let theta_s = sun_direction.y.clamp(0.0, 1.0).acos();
+        let coefficients = perez_coefficients(turbidity);
+        let zenith = zenith_values(turbidity, theta_s);
+        let zenith_perez = [
+            perez(&coefficients[0], 1.0, theta_s),
+            perez(&coefficients[1], 1.0, theta_s),
+            perez(&coefficients[2], 1.0, theta_s),
+        ];
+        let sun_radiance = sun_transmittance(turbidity, theta_s) * (SUN_LUMINANCE * LUMINANCE_SCALE);
+
+        let mut sky = PreethamSky {
+            sun_direction,
+            intensity,
+            coefficients,
+            zenith,
+            zenith_perez,
+            sun_radiance,
+            cos_sun_radius: SUN_RADIUS_DEGREES.to_radians().cos(),
+            ground: Vector3::default(),
+        };
+
+        // the ground reflects whatever falls on it from the sky and the sun, summed over the upper hemisphere
+        let (n_theta, n_phi) = (32, 64);
+        let d_theta = f32::consts::FRAC_PI_2 / n_theta as f32;
+        let d_phi = 2.0 * f32::consts::PI / n_phi as f32;
+        let mut irradiance = sky.sun_radiance * (sky.sun_solid_angle() * sun_direction.y.max(0.0));
+        for i in 0..n_theta {
+            let theta = (i as f32 + 0.5) * d_theta;
+            for j in 0..n_phi {
+                let phi = (j as f32 + 0.5) * d_phi;
+                let dir = Vector3 {x:theta.sin()*phi.cos(), y:theta.cos(), z:theta.sin()*phi.sin()};
+                irradiance = irradiance + sky.sky_radiance(&dir) * (theta.cos() * theta.sin() * d_theta * d_phi);
+            }
+        }
+        sky.ground = ground_albedo * irradiance * (1.0 / f32::consts::PI);
+        sky
+    }
+
+    fn sun_solid_angle(&self) -> f32 {
+        2.0 * f32::consts::PI * (1.0 - self.cos_sun_radius)
+    }
+
+    // the dome without the sun disk, dir normalized and above the horizon
+    fn sky_radiance(&self, dir: &Vector3) -> Vector3 {
+        // the model blows up right at the horizon
+        let cos_theta = dir.y.max(0.01);
+        let gamma = dir.dot(&self.sun_direction).clamp(-1.0, 1.0).acos();
+        let value = |i: usize| self.zenith[i] * perez(&self.coefficients[i], cos_theta, gamma) / self.zenith_perez[i];
+        xyy_to_rgb(value(1), value(2), value(0)) * LUMINANCE_SCALE
+    }
+}
+
+impl Environment for PreethamSky {
+    fn radiance(&self, dir: &Vector3) -> Vector3 {
+        let d = dir.normalize();
+        if d.y < 0.0 {
+            return self.ground * self.intensity;
+        }
+        let mut radiance = self.sky_radiance(&d);
+        if d.dot(&self.sun_direction) >= self.cos_sun_radius {
+            radiance = radiance + self.sun_radiance;
+        }
+        radiance * self.intensity
+    }
+
+    // only the sun is sampled, the sky is smooth enough to leave to the bsdf
+    fn sample(&self, u: (f32, f32)) -> Option<(Vector3, Vector3, f32)> {
+        if self.sun_direction.y <= 0.0 {
+            return None;
+        }
+        let cos_theta = 1.0 - u.0 * (1.0 - self.cos_sun_radius);
+        let sin_theta = (1.0 - cos_theta*cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * f32::consts::PI * u.1;
+        let (t, b) = self.sun_direction.orthonormal_basis();
+        let dir = t * (sin_theta * phi.cos()) + b * (sin_theta * phi.sin()) + self.sun_direction * cos_theta;
+        Some((dir, self.radiance(&dir), 1.0 / self.sun_solid_angle()))
+    }
+
+    fn pdf(&self, dir: &Vector3) -> f32 {
+        if self.sun_direction.y > 0.0 && dir.normalize().dot(&self.sun_direction) >= self.cos_sun_radius {
+            1.0 / self.sun_solid_angle()
+        } else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32;
+
+    use snmath::Vector3;
+    use snrt::environment::Environment;
+    use snrt::sampler::SamplerKind;
+    use super::LUMINANCE_SCALE;
+    use super::PreethamSky;
+
+    fn sky() -> PreethamSky {
+        PreethamSky::new(PreethamSky::sun_at(35.0, 120.0), 3.0, Vector3 {x:0.4, y:0.3, z:0.2}, 1.0)
+    }
+
+    #[test]
+    fn sun_samples_agree_with_pdf_and_radiance() {
+        let sky = sky();
+        let mut sampler = SamplerKind::Independent.create(1, 1);
+        sampler.start_sample(0, 0, 0);
+        for _ in 0..1000 {
+            let (dir, radiance, pdf) = sky.sample(sampler.next_2d()).unwrap();
+            assert!((dir.length() - 1.0).abs() < 1e-4);
+            assert!(dir.dot(&sky.sun_direction) >= sky.cos_sun_radius - 1e-6);
+            assert_eq!(pdf, sky.pdf(&dir));
+            assert_eq!(radiance, sky.radiance(&dir));
+            assert!(radiance.x > sky.radiance(&Vector3 {x:0.0, y:1.0, z:0.0}).x * 100.0);
+        }
+    }
+
+    // uniform directions over a cap twice the sun's size
+    #[test]
+    fn sun_pdf_integrates_to_one() {
+        let sky = sky();
+        let cos_cap = (2.0 * sky.cos_sun_radius.acos()).cos();
+        let cap = 2.0 * f64::from(f32::consts::PI) * (1.0 - f64::from(cos_cap));
+        let (t, b) = sky.sun_direction.orthonormal_basis();
+        let mut sampler = SamplerKind::Independent.create(2, 1);
+        sampler.start_sample(0, 0, 0);
+        let count = 200000;
+        let mut sum = 0.0;
+        for _ in 0..count {
+            let u = sampler.next_2d();
+            let cos_theta = 1.0 - u.0 * (1.0 - cos_cap);
+            let sin_theta = (1.0 - cos_theta*cos_theta).max(0.0).sqrt();
+            let phi = 2.0 * f32::consts::PI * u.1;
+            let dir = t * (sin_theta * phi.cos()) + b * (sin_theta * phi.sin()) + sky.sun_direction * cos_theta;
+            sum += f64::from(sky.pdf(&dir));
+        }
+        let integral = sum / f64::from(count) * cap;
+        assert!((integral - 1.0).abs() < 0.02, "{}", integral);
+        assert_eq!(sky.pdf(&(sky.sun_direction * -1.0)), 0.0);
+    }
+
+    #[test]
+    fn a_sun_below_the_horizon_is_not_sampled() {
+        let sky = PreethamSky::new(PreethamSky::sun_at(-5.0, 0.0), 3.0, Vector3::default(), 1.0);
+        assert!(sky.sample((0.5, 0.5)).is_none());
+        assert_eq!(sky.pdf(&sky.sun_direction), 0.0);
+    }
+
+    #[test]
+    fn zenith_is_the_model_zenith_and_the_sky_is_blue() {
+        let sky = sky();
+        let up = Vector3 {x:0.0, y:1.0, z:0.0};
+        let zenith = sky.radiance(&up);
+        let luminance = 0.2126*zenith.x + 0.7152*zenith.y + 0.0722*zenith.z;
+        assert!((luminance - sky.zenith[0] * LUMINANCE_SCALE).abs() < 0.02 * luminance, "{} vs {}", luminance, sky.zenith[0] * LUMINANCE_SCALE);
+        assert!(zenith.z > zenith.x);
+
+        // brighter around the sun than away from it
+        let near_sun = PreethamSky::sun_at(35.0, 130.0);
+        let away = PreethamSky::sun_at(35.0, 300.0);
+        assert!(sky.radiance(&near_sun).y > sky.radiance(&away).y);
+    }
+
+    // the ground is lit by the sun and the whole dome, integrated on a much finer grid here
+    #[test]
+    fn ground_reflects_what_falls_on_it() {
+        let sky = sky();
+        let (n_theta, n_phi) = (256, 512);
+        let d_theta = f32::consts::FRAC_PI_2 / n_theta as f32;
+        let d_phi = 2.0 * f32::consts::PI / n_phi as f32;
+        let mut irradiance = sky.sun_radiance * (sky.sun_solid_angle() * sky.sun_direction.y);
+        for i in 0..n_theta {
+            let theta = (i as f32 + 0.5) * d_theta;
+            let mut row = Vector3::default();
+            for j in 0..n_phi {
+                let phi = (j as f32 + 0.5) * d_phi;
+                let dir = Vector3 {x:theta.sin()*phi.cos(), y:theta.cos(), z:theta.sin()*phi.sin()};
+                row = row + sky.sky_radiance(&dir) * (theta.cos() * theta.sin() * d_theta * d_phi);
+            }
+            irradiance = irradiance + row;
+        }
+        let expected = Vector3 {x:0.4, y:0.3, z:0.2} * irradiance * (1.0 / f32::consts::PI);
+        let ground = sky.radiance(&Vector3 {x:0.3, y:-0.8, z:0.1});
+        assert!((ground - expected).length() < 0.02 * expected.length(), "{:?} vs {:?}", ground, expected);
+    }
+}
@@ -0,0 +1,348 @@
+use std::path::Path;
+use std::sync::Arc;
+
+extern crate image;
+extern crate rand;
+use rand::Rng;
+
+use snmath::Vector3;
+
+use snrt::tonemap::srgb_decode;
+
+// a color that varies over a surface, looked up by uv or by the hit position for solid textures
+pub trait Texture {
+    fn value(&self, uv: (f32, f32), pos: Vector3) -> Vector3;
+}
+
+// scalar parameters like roughness are read from a texture as the average of its channels
+pub fn scalar(texture: &(dyn Texture + Sync + Send), uv: (f32, f32), pos: Vector3) -> f32 {
+    let v = texture.value(uv, pos);
+    (v.x + v.y + v.z) / 3.0
+}
+
+pub fn solid(color: Vector3) -> Arc<dyn Texture + Sync + Send> {
+    Arc::new(ConstantTexture {color})
+}
+
+pub fn gray(value: f32) -> Arc<dyn Texture + Sync + Send> {
+    solid(Vector3 {x:value, y:value, z:value})
+}
+
+#[derive(Clone,Copy,PartialEq,Default,Debug)]
+pub struct ConstantTexture {
+    pub color: Vector3,
+}
+
+impl Texture for ConstantTexture {
+    fn value(&self, _uv: (f32, f32), _pos: Vector3) -> Vector3 {
+        self.color
+    }
+}
+
+
+// another texture multiplied by a constant color
+pub struct ScaledTexture {
+    pub texture: Arc<dyn Texture + Sync + Send>,
+    pub scale: Vector3,
+}
+
+impl Texture for ScaledTexture {
+    fn value(&self, uv: (f32, f32), pos: Vector3) -> Vector3 {
+        self.texture.value(uv, pos) * self.scale
+    }
+}
+
+
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum CheckerSpace {
+    Uv,
+    // checks fill space so they don't stretch over the uv seams and poles
+    Solid,
+}
+
+pub struct CheckerTexture {
+    pub even: Arc<dyn Texture + Sync + Send>,
+    pub odd: Arc<dyn Texture + Sync + Send>,
+    // checks per unit of uv or of world space
+    pub scale: f32,
+    pub space: CheckerSpace,
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, uv: (f32, f32), pos: Vector3) -> Vector3 {
+        let cells = match self.space {
+            CheckerSpace::Uv => (uv.0 * self.scale).floor() as i64 + (uv.1 * self.scale).floor() as i64,
+            CheckerSpace::Solid => (pos.x * self.scale).floor() as i64 + (pos.y * self.scale).floor() as i64 + (pos.z * self.scale).floor() as i64,
+        };
+        if cells.rem_euclid(2) == 0 {
+            self.even.value(uv, pos)
+        } else {
+            self.odd.value(uv, pos)
+        }
+    }
+}
+
+
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum WrapMode {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl WrapMode {
+    pub fn from_name(name: &str) -> Option<WrapMode> {
+        match name {
+            "repeat" => Some(WrapMode::Repeat),
+            "clamp" => Some(WrapMode::Clamp),
+            "mirror" => Some(WrapMode::Mirror),
+            _ => None,
+        }
+    }
+
+    fn apply(&self, i: i64, size: u32) -> u32 {
+        let size = i64::from(size);
+        let i = match *self {
+            WrapMode::Repeat => i.rem_euclid(size),
+            WrapMode::Clamp => i.max(0).min(size - 1),
+            WrapMode::Mirror => {
+                let i = i.rem_euclid(2 * size);
+                if i < size { i } else { 2 * size - 1 - i }
+            },
+        };
+        i as u32
+    }
+}
+
+// texels are kept linear, v runs from the bottom row of the image up
+pub struct ImageTexture {
+    pub width: u32,
+    pub height: u32,
+    pub texels: Vec<Vector3>,
+    pub wrap: WrapMode,
+}
+
+impl ImageTexture {
+    // color images are stored gamma encoded, data like roughness maps is not
+    pub fn load(path: &Path, wrap: WrapMode, srgb: bool) -> image::ImageResult<ImageTexture> {
+        let img = image::open(path)?.to_rgb();
+        let decode = |c: u8| {
+            let c = f32::from(c) / 255.0;
+            if srgb { srgb_decode(c) } else { c }
+        };
+        let texels = img.pixels().map(|p| Vector3 {x:decode(p[0]), y:decode(p[1]), z:decode(p[2])}).collect();
+        Ok(ImageTexture {width: img.width(), height: img.height(), texels, wrap})
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Vector3 {
+        let x = self.wrap.apply(x, self.width);
+        let y = self.wrap.apply(y, self.height);
+        self.texels[(y * self.width + x) as usize]
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, uv: (f32, f32), _pos: Vector3) -> Vector3 {
+        if self.texels.is_empty() {
+            return Vector3::default();
+        }
+
+        // bilinear between the four texel centers around the lookup
+        let x = uv.0 * self.width as f32 - 0.5;
+        let y = (1.0 - uv.1) * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = Vector3::lerp(&self.texel(x0, y0), &self.texel(x0 + 1, y0), fx);
+        let bottom = Vector3::lerp(&self.texel(x0, y0 + 1), &self.texel(x0 + 1, y0 + 1), fx);
+        Vector3::lerp(&top, &bottom, fy)
+    }
+}
+
+
+const PERLIN_SIZE: usize = 256;
+
+// gradient noise, random unit gradients at lattice points hashed through permutation tables
+pub struct Perlin {
+    gradients: Vec<Vector3>,
+    perm: [Vec<usize>; 3],
+}
+
+impl Perlin {
+    pub fn new<R: Rng>(rng: &mut R) -> Perlin {
+        let gradients = (0..PERLIN_SIZE).map(|_| loop {
+            let v = Vector3 {x:rng.gen_range::<f32>(-1.0, 1.0), y:rng.gen_range::<f32>(-1.0, 1.0), z:rng.gen_range::<f32>(-1.0, 1.0)};
+            let len_sq = v.length_sq();
+            if len_sq > 1e-4 && len_sq <= 1.0 {
+                break v.normalize();
+            }
+        }).collect();
+
+        let mut permutation = || {
+            let mut p: Vec<usize> = (0..PERLIN_SIZE).collect();
+            rng.shuffle(&mut p);
+            p
+        };
+        let perm = [permutation(), permutation(), permutation()];
+
+        Perlin {gradients, perm}
+    }
+
+    // roughly in [-1, 1]
+    pub fn noise(&self, p: Vector3) -> f32 {
+        let (fx, fy, fz) = (p.x.floor(), p.y.floor(), p.z.floor());
+        let (u, v, w) = (p.x - fx, p.y - fy, p.z - fz);
+        let (i, j, k) = (fx as i64, fy as i64, fz as i64);
+        let wrap = |n: i64| n.rem_euclid(PERLIN_SIZE as i64) as usize;
+
+        // hermite smoothing hides the lattice
+        let (su, sv, sw) = (u*u*(3.0 - 2.0*u), v*v*(3.0 - 2.0*v), w*w*(3.0 - 2.0*w));
+
+        let mut sum = 0.0;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let hash = self.perm[0][wrap(i + di)] ^ self.perm[1][wrap(j + dj)] ^ self.perm[2][wrap(k + dk)];
+                    let (a, b, c) = (di as f32, dj as f32, dk as f32);
+                    let offset = Vector3 {x:u - a, y:v - b, z:w - c};
+                    let weight = (a*su + (1.0 - a)*(1.0 - su)) * (b*sv + (1.0 - b)*(1.0 - sv)) * (c*sw + (1.0 - c)*(1.0 - sw));
+                    sum += weight * self.gradients[hash].dot(&offset);
+                }
+            }
+        }
+        sum
+    }
+
+    // sum of octaves of noise at halving amplitudes
+    pub fn turbulence(&self, p: Vector3, octaves: u32) -> f32 {
+        let mut sum = 0.0;
+        let mut p = p;
+        let mut weight = 1.0;
+        for _ in 0..octaves {
+            sum += weight * self.noise(p);
+            weight *= 0.5;
+            p = p * 2.0;
+        }
+        sum.abs()
+    }
+}
+
+pub struct NoiseTexture {
+    pub noise: Perlin,
+    pub color: Vector3,
+    pub scale: f32,
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _uv: (f32, f32), pos: Vector3) -> Vector3 {
+        self.color * (0.5 * (1.0 + self.noise.noise(pos * self.scale)))
+    }
+}
+
+// veins along z, bent by turbulence
+pub struct MarbleTexture {
+    pub noise: Perlin,
+    pub color: Vector3,
+    pub scale: f32,
+    pub turbulence: f32,
+    pub octaves: u32,
+}
+
+impl Texture for MarbleTexture {
+    fn value(&self, _uv: (f32, f32), pos: Vector3) -> Vector3 {
+        let phase = self.scale * pos.z + self.turbulence * self.noise.turbulence(pos, self.octaves);
+        self.color * (0.5 * (1.0 + phase.sin()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use snmath::Vector3;
+    use snmath::seeded_rng;
+
+    use super::CheckerSpace;
+    use super::CheckerTexture;
+    use super::ImageTexture;
+    use super::Perlin;
+    use super::Texture;
+    use super::WrapMode;
+    use super::gray;
+
+    fn g(v: f32) -> Vector3 {
+        Vector3 {x:v, y:v, z:v}
+    }
+
+    fn close(a: Vector3, b: Vector3) -> bool {
+        (a - b).length() < 1e-5
+    }
+
+    #[test]
+    fn wrap_modes_fold_indices_back_into_the_image() {
+        let wrapped = |mode: WrapMode| (-5..9).map(|i| mode.apply(i, 4)).collect::<Vec<u32>>();
+        assert_eq!(wrapped(WrapMode::Repeat), vec![3, 0, 1, 2, 3, 0, 1, 2, 3, 0, 1, 2, 3, 0]);
+        assert_eq!(wrapped(WrapMode::Clamp), vec![0, 0, 0, 0, 0, 0, 1, 2, 3, 3, 3, 3, 3, 3]);
+        assert_eq!(wrapped(WrapMode::Mirror), vec![3, 3, 2, 1, 0, 0, 1, 2, 3, 3, 2, 1, 0, 0]);
+        assert_eq!(WrapMode::Mirror.apply(-1, 1), 0);
+    }
+
+    #[test]
+    fn image_lookups_blend_between_texel_centers() {
+        // two texels across and two down, the first row is the top of the image
+        let texture = |wrap| ImageTexture {width: 2, height: 2, texels: vec![g(0.0), g(1.0), g(0.5), g(0.25)], wrap};
+        let clamped = texture(WrapMode::Clamp);
+        assert!(close(clamped.value((0.25, 0.75), g(0.0)), g(0.0)));
+        assert!(close(clamped.value((0.75, 0.75), g(0.0)), g(1.0)));
+        assert!(close(clamped.value((0.25, 0.25), g(0.0)), g(0.5)));
+        assert!(close(clamped.value((0.5, 0.75), g(0.0)), g(0.5)));
+        assert!(close(clamped.value((0.25, 0.5), g(0.0)), g(0.25)));
+        assert!(close(clamped.value((0.5, 0.5), g(0.0)), g(0.4375)));
+        // past the centers the edge texels are held or wrap around
+        assert!(close(clamped.value((0.0, 0.75), g(0.0)), g(0.0)));
+        assert!(close(texture(WrapMode::Repeat).value((0.0, 0.75), g(0.0)), g(0.5)));
+        assert!(close(texture(WrapMode::Repeat).value((-0.75, 1.75), g(0.0)), g(0.0)));
+        assert!(close(texture(WrapMode::Mirror).value((1.0, 0.75), g(0.0)), g(1.0)));
+
+        let empty = ImageTexture {width: 0, height: 0, texels: Vec::new(), wrap: WrapMode::Repeat};
+        assert_eq!(empty.value((0.5, 0.5), g(0.0)), g(0.0));
+    }
+
+    #[test]
+    fn checks_alternate_across_cells_and_negative_coordinates() {
+        let checker = |space| CheckerTexture {even: gray(1.0), odd: gray(0.0), scale: 2.0, space};
+        let uv = checker(CheckerSpace::Uv);
+        assert_eq!(uv.value((0.1, 0.1), g(0.0)), g(1.0));
+        assert_eq!(uv.value((0.6, 0.1), g(0.0)), g(0.0));
+        assert_eq!(uv.value((0.6, 0.6), g(0.0)), g(1.0));
+        assert_eq!(uv.value((-0.1, 0.1), g(0.0)), g(0.0));
+
+        let solid = checker(CheckerSpace::Solid);
+        assert_eq!(solid.value((0.0, 0.0), Vector3 {x:0.1, y:0.1, z:0.1}), g(1.0));
+        assert_eq!(solid.value((0.0, 0.0), Vector3 {x:0.1, y:0.1, z:0.6}), g(0.0));
+        assert_eq!(solid.value((0.0, 0.0), Vector3 {x:-0.1, y:-0.1, z:0.1}), g(1.0));
+        assert_eq!(solid.value((0.0, 0.0), Vector3 {x:-0.1, y:-0.1, z:-0.1}), g(0.0));
+    }
+
+    #[test]
+    fn perlin_noise_stays_in_range_and_vanishes_on_the_lattice() {
+        let mut rng = seeded_rng(3);
+        let perlin = Perlin::new(&mut rng);
+        let (mut lowest, mut highest) = (0.0f32, 0.0f32);
+        for _ in 0..20_000 {
+            let p = Vector3 {x:rng.gen_range(-50.0, 50.0), y:rng.gen_range(-50.0, 50.0), z:rng.gen_range(-50.0, 50.0)};
+            let n = perlin.noise(p);
+            assert!((-1.0..=1.0).contains(&n), "{:?}: {}", p, n);
+            assert!(perlin.turbulence(p, 4) >= 0.0);
+            lowest = lowest.min(n);
+            highest = highest.max(n);
+        }
+        // it actually varies rather than being flat
+        assert!(lowest < -0.3 && highest > 0.3, "{} {}", lowest, highest);
+
+        for &p in &[g(0.0), Vector3 {x:3.0, y:-7.0, z:300.0}] {
+            assert!(perlin.noise(p).abs() < 1e-6);
+        }
+    }
+}
@@ -0,0 +1,160 @@
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum TileOrder {
+    Scanline,
+    // outwards from the middle, where the subject usually is
+    Spiral,
+    // along a hilbert curve, neighbouring tiles share more of the scene in cache
+    Hilbert,
+}
+
+pub const TILE_ORDER_NAMES: &str = "spiral, hilbert or scanline";
+
+impl TileOrder {
+    pub fn from_name(name: &str) -> Option<TileOrder> {
+        match name {
+            "scanline" => Some(TileOrder::Scanline),
+            "spiral" => Some(TileOrder::Spiral),
+            "hilbert" => Some(TileOrder::Hilbert),
+            _ => None,
+        }
+    }
+}
+
+// pixels [x0, x1) x [y0, y1), y running down from the top
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub struct Tile {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+}
+
+impl Tile {
+    pub fn pixel_count(&self) -> u64 {
+        u64::from(self.x1 - self.x0) * u64::from(self.y1 - self.y0)
+    }
+}
+
+// distance along a hilbert curve filling a side x side grid, side a power of two
+fn hilbert_index(side: u32, x: u32, y: u32) -> u64 {
+    let (mut x, mut y) = (x, y);
+    let mut d = 0u64;
+    let mut s = side / 2;
+    while s > 0 {
+        let rx = u32::from(x & s > 0);
+        let ry = u32::from(y & s > 0);
+        d += u64::from(s) * u64::from(s) * u64::from((3 * rx) ^ ry);
+        // rotate the quadrant so the curve stays continuous
+        if ry == 0 {
+            if rx == 1 {
+                x = side - 1 - x;
+                y = side - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    d
+}
+
+// grid cells in square rings walking outwards from the center one
+fn spiral_order(columns: u32, rows: u32) -> Vec<(u32, u32)> {
+    let (cx, cy) = (i64::from((columns - 1) / 2), i64::from((rows - 1) / 2));
+    let mut order = Vec::with_capacity((columns * rows) as usize);
+    let inside = |x: i64, y: i64| x >= 0 && y >= 0 && x < i64::from(columns) && y < i64::from(rows);
+
+    order.push((cx as u32, cy as u32));
+    let mut ring = 1;
+    while order.len() < (columns * rows) as usize {
+        // top edge left to right, then down the right, back along the bottom and up the left
+        let mut ring_cells = Vec::new();
+        for x in -ring..=ring {
+            ring_cells.push((x, -ring));
+        }
+        for y in -ring + 1..=ring {
+            ring_cells.push((ring, y));
+        }
+        for x in (-ring..ring).rev() {
+            ring_cells.push((x, ring));
+        }
+        for y in (-ring + 1..ring).rev() {
+            ring_cells.push((-ring, y));
+        }
+        for (dx, dy) in ring_cells {
+            if inside(cx + dx, cy + dy) {
+                order.push(((cx + dx) as u32, (cy + dy) as u32));
+            }
+        }
+        ring += 1;
+    }
+    order
+}
+
+pub fn split_into_tiles(width: u32, height: u32, size: u32, order: TileOrder) -> Vec<Tile> {
+    let columns = width.div_ceil(size);
+    let rows = height.div_ceil(size);
+    let tile = |(tx, ty): (u32, u32)| Tile {x0: tx * size, y0: ty * size, x1: ((tx + 1) * size).min(width), y1: ((ty + 1) * size).min(height)};
+
+    let mut cells: Vec<(u32, u32)> = match order {
+        TileOrder::Spiral => spiral_order(columns, rows),
+        _ => (0..rows).flat_map(|ty| (0..columns).map(move |tx| (tx, ty))).collect(),
+    };
+    if order == TileOrder::Hilbert {
+        let side = columns.max(rows).next_power_of_two();
+        cells.sort_by_key(|&(tx, ty)| hilbert_index(side, tx, ty));
+    }
+
+    cells.into_iter().map(tile).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TileOrder;
+    use super::hilbert_index;
+    use super::split_into_tiles;
+
+    #[test]
+    fn every_order_covers_each_pixel_once() {
+        for &order in &[TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert] {
+            for &(width, height, size) in &[(37, 23, 8), (5, 61, 7), (1, 1, 16), (96, 33, 32), (13, 13, 1)] {
+                let mut covered = vec![0; (width * height) as usize];
+                for tile in split_into_tiles(width, height, size, order) {
+                    assert!(tile.x0 < tile.x1 && tile.x1 <= width && tile.y0 < tile.y1 && tile.y1 <= height, "{:?}", tile);
+                    for y in tile.y0..tile.y1 {
+                        for x in tile.x0..tile.x1 {
+                            covered[(y * width + x) as usize] += 1;
+                        }
+                    }
+                }
+                assert!(covered.iter().all(|&c| c == 1), "{:?} {}x{} in {}", order, width, height, size);
+            }
+        }
+    }
+
+    #[test]
+    fn spiral_starts_in_the_middle() {
+        let tiles = split_into_tiles(50, 30, 10, TileOrder::Spiral);
+        assert_eq!((tiles[0].x0, tiles[0].y0), (20, 10));
+        // then the ring around it, starting from its top left
+        assert_eq!((tiles[1].x0, tiles[1].y0), (10, 0));
+    }
+
+    #[test]
+    fn hilbert_index_numbers_every_cell_once_along_a_connected_curve() {
+        for &side in &[1u32, 2, 4, 8, 16] {
+            let mut cells = vec![None; (side * side) as usize];
+            for y in 0..side {
+                for x in 0..side {
+                    let d = hilbert_index(side, x, y) as usize;
+                    assert!(cells[d].is_none(), "{} is used twice on a {} grid", d, side);
+                    cells[d] = Some((x, y));
+                }
+            }
+            let cells: Vec<(u32, u32)> = cells.into_iter().map(Option::unwrap).collect();
+            for pair in cells.windows(2) {
+                let ((ax, ay), (bx, by)) = (pair[0], pair[1]);
+                assert_eq!(ax.max(bx) - ax.min(bx) + ay.max(by) - ay.min(by), 1, "{:?} on a {} grid", pair, side);
+            }
+        }
+    }
+}
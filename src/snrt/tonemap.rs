@@ -0,0 +1,203 @@
+use snmath::Vector3;
+
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum ToneMapper {
+    // no compression, anything over 1 clips
+    Clamp,
+    Reinhard,
+    // reinhard that reaches white at the white point instead of at infinity
+    ExtendedReinhard,
+    Aces,
+    Hable,
+}
+
+pub const TONE_MAPPER_NAMES: &str = "clamp, reinhard, reinhard-extended, aces or hable";
+
+impl ToneMapper {
+    pub fn from_name(name: &str) -> Option<ToneMapper> {
+        match name {
+            "clamp" | "linear" => Some(ToneMapper::Clamp),
+            "reinhard" => Some(ToneMapper::Reinhard),
+            "reinhard-extended" => Some(ToneMapper::ExtendedReinhard),
+            "aces" => Some(ToneMapper::Aces),
+            "hable" | "uncharted" => Some(ToneMapper::Hable),
+            _ => None,
+        }
+    }
+}
+
+fn luminance(c: Vector3) -> f32 {
+    0.2126*c.x + 0.7152*c.y + 0.0722*c.z
+}
+
+// narkowicz's fit of the aces reference rendering transform
+fn aces(x: f32) -> f32 {
+    (x*(2.51*x + 0.03)) / (x*(2.43*x + 0.59) + 0.14)
+}
+
+// john hable's filmic curve from uncharted 2
+fn hable(x: f32) -> f32 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    (x*(a*x + c*b) + d*e) / (x*(a*x + b) + d*f) - e/f
+}
+
+// piecewise srgb transfer function rather than a plain 2.2 gamma
+pub fn srgb_encode(c: f32) -> f32 {
+    if c <= 0.003_130_8 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1.0/2.4) - 0.055
+    }
+}
+
+// the inverse, for color textures stored srgb encoded
+pub fn srgb_decode(c: f32) -> f32 {
+    if c <= 0.040_45 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// how linear radiance is turned into 8 bit display values
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub struct DisplayTransform {
+    pub tone_mapper: ToneMapper,
+    // in stops, each one doubles the brightness
+    pub exposure: f32,
+    // the luminance that maps to white for extended reinhard
+    pub white_point: f32,
+}
+
+impl Default for DisplayTransform {
+    fn default() -> DisplayTransform {
+        DisplayTransform {tone_mapper: ToneMapper::Clamp, exposure: 0.0, white_point: 4.0}
+    }
+}
+
+impl DisplayTransform {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.exposure.is_finite() {
+            return Err("exposure must be a finite number".to_string());
+        }
+        if self.white_point <= 0.0 || !self.white_point.is_finite() {
+            return Err("white point must be positive".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn tone_map(&self, c: Vector3) -> Vector3 {
+        let c = c * 2f32.powf(self.exposure);
+        match self.tone_mapper {
+            ToneMapper::Clamp => c,
+            // the reinhards work on luminance so bright colors keep their hue
+            ToneMapper::Reinhard => {
+                let l = luminance(c);
+                if l <= 0.0 { c } else { c * (1.0 / (1.0 + l)) }
+            },
+            ToneMapper::ExtendedReinhard => {
+                let l = luminance(c);
+                let w_sq = self.white_point * self.white_point;
+                if l <= 0.0 { c } else { c * ((1.0 + l / w_sq) / (1.0 + l)) }
+            },
+            ToneMapper::Aces => Vector3 {x:aces(c.x), y:aces(c.y), z:aces(c.z)},
+            ToneMapper::Hable => {
+                // the curve expects a brighter exposure and is normalised to its white point
+                let white_scale = 1.0 / hable(11.2);
+                Vector3 {x:hable(2.0*c.x)*white_scale, y:hable(2.0*c.y)*white_scale, z:hable(2.0*c.z)*white_scale}
+            },
+        }
+    }
+
+    pub fn encode(&self, c: Vector3) -> [u8; 3] {
+        let c = self.tone_map(c);
+        let encode = |v: f32| {
+            // nan from a broken sample shows up black rather than poisoning the cast
+            let v = if v.is_nan() { 0.0 } else { v.clamp(0.0, 1.0) };
+            (srgb_encode(v) * 255.0 + 0.5) as u8
+        };
+        [encode(c.x), encode(c.y), encode(c.z)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use snmath::Vector3;
+
+    use super::DisplayTransform;
+    use super::ToneMapper;
+    use super::srgb_decode;
+    use super::srgb_encode;
+
+    const MAPPERS: [ToneMapper; 5] = [ToneMapper::Clamp, ToneMapper::Reinhard, ToneMapper::ExtendedReinhard, ToneMapper::Aces, ToneMapper::Hable];
+
+    fn gray(v: f32) -> Vector3 {
+        Vector3 {x:v, y:v, z:v}
+    }
+
+    fn display(tone_mapper: ToneMapper) -> DisplayTransform {
+        DisplayTransform {tone_mapper, ..DisplayTransform::default()}
+    }
+
+    #[test]
+    fn black_stays_black() {
+        for &mapper in &MAPPERS {
+            let display = display(mapper);
+            assert!(display.tone_map(gray(0.0)).x.abs() < 1e-6, "{:?}", mapper);
+            assert_eq!(display.encode(gray(0.0)), [0, 0, 0], "{:?}", mapper);
+        }
+    }
+
+    #[test]
+    fn brighter_input_never_encodes_darker() {
+        for &mapper in &MAPPERS {
+            let display = display(mapper);
+            let mut last = 0;
+            for i in 0..400 {
+                let v = 1e-4 * 1.05f32.powi(i);
+                let [r, g, b] = display.encode(gray(v));
+                assert!(r == g && g == b);
+                assert!(r >= last, "{:?} at {}: {} after {}", mapper, v, r, last);
+                last = r;
+            }
+        }
+        assert_eq!(display(ToneMapper::Clamp).encode(gray(1.0)), [255, 255, 255]);
+        assert_eq!(display(ToneMapper::Clamp).encode(gray(50.0)), [255, 255, 255]);
+        assert_eq!(display(ToneMapper::Clamp).encode(gray(-1.0)), [0, 0, 0]);
+        assert_eq!(display(ToneMapper::Clamp).encode(Vector3 {x:f32::NAN, y:0.5, z:1.0}), [0, 188, 255]);
+    }
+
+    #[test]
+    fn exposure_scales_by_powers_of_two() {
+        let c = Vector3 {x:0.1, y:0.2, z:0.05};
+        for &ev in &[-2.0, -0.5, 0.0, 1.0, 3.0] {
+            let display = DisplayTransform {exposure: ev, ..DisplayTransform::default()};
+            let mapped = display.tone_map(c);
+            let scale = 2f32.powf(ev);
+            assert!((mapped.x - c.x * scale).abs() < 1e-6);
+            assert!((mapped.y - c.y * scale).abs() < 1e-6);
+            assert!((mapped.z - c.z * scale).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn srgb_curve_is_linear_below_the_breakpoint_and_continuous_at_it() {
+        assert_eq!(srgb_encode(0.0), 0.0);
+        assert!((srgb_encode(0.002) - 12.92 * 0.002).abs() < 1e-7);
+        let below = srgb_encode(0.003_130_8);
+        let above = srgb_encode(0.003_130_9);
+        assert!((below - 0.040_449_9).abs() < 1e-5);
+        assert!(above > below && above - below < 1e-5);
+        assert!((srgb_encode(0.5) - 0.735_357).abs() < 1e-5);
+        assert!((srgb_encode(1.0) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn srgb_decode_undoes_encode() {
+        for i in 0..=1000 {
+            let c = i as f32 / 1000.0;
+            assert!((srgb_decode(srgb_encode(c)) - c).abs() < 1e-5, "{}", c);
+        }
+        assert!((srgb_decode(0.04) - 0.04 / 12.92).abs() < 1e-7);
+    }
+}
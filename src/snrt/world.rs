@@ -1,46 +1,131 @@
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
 extern crate rand;
-use rand::{thread_rng, Rng};
+use rand::Rng;
 
 use snmath::Vector3;
 use snmath::Ray;
+use snmath::Aabb;
 
 use snrt::Hitable;
+use snrt::HitRecord;
+use snrt::bvh::Bvh;
+use snrt::environment::Environment;
+use snrt::environment::GradientSky;
+use snrt::medium::HomogeneousMedium;
+use snrt::sampler::Sampler;
+use snrt::light::Light;
 use snrt::Sphere;
 use snrt::AABox;
+use snrt::obj::ObjError;
+use snrt::obj::load_obj_with_assets;
 use snrt::material::Material;
 use snrt::material::Lambertian;
 use snrt::material::Metallic;
 use snrt::material::Dielectric;
+use snrt::texture::solid;
+use snrt::texture::gray;
 
 pub struct World {
-    pub entities: Vec<Box<Hitable + Sync>>,
+    entities: Vec<Box<dyn Hitable + Sync>>,
+    pub lights: Vec<Box<dyn Light + Sync>>,
+    // what rays that miss everything see, and a light source in its own right
+    pub environment: Box<dyn Environment + Sync + Send>,
+    // fills all the space that isn't inside some other medium
+    pub fog: Option<HomogeneousMedium>,
+    // files it was loaded from other than a scene file, a checkpoint only fits while they're unchanged
+    pub assets: Vec<PathBuf>,
+    // built the first time it's needed and thrown away by add, so it never misses an entity
+    accel: OnceLock<Accel>,
+}
+
+struct Accel {
+    bvh: Bvh,
+    // entities with an emissive material, the area lights
+    emitters: Vec<usize>,
+}
+
+impl Default for World {
+    fn default() -> World {
+        World::new()
+    }
 }
 
 impl World {
-    pub fn trace(&self, r: Ray, min_t: f32, max_t: f32) -> (f32, Option<&Box<Hitable + Sync>>) {
-        let world_iter = self.entities.iter();
-        let mut best:(f32, Option<&Box<Hitable + Sync>>) = (::std::f32::MAX, None);
-        for hitable in world_iter {
-            let res = hitable.hit_check(&r, min_t, max_t);
-            if res.is_some() {
-                let new_t = res.unwrap();
-
-                if new_t < best.0 {
-                    best.0 = new_t;
-                    best.1 = Some(hitable);
-                }
+    pub fn new() -> World {
+        World { entities: Vec::new(), lights: Vec::new(), environment: Box::new(GradientSky::default()), fog: None, assets: Vec::new(), accel: OnceLock::new() }
+    }
+
+    pub fn add(&mut self, entity: Box<dyn Hitable + Sync>) {
+        self.entities.push(entity);
+        self.accel = OnceLock::new();
+    }
+
+    pub fn add_light(&mut self, light: Box<dyn Light + Sync>) {
+        self.lights.push(light);
+    }
+
+    // builds the bvh now rather than on the first trace, which the renderer does before its threads start
+    pub fn build_bvh(&self) {
+        self.accel();
+    }
+
+    fn accel(&self) -> &Accel {
+        self.accel.get_or_init(|| {
+            let bounds: Vec<Aabb> = self.entities.iter().map(|e| e.bounding_box()).collect();
+            let emitters = (0..self.entities.len()).filter(|&i| self.entities[i].material().is_emissive() && self.entities[i].area() > 0.0).collect();
+            Accel {bvh: Bvh::build(&bounds), emitters}
+        })
+    }
+
+    // picks an area light uniformly, along with the probability of having picked it
+    pub fn pick_emitter(&self, sampler: &mut dyn Sampler) -> Option<(&(dyn Hitable + Sync), f32)> {
+        let emitters = &self.accel().emitters;
+        if emitters.is_empty() {
+            return None;
+        }
+        let idx = ((sampler.next_1d() * emitters.len() as f32) as usize).min(emitters.len() - 1);
+        Some((self.entities[emitters[idx]].as_ref(), self.emitter_pick_pdf()))
+    }
+
+    pub fn emitter_pick_pdf(&self) -> f32 {
+        1.0 / self.accel().emitters.len().max(1) as f32
+    }
+
+    pub fn trace(&self, r: Ray, min_t: f32, max_t: f32) -> Option<(HitRecord<'_>, &(dyn Hitable + Sync))> {
+        let mut best: Option<HitRecord> = None;
+        let (idx, _) = self.accel().bvh.trace(&r, min_t, max_t, |idx, closest| {
+            let hit = self.entities[idx].hit(&r, min_t, closest)?;
+            let t = hit.t;
+            best = Some(hit);
+            Some(t)
+        })?;
+
+        best.map(|hit| (hit, self.entities[idx].as_ref()))
+    }
+
+    pub fn add_obj(&mut self, path: &Path) -> Result<(), ObjError> {
+        for mesh in load_obj_with_assets(path, &mut self.assets)? {
+            for tri in mesh.triangles() {
+                self.add(Box::new(tri));
             }
         }
+        Ok(())
+    }
 
-        return best;
+    pub fn from_obj(path: &Path) -> Result<World, ObjError> {
+        let mut new_world = World::new();
+        new_world.add_obj(path)?;
+        Ok(new_world)
     }
 
-    pub fn create() -> World {
-        let mut new_world = World { entities: Vec::new() };
-        let mut world_rng = thread_rng();
+    pub fn create<R: Rng>(world_rng: &mut R) -> World {
+        let mut new_world = World::new();
 
-        new_world.entities.push(Box::new(Sphere {pos: Vector3 {x:0.0, y:-1000.0, z:0.0}, radius: 1000.0, 
-                            material:Box::new(Lambertian{albedo:Vector3{x:0.4,y:0.4,z:0.5}})}));
+        new_world.add(Box::new(Sphere {pos: Vector3 {x:0.0, y:-1000.0, z:0.0}, radius: 1000.0, 
+                            material:Box::new(Lambertian{albedo:solid(Vector3{x:0.4,y:0.4,z:0.5})})}));
 
         let ball_min = -4;
         let ball_max = 4;
@@ -52,17 +137,17 @@ impl World {
                                     y:world_rng.gen_range::<f32>(0.0,1.0) * 0.1 + 0.2,
                                     z:world_rng.gen_range::<f32>(0.0,1.0) * 0.1 + 0.2};
                 let center = Vector3 {x:a as f32 + 0.6 * world_rng.gen_range::<f32>(0.0,1.0), y:rands.z, z:b as f32 + 0.6 * world_rng.gen_range::<f32>(0.0,1.0) };
-                let mut mat : Box<Material + Sync>;
+                let mat : Box<dyn Material + Sync + Send>;
                 if mat_val < 0.8 {
-                    mat = Box::new(Lambertian{albedo:Vector3{x:world_rng.gen_range::<f32>(0.0,1.0)*world_rng.gen_range::<f32>(0.0,1.0),
+                    mat = Box::new(Lambertian{albedo:solid(Vector3{x:world_rng.gen_range::<f32>(0.0,1.0)*world_rng.gen_range::<f32>(0.0,1.0),
                                                              y:world_rng.gen_range::<f32>(0.0,1.0)*world_rng.gen_range::<f32>(0.0,1.0),
-                                                             z:world_rng.gen_range::<f32>(0.0,1.0)*world_rng.gen_range::<f32>(0.0,1.0)}});
+                                                             z:world_rng.gen_range::<f32>(0.0,1.0)*world_rng.gen_range::<f32>(0.0,1.0)})});
                 }
                 else if mat_val < 0.95 {
-                    mat = Box::new(Metallic{ albedo:Vector3{ x:0.5*(1.0+world_rng.gen_range::<f32>(0.0,1.0)),
+                    mat = Box::new(Metallic{ albedo:solid(Vector3{ x:0.5*(1.0+world_rng.gen_range::<f32>(0.0,1.0)),
                                                              y:0.5*(1.0+world_rng.gen_range::<f32>(0.0,1.0)),
-                                                             z:0.5*(1.0+world_rng.gen_range::<f32>(0.0,1.0))},
-                                                             roughness:0.5*world_rng.gen_range::<f32>(0.0,1.0)});
+                                                             z:0.5*(1.0+world_rng.gen_range::<f32>(0.0,1.0))}),
+                                                             roughness:gray(0.5*world_rng.gen_range::<f32>(0.0,1.0))});
 
                 }
                 else {
@@ -70,25 +155,64 @@ impl World {
                 }
 
                 if type_val < 0.5 {
-                    new_world.entities.push(Box::new(AABox {pos:center, dims:rands, material:mat}))
+                    new_world.add(Box::new(AABox {pos:center, dims:rands, material:mat}))
                 }
                 else {
-                    new_world.entities.push(Box::new(Sphere {pos:center, radius:rands.z, material:mat}));
+                    new_world.add(Box::new(Sphere {pos:center, radius:rands.z, material:mat}));
                 }
 
             }
         }
 
 
-        new_world.entities.push(Box::new(AABox {pos: Vector3 {x:-4.0, y:1.0, z:-1.0}, dims: Vector3 {x:1.0,y:2.0,z:1.0},
-                                    material:Box::new(Lambertian{albedo:Vector3{x:0.1,y:0.2,z:0.5}})}));
+        new_world.add(Box::new(AABox {pos: Vector3 {x:-4.0, y:1.0, z:-1.0}, dims: Vector3 {x:1.0,y:2.0,z:1.0},
+                                    material:Box::new(Lambertian{albedo:solid(Vector3{x:0.1,y:0.2,z:0.5})})}));
         //new_world.entities.push(Box::new(Sphere {pos: Vector3 {x:-4.0, y:1.0, z:-1.0}, radius: 1.0,
         //                            material:Box::new(Lambertian{albedo:Vector3{x:0.1,y:0.2,z:0.5}})}));
-        new_world.entities.push(Box::new(Sphere {pos: Vector3 {x:4.0, y:1.0, z:-1.0}, radius: 1.0,
-                                    material:Box::new(Metallic{albedo:Vector3{x:0.7,y:0.6,z:0.5},roughness:0.1})}));
-        new_world.entities.push(Box::new(Sphere {pos: Vector3 {x:0.0, y:1.0, z:-1.0}, radius: 1.0, 
+        new_world.add(Box::new(Sphere {pos: Vector3 {x:4.0, y:1.0, z:-1.0}, radius: 1.0,
+                                    material:Box::new(Metallic{albedo:solid(Vector3{x:0.7,y:0.6,z:0.5}),roughness:gray(0.1)})}));
+        new_world.add(Box::new(Sphere {pos: Vector3 {x:0.0, y:1.0, z:-1.0}, radius: 1.0, 
                                     material:Box::new(Dielectric{ior:1.5})}));
 
-        return new_world;
+        new_world
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use snmath::Vector3;
+    use snmath::Ray;
+
+    use snrt::Sphere;
+    use snrt::material::Lambertian;
+    use snrt::material::DiffuseLight;
+    use snrt::sampler::SamplerKind;
+    use snrt::texture::gray;
+    use super::World;
+
+    fn sphere_at(z: f32) -> Box<Sphere> {
+        Box::new(Sphere {pos: Vector3 {x:0.0, y:0.0, z}, radius: 0.5, material: Box::new(Lambertian {albedo: gray(0.5)})})
+    }
+
+    #[test]
+    fn sees_entities_added_after_tracing() {
+        let r = Ray {origin: Vector3::default(), direction: Vector3 {x:0.0, y:0.0, z:-1.0}};
+        let mut world = World::new();
+        assert!(world.trace(r, 0.001, 100.0).is_none());
+
+        world.add(sphere_at(-10.0));
+        assert!((world.trace(r, 0.001, 100.0).unwrap().0.t - 9.5).abs() < 1e-4);
+
+        world.add(sphere_at(-5.0));
+        assert!((world.trace(r, 0.001, 100.0).unwrap().0.t - 4.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn picks_up_emitters_added_after_sampling() {
+        let mut world = World::new();
+        let mut sampler = SamplerKind::Independent.create(1, 1);
+        assert!(world.pick_emitter(sampler.as_mut()).is_none());
+
+        world.add(Box::new(Sphere {pos: Vector3::default(), radius: 1.0, material: Box::new(DiffuseLight {emit: gray(1.0)})}));
+        assert_eq!(world.pick_emitter(sampler.as_mut()).map(|e| e.1), Some(1.0));
+    }
+}